@@ -16,6 +16,12 @@ pub enum CoconutErrorKind {
     )]
     UnequalNoOfBasesExponents { bases: usize, exponents: usize },
 
+    #[fail(display = "Verkey shares of signers {:?} are inconsistent with the others", ids)]
+    InconsistentVerkeyShares { ids: Vec<usize> },
+
+    #[fail(display = "Invalid input {:?}", msg)]
+    ValidationError { msg: String },
+
     #[fail(display = "Error from PS sig crate {:?}", msg)]
     PSError { msg: String },
 
@@ -1,8 +1,9 @@
 use secret_sharing::shamir_secret_sharing::get_shared_secret;
 use secret_sharing::pedersen_vss::PedersenVSS;
 
+use crate::secret::SecretFieldElement;
 use crate::signature::{Params, Sigkey, Verkey};
-use amcl_wrapper::field_elem::{FieldElement, FieldElementVector};
+use amcl_wrapper::field_elem::FieldElement;
 use amcl_wrapper::group_elem_g1::G1;
 use std::collections::HashMap;
 use secret_sharing::pedersen_dvss::PedersenDVSSParticipant;
@@ -24,13 +25,13 @@ fn keygen_from_shares(
     let mut signers = vec![];
     for i in 0..num_signers {
         let id = i + 1;
-        let x_i = x_shares.remove(&id).unwrap();
-        let alpha_i = &params.g_tilde * &x_i;
+        let x_i = SecretFieldElement::new(x_shares.remove(&id).unwrap());
+        let alpha_i = &params.g_tilde * x_i.expose_secret();
         let mut y_i = vec![];
         let mut beta_i = vec![];
         for j in 0..params.msg_count() {
-            y_i.push(y_shares[j].remove(&id).unwrap());
-            beta_i.push(&params.g_tilde * &y_i[j]);
+            y_i.push(SecretFieldElement::new(y_shares[j].remove(&id).unwrap()));
+            beta_i.push(&params.g_tilde * y_i[j].expose_secret());
         }
 
         signers.push(Signer {
@@ -55,17 +56,17 @@ pub fn trusted_party_SSS_keygen(
     threshold: usize,
     total: usize,
     params: &Params,
-) -> (FieldElement, FieldElementVector, Vec<Signer>) {
+) -> (SecretFieldElement, Vec<SecretFieldElement>, Vec<Signer>) {
     let (secret_x, x_shares) = get_shared_secret(threshold, total);
     let mut y = vec![];
-    let mut secret_y = FieldElementVector::with_capacity(params.msg_count());
+    let mut secret_y = Vec::with_capacity(params.msg_count());
     for _ in 0..params.msg_count() {
         let (sec_y, y_shares) = get_shared_secret(threshold, total);
-        secret_y.push(sec_y);
+        secret_y.push(SecretFieldElement::new(sec_y));
         y.push(y_shares);
     }
     (
-        secret_x,
+        SecretFieldElement::new(secret_x),
         secret_y,
         keygen_from_shares(total, x_shares, y, params),
     )
@@ -79,14 +80,14 @@ pub fn trusted_party_PVSS_keygen(
     g: &G1,
     h: &G1,
 ) -> (
-    FieldElement,       // shared secret for x
-    FieldElementVector, // shared secret for each y
+    SecretFieldElement,      // shared secret for x
+    Vec<SecretFieldElement>, // shared secret for each y
     Vec<Signer>,
-    FieldElement,       // blinding for x
+    SecretFieldElement, // blinding for x
     HashMap<usize, G1>, // commitment to coefficients for polynomial for x
     HashMap<usize, FieldElement>,
     HashMap<usize, FieldElement>,
-    FieldElementVector,      // blindings for each y
+    Vec<SecretFieldElement>, // blindings for each y
     Vec<HashMap<usize, G1>>, // commitment to coefficients for polynomial for each y
     Vec<HashMap<usize, FieldElement>>,
     Vec<HashMap<usize, FieldElement>>,
@@ -94,24 +95,24 @@ pub fn trusted_party_PVSS_keygen(
     let (secret_x, secret_x_t, comm_coeff_x, x_shares, x_t_shares) =
         PedersenVSS::deal(threshold, total, g, h);
     let mut y = vec![];
-    let mut secret_y = FieldElementVector::with_capacity(params.msg_count());
-    let mut secret_y_t = FieldElementVector::with_capacity(params.msg_count());
+    let mut secret_y = Vec::with_capacity(params.msg_count());
+    let mut secret_y_t = Vec::with_capacity(params.msg_count());
     let mut comm_coeff_y_vec = vec![];
     let mut y_t = vec![];
     for _ in 0..params.msg_count() {
         let (sec_y, sec_y_t, comm_coeff_y, y_shares, y_t_shares) =
             PedersenVSS::deal(threshold, total, g, h);
-        secret_y.push(sec_y);
-        secret_y_t.push(sec_y_t);
+        secret_y.push(SecretFieldElement::new(sec_y));
+        secret_y_t.push(SecretFieldElement::new(sec_y_t));
         comm_coeff_y_vec.push(comm_coeff_y);
         y.push(y_shares);
         y_t.push(y_t_shares);
     }
     (
-        secret_x,
+        SecretFieldElement::new(secret_x),
         secret_y,
         keygen_from_shares(total, x_shares.clone(), y.clone(), params),
-        secret_x_t,
+        SecretFieldElement::new(secret_x_t),
         comm_coeff_x,
         x_shares,
         x_t_shares,
@@ -173,10 +174,10 @@ pub(crate) fn setup_signers_for_test(
     params: &Params,
     g: &G1,
     h: &G1,
-) -> (FieldElement, FieldElementVector, Vec<Signer>) {
+) -> (SecretFieldElement, Vec<SecretFieldElement>, Vec<Signer>) {
 
     let mut secret_x = FieldElement::zero();
-    let mut secret_y = FieldElementVector::with_capacity(params.msg_count());
+    let mut secret_y = Vec::with_capacity(params.msg_count());
 
     let mut x_shares: HashMap<usize, FieldElement> = HashMap::new();
     let mut y_shares: Vec<HashMap<usize, FieldElement>> = vec![];
@@ -198,11 +199,11 @@ pub(crate) fn setup_signers_for_test(
             sec_y += &participants_y[i].secret;
         }
         y_shares.push(y);
-        secret_y.push(sec_y);
+        secret_y.push(SecretFieldElement::new(sec_y));
     }
 
     let signers = keygen_from_shares(total, x_shares, y_shares, params);
-    (secret_x, secret_y, signers)
+    (SecretFieldElement::new(secret_x), secret_y, signers)
 }
 
 #[cfg(test)]
@@ -211,8 +212,10 @@ mod tests {
     use secret_sharing::polynomial::Polynomial;
     use secret_sharing::shamir_secret_sharing::reconstruct_secret;
     use crate::VerkeyGroupVec;
+    use amcl_wrapper::field_elem::FieldElementVector;
     use amcl_wrapper::group_elem::GroupElementVector;
     use std::collections::{HashMap, HashSet};
+    use zeroize::Zeroize;
 
     #[test]
     fn test_keygen() {
@@ -232,8 +235,8 @@ mod tests {
     fn check_reconstructed_keys(
         threshold: usize,
         msg_count: usize,
-        secret_x: FieldElement,
-        secret_y: FieldElementVector,
+        secret_x: SecretFieldElement,
+        secret_y: Vec<SecretFieldElement>,
         signers: &[Signer],
         params: &Params,
     ) {
@@ -241,17 +244,23 @@ mod tests {
         let mut shares_x = HashMap::<usize, FieldElement>::new();
         let mut shares_y = vec![HashMap::<usize, FieldElement>::new(); msg_count];
         for i in 0..threshold {
-            shares_x.insert(signers[i].id, signers[i].sigkey.x.clone());
+            shares_x.insert(signers[i].id, signers[i].sigkey.x.expose_secret().clone());
             for j in 0..msg_count {
-                shares_y[j].insert(signers[i].id, signers[i].sigkey.y[j].clone());
+                shares_y[j].insert(signers[i].id, signers[i].sigkey.y[j].expose_secret().clone());
             }
         }
-        let recon_sec_x = reconstruct_secret(threshold, shares_x);
-        assert_eq!(secret_x, recon_sec_x);
+        let recon_sec_x = reconstruct_secret(threshold, shares_x.clone());
+        assert_eq!(secret_x.expose_secret(), &recon_sec_x);
 
         for i in 0..msg_count {
             let recon_sec_y = reconstruct_secret(threshold, shares_y[i].clone());
-            assert_eq!(secret_y[i], recon_sec_y);
+            assert_eq!(secret_y[i].expose_secret(), &recon_sec_y);
+        }
+        // The shares are copies of the signing keys
+        for shares in std::iter::once(&mut shares_x).chain(shares_y.iter_mut()) {
+            for share in shares.values_mut() {
+                share.zeroize();
+            }
         }
 
         // Reconstruct public key
@@ -278,7 +287,7 @@ mod tests {
             }
         }
 
-        let expected_X_tilde = &params.g_tilde * &secret_x;
+        let expected_X_tilde = &params.g_tilde * secret_x.expose_secret();
         assert_eq!(
             expected_X_tilde,
             recon_X_tilde_bases
@@ -287,7 +296,7 @@ mod tests {
         );
 
         for i in 0..msg_count {
-            let expected_Y_tilde_i = &params.g_tilde * &secret_y[i];
+            let expected_Y_tilde_i = &params.g_tilde * secret_y[i].expose_secret();
             assert_eq!(
                 expected_Y_tilde_i,
                 recon_Y_tilde_bases[i]
@@ -27,6 +27,9 @@ pub mod errors;
 #[macro_use]
 pub mod elgamal;
 pub mod keygen;
+pub mod param_spec;
 pub mod pok_sig;
+pub mod secret;
 pub mod signature;
+pub mod validation;
 // pub mod js_signature;
\ No newline at end of file
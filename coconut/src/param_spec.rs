@@ -0,0 +1,144 @@
+// Derivation of public parameters.
+//
+// Every generator is hashed to the curve from the label, a domain separation tag and the name of
+// the generator. No one picks a generator, so no one knows the discrete log of one generator with
+// respect to another, and anyone can recompute the parameters from the spec to check this.
+
+use crate::errors::CoconutError;
+use crate::signature::Params;
+use crate::validation::invalid;
+use amcl_wrapper::group_elem::{GroupElement, GroupElementVector};
+use sha2::{Digest, Sha256};
+
+use crate::{SignatureGroup, SignatureGroupVec, VerkeyGroup};
+
+/// Version of the derivation implemented by `ParamSpec`.
+pub const PARAM_SPEC_VERSION: u16 = 1;
+
+const DOMAIN_SEPARATION_TAG: &[u8] = b"coconut params : ";
+
+/// Everything the parameters are derived from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParamSpec {
+    pub version: u16,
+    pub label: Vec<u8>,
+    pub msg_count: usize,
+}
+
+impl ParamSpec {
+    pub fn new(msg_count: usize, label: &[u8]) -> Self {
+        ParamSpec {
+            version: PARAM_SPEC_VERSION,
+            label: label.to_vec(),
+            msg_count,
+        }
+    }
+
+    /// Input hashed to the curve for generator `name`, indexed for lists of generators. Label and
+    /// name are length prefixed so no two (label, name, index) give the same input.
+    pub fn generator_input(&self, name: &str, index: Option<usize>) -> Vec<u8> {
+        let mut bytes = DOMAIN_SEPARATION_TAG.to_vec();
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.extend_from_slice(&(self.label.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&self.label);
+        bytes.extend_from_slice(&(name.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        if let Some(i) = index {
+            bytes.extend_from_slice(&(i as u64).to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn signature_group_generator(&self, name: &str, index: Option<usize>) -> SignatureGroup {
+        SignatureGroup::from_msg_hash(&self.generator_input(name, index))
+    }
+
+    pub fn verkey_group_generator(&self, name: &str, index: Option<usize>) -> VerkeyGroup {
+        VerkeyGroup::from_msg_hash(&self.generator_input(name, index))
+    }
+
+    pub fn params(&self) -> Params {
+        let mut h = SignatureGroupVec::with_capacity(self.msg_count);
+        for i in 0..self.msg_count {
+            h.push(self.signature_group_generator("h", Some(i)));
+        }
+        Params {
+            g: self.signature_group_generator("g", None),
+            g_tilde: self.verkey_group_generator("g_tilde", None),
+            h,
+        }
+    }
+
+    /// Params of credential type `type_id`. They share `g` and `g_tilde`, and so the signing keys,
+    /// with `params()` but have their own message generators, so a signature request made for one
+    /// type does not verify as a request for another.
+    pub fn type_params(&self, type_id: &str) -> Params {
+        let name = format!("h : type : {}", type_id);
+        let mut params = self.params();
+        params.h = SignatureGroupVec::with_capacity(self.msg_count);
+        for i in 0..self.msg_count {
+            params.h.push(self.signature_group_generator(&name, Some(i)));
+        }
+        params
+    }
+
+    /// Check that `params` are the ones derived from this spec
+    pub fn verify_derivation(&self, params: &Params) -> Result<(), CoconutError> {
+        if self.version != PARAM_SPEC_VERSION {
+            return Err(invalid(format!("Unsupported parameter derivation version {}", self.version)));
+        }
+        let derived = self.params();
+        if derived.g != params.g || derived.g_tilde != params.g_tilde || derived.h.as_slice() != params.h.as_slice() {
+            return Err(invalid(String::from("Params are not derived from the spec")));
+        }
+        Ok(())
+    }
+
+    /// Hash of the spec and `params`. Parties embed it in their messages so that messages made
+    /// under other parameters are rejected. Check `params` with `verify_derivation` first.
+    pub fn fingerprint(&self, params: &Params) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.generator_input("fingerprint", None));
+        hasher.update(&(self.msg_count as u64).to_be_bytes());
+        hasher.update(&params.g.to_bytes());
+        hasher.update(&params.g_tilde.to_bytes());
+        for h in params.h.as_slice() {
+            hasher.update(&h.to_bytes());
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_derivation() {
+        let spec = ParamSpec::new(3, "test".as_bytes());
+        let params = spec.params();
+        spec.verify_derivation(&params).unwrap();
+        assert_eq!(spec.fingerprint(&params), spec.fingerprint(&Params::new(3, "test".as_bytes())));
+
+        // Generators with a known relation are caught
+        let mut trapdoor = params.clone();
+        trapdoor.h[1] = &params.g + &params.g;
+        assert!(spec.verify_derivation(&trapdoor).is_err());
+
+        let other = ParamSpec::new(3, "other".as_bytes());
+        assert!(other.verify_derivation(&params).is_err());
+        assert_ne!(other.fingerprint(&other.params()), spec.fingerprint(&params));
+
+        // Length prefixes keep label and name apart
+        assert_ne!(
+            ParamSpec::new(1, "a".as_bytes()).generator_input("bh", None),
+            ParamSpec::new(1, "ab".as_bytes()).generator_input("h", None)
+        );
+
+        // Types share the signing generators but not the message generators
+        let typed = spec.type_params("passport");
+        assert_eq!(typed.g_tilde, params.g_tilde);
+        assert_ne!(typed.h[0], params.h[0]);
+        assert_ne!(typed.h[0], spec.type_params("license").h[0]);
+    }
+}
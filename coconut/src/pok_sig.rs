@@ -26,10 +26,10 @@ mod tests {
 
         let msgs = (0..msg_count).map(|_| FieldElement::random()).collect::<Vec<FieldElement>>();
         let (elg_sk, elg_pk) = elgamal_keygen!(&params.g);
-        let (sig_req, randomness) = SignatureRequest::new(&FieldElementVector::from(msgs.as_slice()), count_hidden, &elg_pk, &params);
+        let (sig_req, randomness) = SignatureRequest::new(&FieldElementVector::from(msgs.as_slice()), count_hidden, &elg_pk, &params).unwrap();
 
         // Initiate proof of knowledge of various items of Signature request
-        let sig_req_pok = SignatureRequestPoK::init(&sig_req, &elg_pk, &params);
+        let sig_req_pok = SignatureRequestPoK::init(&sig_req, &elg_pk, &params).unwrap();
 
         // The challenge can include other things also (if proving other predicates)
         let challenge_for_prover = FieldElement::from_msg_hash(&sig_req_pok.to_bytes());
@@ -53,7 +53,7 @@ mod tests {
             assert!(sig_req_proof
                 .verify(&sig_req, &elg_pk, &challenge_for_verifier, &params)
                 .unwrap());
-            blinded_sigs.push(BlindSignature::new(&sig_req, &signers[i].sigkey));
+            blinded_sigs.push(BlindSignature::new(&sig_req, &signers[i].sigkey).unwrap());
         }
 
         let mut unblinded_sigs = vec![];
@@ -0,0 +1,82 @@
+use amcl_wrapper::field_elem::{FieldElement, FieldElementVector};
+use std::fmt;
+use zeroize::Zeroize;
+
+/// Secret scalar such as a signing key, an Elgamal secret key or a user secret. The value is
+/// zeroized when dropped, is redacted from Debug output and can only be read through
+/// `expose_secret`. Every clone is zeroized on drop as well.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SecretFieldElement(FieldElement);
+
+impl SecretFieldElement {
+    pub fn new(value: FieldElement) -> Self {
+        SecretFieldElement(value)
+    }
+
+    pub fn random() -> Self {
+        SecretFieldElement(FieldElement::random())
+    }
+
+    pub fn expose_secret(&self) -> &FieldElement {
+        &self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl From<FieldElement> for SecretFieldElement {
+    fn from(value: FieldElement) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Zeroize for SecretFieldElement {
+    fn zeroize(&mut self) {
+        // Clears the limbs with volatile writes, through amcl_wrapper's impl
+        self.0.zeroize();
+    }
+}
+
+impl Drop for SecretFieldElement {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+/// Overwrite every element of `values`, for temporary copies of secrets that have to be passed
+/// as a plain vector
+pub fn zeroize_vector(values: &mut FieldElementVector) {
+    for i in 0..values.len() {
+        values[i].zeroize();
+    }
+}
+
+impl fmt::Debug for SecretFieldElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretFieldElement([REDACTED])")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_field_element() {
+        let value = FieldElement::random();
+        let mut secret = SecretFieldElement::new(value.clone());
+        assert_eq!(secret.expose_secret(), &value);
+        assert!(!format!("{:?}", secret).contains(&value.to_hex()));
+
+        // Serialization is the same as for the plain value
+        let json = serde_json::to_string(&secret).unwrap();
+        assert_eq!(json, serde_json::to_string(&value).unwrap());
+        assert_eq!(serde_json::from_str::<SecretFieldElement>(&json).unwrap(), secret);
+
+        secret.zeroize();
+        assert!(secret.is_zero());
+    }
+}
@@ -1,4 +1,6 @@
-use crate::errors::CoconutError;
+use crate::errors::{CoconutError, CoconutErrorKind};
+use crate::secret::{zeroize_vector, SecretFieldElement};
+use crate::param_spec::ParamSpec;
 use secret_sharing::polynomial::Polynomial;
 use crate::{ate_2_pairing, VerkeyGroup, VerkeyGroupVec, SignatureGroup, SignatureGroupVec};
 use amcl_wrapper::field_elem::{FieldElement, FieldElementVector};
@@ -8,6 +10,7 @@ use ps_sig::keys::Params as PSParams;
 use ps_sig::keys::Verkey as PSVerkey;
 use ps_sig::signature::Signature as PSSignature;
 use std::collections::HashSet;
+use zeroize::Zeroize;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Params {
@@ -18,17 +21,9 @@ pub struct Params {
 
 impl Params {
     /// Generate g1, g2 and 1 h for each message. These are shared by all signers and users.
-    /// "Setup" from paper.
+    /// "Setup" from paper. Derived as described by `ParamSpec`.
     pub fn new(msg_count: usize, label: &[u8]) -> Self {
-        let g = SignatureGroup::from_msg_hash(&[label, " : g".as_bytes()].concat());
-        let g_tilde = VerkeyGroup::from_msg_hash(&[label, " : g_tilde".as_bytes()].concat());
-        let mut h = SignatureGroupVec::with_capacity(msg_count);
-        for i in 0..msg_count {
-            h.push(SignatureGroup::from_msg_hash(
-                &[label, " : y".as_bytes(), i.to_string().as_bytes()].concat(),
-            ));
-        }
-        Self { g, g_tilde, h }
+        ParamSpec::new(msg_count, label).params()
     }
 
     pub fn msg_count(&self) -> usize {
@@ -38,8 +33,8 @@ impl Params {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Sigkey {
-    pub x: FieldElement,
-    pub y: Vec<FieldElement>,
+    pub x: SecretFieldElement,
+    pub y: Vec<SecretFieldElement>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -52,6 +47,9 @@ pub struct Verkey {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SignatureRequest {
     pub known_messages: FieldElementVector,
+    /// Indices of the hidden messages in increasing order, one for each ciphertext. The known
+    /// messages take the remaining indices, in order.
+    pub hidden_indices: Vec<usize>,
     pub commitment: SignatureGroup,
     pub ciphertexts: Vec<(SignatureGroup, SignatureGroup)>,
 }
@@ -103,6 +101,23 @@ pub fn transform_to_PS_sig(sig: &Signature) -> PSSignature {
     }
 }
 
+/// Check that `hidden_indices` are increasing indices of messages among `msg_count` messages
+pub fn check_hidden_indices(hidden_indices: &[usize], msg_count: usize) -> Result<(), CoconutError> {
+    let increasing = hidden_indices.windows(2).all(|w| w[0] < w[1]);
+    if !increasing || hidden_indices.last().map_or(false, |i| *i >= msg_count) {
+        return Err(CoconutErrorKind::ValidationError {
+            msg: format!("Invalid hidden message indices {:?} for {} messages", hidden_indices, msg_count),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Indices among `msg_count` messages that are not in `hidden_indices`
+pub fn known_indices(hidden_indices: &[usize], msg_count: usize) -> Vec<usize> {
+    (0..msg_count).filter(|i| !hidden_indices.contains(i)).collect()
+}
+
 /// Created by entity requesting a signature to prove knowledge of hidden elements used in SignatureRequest.
 /// Represents the commitment phase of Schnoor protocol
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -110,6 +125,8 @@ pub struct SignatureRequestPoK {
     pub pok_vc_elgamal_sk: ProverCommittedSignatureGroup,
     pub pok_vc_commitment: ProverCommittedSignatureGroup,
     pub pok_vc_ciphertext: Vec<(ProverCommittedSignatureGroup, ProverCommittedSignatureGroup)>,
+    /// For the tag computed from the first hidden message, if any
+    pub pok_vc_tag: Option<ProverCommittedSignatureGroup>,
 }
 
 /// Created by entity requesting a signature to prove knowledge of hidden elements used in SignatureRequest.
@@ -119,6 +136,8 @@ pub struct SignatureRequestProof {
     pub proof_elgamal_sk: ProofSignatureGroup,
     pub proof_commitment: ProofSignatureGroup,
     pub proof_ciphertexts: Vec<(ProofSignatureGroup, ProofSignatureGroup)>,
+    #[serde(default)]
+    pub proof_tag: Option<ProofSignatureGroup>,
 }
 
 impl SignatureRequest {
@@ -129,50 +148,63 @@ impl SignatureRequest {
         count_hidden: usize,
         elgamal_pubkey: &SignatureGroup,
         params: &Params,
-    ) -> (Self, FieldElementVector) {
-        assert!(messages.len() >= count_hidden);
-        assert_eq!(messages.len(), params.h.len());
+    ) -> Result<(Self, FieldElementVector), CoconutError> {
+        let hidden_indices = (0..count_hidden).collect::<Vec<usize>>();
+        Self::new_with_hidden_indices(messages, &hidden_indices, elgamal_pubkey, params)
+    }
+
+    /// Like `new` but the messages at `hidden_indices`, in increasing order, are hidden from the signer.
+    pub fn new_with_hidden_indices(
+        messages: &FieldElementVector,
+        hidden_indices: &[usize],
+        elgamal_pubkey: &SignatureGroup,
+        params: &Params,
+    ) -> Result<(Self, FieldElementVector), CoconutError> {
+        if messages.len() != params.h.len() {
+            return Err(CoconutErrorKind::UnsupportedNoOfMessages {
+                expected: params.h.len(),
+                given: messages.len(),
+            }
+            .into());
+        }
+        check_hidden_indices(hidden_indices, messages.len())?;
+        let count_hidden = hidden_indices.len();
 
         // Randomness for commitment and ciphertexts. Used to prove knowleddge later on
         let mut randomness = FieldElementVector::with_capacity(count_hidden + 1);
 
         // Commit to the hidden messages
-        let mut bases: SignatureGroupVec = params
-            .h
+        let mut bases: SignatureGroupVec = hidden_indices
             .iter()
-            .take(count_hidden)
-            .map(|g| g.clone())
+            .map(|i| params.h[*i].clone())
             .collect::<Vec<SignatureGroup>>()
             .into();
         bases.push(params.g.clone());
-        let mut exponents: FieldElementVector = messages
+        let mut exponents: FieldElementVector = hidden_indices
             .iter()
-            .take(count_hidden)
-            .map(|f| f.clone())
+            .map(|i| messages[*i].clone())
             .collect::<Vec<FieldElement>>()
             .into();
         let r = FieldElement::random();
         exponents.push(r.clone());
-        // commitment = h_1^m_1.h_2^m_2...h_count_hidden^m_count_hidden.g_1^r
-        let commitment = bases.multi_scalar_mul_const_time(&exponents).unwrap();
+        // commitment = h_i^m_i... for each hidden index i, times g_1^r
+        let commitment = multi_scalar_mul(&bases, &exponents)?;
 
         randomness.push(r);
 
-        let known_messages = messages
-            .iter()
-            .skip(count_hidden)
-            .map(|f| f.clone())
+        let known_messages = known_indices(hidden_indices, messages.len())
+            .into_iter()
+            .map(|i| messages[i].clone())
             .collect::<Vec<FieldElement>>();
 
         // Each element of `ciphertexts` is the elgamal ciphertext and the randomness used during encryption.
         // The randomness is used for proof of knowledge
         let ciphertexts = if count_hidden > 0 {
             let h = Self::compute_h(&commitment, &known_messages);
-            messages
+            hidden_indices
                 .iter()
-                .take(count_hidden)
-                .map(|m| {
-                    let (c1, c2, k) = elgamal_encrypt!(&params.g, elgamal_pubkey, &(&h * m));
+                .map(|i| {
+                    let (c1, c2, k) = elgamal_encrypt!(&params.g, elgamal_pubkey, &(&h * &messages[*i]));
                     randomness.push(k);
                     (c1, c2)
                 })
@@ -181,14 +213,15 @@ impl SignatureRequest {
             vec![]
         };
 
-        (
+        Ok((
             Self {
                 known_messages: known_messages.into(),
+                hidden_indices: hidden_indices.to_vec(),
                 commitment,
                 ciphertexts,
             },
             randomness,
-        )
+        ))
     }
 
     /// Compute a generator in SignatureGroup by hashing commitment to hidden messages and all known messages.
@@ -208,6 +241,39 @@ impl SignatureRequest {
     pub fn h(&self) -> SignatureGroup {
         Self::compute_h(&self.commitment, self.known_messages.as_slice())
     }
+
+    pub fn msg_count(&self) -> usize {
+        self.known_messages.len() + self.ciphertexts.len()
+    }
+
+    /// Indices of the known messages
+    pub fn known_indices(&self) -> Vec<usize> {
+        known_indices(&self.hidden_indices, self.msg_count())
+    }
+
+    /// Check that the request is over as many messages as `count` with a hidden index for each
+    /// ciphertext
+    pub fn check_message_count(&self, count: usize) -> Result<(), CoconutError> {
+        if self.msg_count() != count {
+            return Err(CoconutErrorKind::UnsupportedNoOfMessages {
+                expected: count,
+                given: self.msg_count(),
+            }
+            .into());
+        }
+        if self.hidden_indices.len() != self.ciphertexts.len() {
+            return Err(CoconutErrorKind::ValidationError {
+                msg: format!("{} hidden indices for {} ciphertexts", self.hidden_indices.len(), self.ciphertexts.len()),
+            }
+            .into());
+        }
+        check_hidden_indices(&self.hidden_indices, count)
+    }
+
+    /// Generators of the hidden messages in the commitment
+    pub fn hidden_bases(&self, params: &Params) -> Vec<SignatureGroup> {
+        self.hidden_indices.iter().map(|i| params.h[*i].clone()).collect()
+    }
 }
 
 impl SignatureRequestPoK {
@@ -221,11 +287,44 @@ impl SignatureRequestPoK {
         sig_req: &SignatureRequest,
         elgamal_pk: &SignatureGroup,
         params: &Params,
-    ) -> SignatureRequestPoK {
-        assert_eq!(
-            sig_req.known_messages.len() + sig_req.ciphertexts.len(),
-            params.h.len()
-        );
+    ) -> Result<SignatureRequestPoK, CoconutError> {
+        Self::init_with_tag(sig_req, elgamal_pk, None, params)
+    }
+
+    /// Like `init`, also proving that the tag `tag_base^m_1` is computed from the first hidden
+    /// message `m_1`.
+    pub fn init_with_tag(
+        sig_req: &SignatureRequest,
+        elgamal_pk: &SignatureGroup,
+        tag_base: Option<&SignatureGroup>,
+        params: &Params,
+    ) -> Result<SignatureRequestPoK, CoconutError> {
+        Self::init_with_blindings(sig_req, elgamal_pk, tag_base, None, params)
+    }
+
+    /// Like `init_with_tag`, using the given blindings for the hidden messages. A proof of another
+    /// protocol that uses the same blindings for the same messages under the same challenge has
+    /// the same responses for them, which shows that the messages are equal.
+    pub fn init_with_blindings(
+        sig_req: &SignatureRequest,
+        elgamal_pk: &SignatureGroup,
+        tag_base: Option<&SignatureGroup>,
+        hidden_blindings: Option<&[FieldElement]>,
+        params: &Params,
+    ) -> Result<SignatureRequestPoK, CoconutError> {
+        sig_req.check_message_count(params.h.len())?;
+        if hidden_blindings.map_or(false, |b| b.len() != sig_req.hidden_indices.len()) {
+            return Err(CoconutErrorKind::GeneralError {
+                msg: format!("Blindings must be given for all {} hidden messages", sig_req.hidden_indices.len()),
+            }
+            .into());
+        }
+        if tag_base.is_some() && sig_req.ciphertexts.is_empty() {
+            return Err(CoconutErrorKind::GeneralError {
+                msg: String::from("Tag needs a hidden message"),
+            }
+            .into());
+        }
 
         // For knowledge of Elgamal secret key
         let mut committing_elgamal_sk = ProverCommittingSignatureGroup::new();
@@ -236,8 +335,11 @@ impl SignatureRequestPoK {
         let mut committing_comm = ProverCommittingSignatureGroup::new();
         // Since the hidden messages are same inside this commitment and ciphertexts, same blinding needs to be used.
         let mut hidden_msg_blindings = vec![];
-        for h in params.h.iter().take(sig_req.ciphertexts.len()) {
-            let b = FieldElement::random();
+        for (i, h) in sig_req.hidden_bases(params).iter().enumerate() {
+            let b = match hidden_blindings {
+                Some(blindings) => blindings[i].clone(),
+                None => FieldElement::random(),
+            };
             committing_comm.commit(h, Some(&b));
             hidden_msg_blindings.push(b);
         }
@@ -247,7 +349,7 @@ impl SignatureRequestPoK {
 
         let ciphertext_commts = if sig_req.ciphertexts.len() > 0 {
             // XXX: This computation can be avoided if h is persisted from `new`
-            let h = SignatureRequest::compute_h(&sig_req.commitment, sig_req.known_messages.as_slice());
+            let h = sig_req.h();
 
             let mut ciphertext_commts = vec![];
             for i in 0..sig_req.ciphertexts.len() {
@@ -265,11 +367,19 @@ impl SignatureRequestPoK {
             vec![]
         };
 
-        SignatureRequestPoK {
+        // The tag uses the same blinding as the first hidden message in the commitment
+        let tag_commt = tag_base.map(|base| {
+            let mut committing_tag = ProverCommittingSignatureGroup::new();
+            committing_tag.commit(base, Some(&hidden_msg_blindings[0]));
+            committing_tag.finish()
+        });
+
+        Ok(SignatureRequestPoK {
             pok_vc_elgamal_sk: committed_elgamal_sk,
             pok_vc_commitment: committed_comm,
             pok_vc_ciphertext: ciphertext_commts,
-        }
+            pok_vc_tag: tag_commt,
+        })
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -280,18 +390,32 @@ impl SignatureRequestPoK {
             bytes.append(&mut pok_vc_1.to_bytes());
             bytes.append(&mut pok_vc_2.to_bytes());
         }
+        if let Some(pok_vc_tag) = &self.pok_vc_tag {
+            bytes.append(&mut pok_vc_tag.to_bytes());
+        }
         bytes
     }
 
     pub fn gen_proof(
         self,
         hidden_messages: &FieldElementVector,
-        randomness: FieldElementVector,
+        mut randomness: FieldElementVector,
         elgamal_sk: &FieldElement,
         challenge: &FieldElement,
     ) -> Result<SignatureRequestProof, CoconutError> {
-        assert_eq!(self.pok_vc_ciphertext.len(), hidden_messages.len());
-        assert_eq!(self.pok_vc_ciphertext.len(), randomness.len() - 1);
+        if self.pok_vc_ciphertext.len() != hidden_messages.len()
+            || self.pok_vc_ciphertext.len() + 1 != randomness.len()
+        {
+            return Err(CoconutErrorKind::GeneralError {
+                msg: format!(
+                    "Proof over {} hidden messages given {} messages and {} randomness",
+                    self.pok_vc_ciphertext.len(),
+                    hidden_messages.len(),
+                    randomness.len()
+                ),
+            }
+            .into());
+        }
 
         // Proof of knowledge of Elgamal secret key.
         let proof_elgamal_sk = self
@@ -316,10 +440,20 @@ impl SignatureRequestPoK {
             )?;
             proof_ciphertexts.push((proof_1, proof_2));
         }
+
+        let proof_tag = match self.pok_vc_tag {
+            Some(pok_vc_tag) => Some(pok_vc_tag.gen_proof(challenge, &[hidden_messages[0].clone()])?),
+            None => None,
+        };
+        // The randomness of the ciphertexts reveals the hidden messages, like the copies of them
+        // in the commitment proof
+        secrets_commitment.iter_mut().zeroize();
+        zeroize_vector(&mut randomness);
         Ok(SignatureRequestProof {
             proof_elgamal_sk,
             proof_commitment,
             proof_ciphertexts,
+            proof_tag,
         })
     }
 }
@@ -336,7 +470,7 @@ impl SignatureRequestProof {
         bytes.extend_from_slice(&g_bytes);
         bytes.append(&mut self.proof_elgamal_sk.commitment.to_bytes());
 
-        for h in params.h.iter().take(sig_req.ciphertexts.len()) {
+        for h in sig_req.hidden_bases(params) {
             bytes.append(&mut h.to_bytes());
         }
         bytes.extend_from_slice(&g_bytes);
@@ -355,6 +489,39 @@ impl SignatureRequestProof {
         bytes
     }
 
+    /// Bytes of the tag proof for the challenge, to be appended to `get_bytes_for_challenge`.
+    /// Empty if the proof has no tag proof.
+    pub fn get_tag_bytes_for_challenge(&self, tag_base: &SignatureGroup) -> Vec<u8> {
+        let mut bytes = vec![];
+        if let Some(proof_tag) = &self.proof_tag {
+            bytes.append(&mut tag_base.to_bytes());
+            bytes.append(&mut proof_tag.commitment.to_bytes());
+        }
+        bytes
+    }
+
+    /// Verify that `tag` is `tag_base` raised to the first hidden message. Must be used together
+    /// with `verify` with the same challenge.
+    pub fn verify_tag(
+        &self,
+        tag_base: &SignatureGroup,
+        tag: &SignatureGroup,
+        challenge: &FieldElement,
+    ) -> Result<bool, CoconutError> {
+        let proof_tag = match &self.proof_tag {
+            Some(proof_tag) => proof_tag,
+            None => return Ok(false),
+        };
+        // The response for the first hidden message should be same as that in the commitment.
+        if self.proof_commitment.responses.len() < 2
+            || proof_tag.responses.len() != 1
+            || proof_tag.responses[0] != self.proof_commitment.responses[0]
+        {
+            return Ok(false);
+        }
+        Ok(proof_tag.verify(&[tag_base.clone()], tag, challenge)?)
+    }
+
     pub fn verify(
         &self,
         sig_req: &SignatureRequest,
@@ -362,11 +529,12 @@ impl SignatureRequestProof {
         challenge: &FieldElement,
         params: &Params,
     ) -> Result<bool, CoconutError> {
-        assert_eq!(self.proof_ciphertexts.len(), sig_req.ciphertexts.len());
-        assert_eq!(
-            self.proof_commitment.responses.len(),
-            self.proof_ciphertexts.len() + 1
-        );
+        if sig_req.check_message_count(params.h.len()).is_err()
+            || self.proof_ciphertexts.len() != sig_req.ciphertexts.len()
+            || self.proof_commitment.responses.len() != self.proof_ciphertexts.len() + 1
+        {
+            return Ok(false);
+        }
 
         // Verify proof of knowledge of Elgamal secret key
         if !self
@@ -377,12 +545,7 @@ impl SignatureRequestProof {
         }
 
         // Verify proof of knowledge of hidden messages in the commitment
-        let mut bases = params
-            .h
-            .iter()
-            .take(sig_req.ciphertexts.len())
-            .map(|h| h.clone())
-            .collect::<Vec<SignatureGroup>>();
+        let mut bases = sig_req.hidden_bases(params);
         bases.push(params.g.clone());
         if !self
             .proof_commitment
@@ -392,11 +555,11 @@ impl SignatureRequestProof {
         }
 
         // XXX: This computation can be avoided if h is persisted`
-        let h = SignatureRequest::compute_h(&sig_req.commitment, sig_req.known_messages.as_slice());
+        let h = sig_req.h();
         let bases = vec![elgamal_pk.clone(), h];
         for (i, (proof_1, proof_2)) in self.proof_ciphertexts.iter().enumerate() {
             // The response for the hidden message should be same as that in the commitment.
-            if proof_2.responses[1] != self.proof_commitment.responses[i] {
+            if proof_2.responses.len() != 2 || proof_2.responses[1] != self.proof_commitment.responses[i] {
                 return Ok(false);
             }
 
@@ -413,22 +576,16 @@ impl SignatureRequestProof {
 
 impl BlindSignature {
     /// Signed creates a blinded signature. "BlindSign" from paper.
-    pub fn new(sig_request: &SignatureRequest, sigkey: &Sigkey) -> Self {
+    pub fn new(sig_request: &SignatureRequest, sigkey: &Sigkey) -> Result<Self, CoconutError> {
+        sig_request.check_message_count(sigkey.y.len())?;
         let hidden_msg_count = sig_request.ciphertexts.len();
 
-        assert_eq!(
-            hidden_msg_count + sig_request.known_messages.len(),
-            sigkey.y.len()
-        );
-
-        let h = SignatureRequest::compute_h(
-            &sig_request.commitment,
-            sig_request.known_messages.as_slice(),
-        );
+        let h = sig_request.h();
 
         // The blinded signature is (h, c_tilde).
         // c_tilde = (a_1^y_1.a_2^y_2...a_hidden_msg_count^y_hidden_msg_count, b_1^y_1.b_2^y_2....b_hidden_msg_count^y_hidden_msg_count . h^(x + y_{hidden_msg_count+1}*m_{hidden_msg_count+1} + y_{hidden_msg_count+2}*m_{hidden_msg_count+2} + .. y_n*m_n))
-        // where each (a_i, b_i) forms an element in `sig_request.ciphertexts`
+        // where each (a_i, b_i) forms an element in `sig_request.ciphertexts`. Indices are written as if
+        // the hidden messages come first, each y_i is that of the message's index in `hidden_indices`.
 
         // c_tilde_1 = a_1^y_1.a_2^y_2...a_hidden_msg_count^y_hidden_msg_count
         let mut c_tilde_1_bases = SignatureGroupVec::with_capacity(hidden_msg_count);
@@ -438,32 +595,32 @@ impl BlindSignature {
         let mut c_tilde_2_bases = SignatureGroupVec::with_capacity(hidden_msg_count + 1);
         let mut c_tilde_2_exps = FieldElementVector::with_capacity(hidden_msg_count + 1);
 
-        for (i, (a, b)) in sig_request.ciphertexts.iter().enumerate() {
+        for ((a, b), i) in sig_request.ciphertexts.iter().zip(sig_request.hidden_indices.iter()) {
             c_tilde_1_bases.push(a.clone());
-            c_tilde_1_exps.push(sigkey.y[i].clone());
+            c_tilde_1_exps.push(sigkey.y[*i].expose_secret().clone());
 
             c_tilde_2_bases.push(b.clone());
-            c_tilde_2_exps.push(sigkey.y[i].clone());
+            c_tilde_2_exps.push(sigkey.y[*i].expose_secret().clone());
         }
 
         // h^(x + y_j*m_j + y_{j+1}*m_{j+1}) for all known messages
         c_tilde_2_bases.push(h.clone());
-        let mut exp = sigkey.x.clone();
-        for i in 0..sig_request.known_messages.len() {
-            exp += &sigkey.y[hidden_msg_count + i] * &sig_request.known_messages[i];
+        let mut exp = sigkey.x.expose_secret().clone();
+        for (m, j) in sig_request.known_messages.iter().zip(sig_request.known_indices()) {
+            exp += sigkey.y[j].expose_secret() * m;
         }
         c_tilde_2_exps.push(exp);
 
-        let c_tilde_1 = c_tilde_1_bases
-            .multi_scalar_mul_const_time(&c_tilde_1_exps)
-            .unwrap();
-        let c_tilde_2 = c_tilde_2_bases
-            .multi_scalar_mul_const_time(&c_tilde_2_exps)
-            .unwrap();
-        Self {
+        let c_tilde_1 = multi_scalar_mul(&c_tilde_1_bases, &c_tilde_1_exps);
+        let c_tilde_2 = multi_scalar_mul(&c_tilde_2_bases, &c_tilde_2_exps);
+        // The exponents are copies of the signing key
+        zeroize_vector(&mut c_tilde_1_exps);
+        zeroize_vector(&mut c_tilde_2_exps);
+        let (c_tilde_1, c_tilde_2) = (c_tilde_1?, c_tilde_2?);
+        Ok(Self {
             h,
             blinded: (c_tilde_1, c_tilde_2),
-        }
+        })
     }
 
     /// User unblinds the blinded signature received from a signer. "Unblind" from paper.
@@ -477,6 +634,16 @@ impl BlindSignature {
     }
 }
 
+fn multi_scalar_mul(bases: &SignatureGroupVec, exponents: &FieldElementVector) -> Result<SignatureGroup, CoconutError> {
+    bases.multi_scalar_mul_const_time(exponents).map_err(|_| {
+        CoconutErrorKind::UnequalNoOfBasesExponents {
+            bases: bases.len(),
+            exponents: exponents.len(),
+        }
+        .into()
+    })
+}
+
 impl Signature {
     /// Create an aggregated signature from signatures from various signers. "AggCred" from paper.
     pub fn aggregate(threshold: usize, sigs: Vec<(usize, Signature)>) -> Signature {
@@ -604,6 +771,148 @@ impl Verkey {
         }
         Self { X_tilde, Y_tilde }
     }
+
+    /// Ids of the shares that do not lie on the polynomial of degree `threshold - 1` in the
+    /// exponent that goes through the most shares. Empty if all shares lie on one polynomial. When
+    /// no polynomial goes through more than `threshold` shares the culprits cannot be told apart
+    /// and all ids are returned.
+    pub fn inconsistent_shares(threshold: usize, keys: &[(usize, &Verkey)]) -> Vec<usize> {
+        if threshold == 0 || keys.len() <= threshold {
+            return vec![];
+        }
+        let off_polynomial = |basis: &[(usize, &Verkey)]| {
+            keys.iter()
+                .filter(|(id, vk)| {
+                    !basis.iter().any(|(b, _)| b == id) && !interpolate_at(basis, *id).equals(vk)
+                })
+                .map(|(id, _)| *id)
+                .collect::<Vec<usize>>()
+        };
+
+        // Shares are usually consistent, so try the first ones as basis before searching
+        let first = off_polynomial(&keys[..threshold]);
+        if first.is_empty() {
+            return first;
+        }
+        let mut best = first;
+        for subset in subsets(keys.len(), threshold) {
+            let basis = subset.iter().map(|&i| keys[i]).collect::<Vec<(usize, &Verkey)>>();
+            let off = off_polynomial(&basis);
+            if off.len() < best.len() {
+                best = off;
+            }
+            if best.is_empty() {
+                break;
+            }
+        }
+        if keys.len() - best.len() > threshold {
+            best
+        } else {
+            keys.iter().map(|(id, _)| *id).collect()
+        }
+    }
+
+    /// Create an aggregated verkey after checking that all the given shares lie on one polynomial
+    /// of degree `threshold - 1` in the exponent. Give more than `threshold` shares for the check
+    /// to detect anything.
+    pub fn checked_aggregate(threshold: usize, keys: Vec<(usize, &Verkey)>) -> Result<Verkey, CoconutError> {
+        if threshold == 0 || keys.len() < threshold {
+            return Err(CoconutErrorKind::GeneralError {
+                msg: format!("Need {} verkey shares but got {}", threshold, keys.len()),
+            }
+            .into());
+        }
+        let q = keys[0].1.Y_tilde.len();
+        if let Some((_, vk)) = keys.iter().find(|(_, vk)| vk.Y_tilde.len() != q) {
+            return Err(CoconutErrorKind::UnsupportedNoOfMessages {
+                expected: q,
+                given: vk.Y_tilde.len(),
+            }
+            .into());
+        }
+        let ids = keys.iter().map(|(id, _)| *id).collect::<HashSet<usize>>();
+        if ids.len() != keys.len() || ids.contains(&0) {
+            return Err(CoconutErrorKind::GeneralError {
+                msg: String::from("Verkey shares need distinct, non zero ids"),
+            }
+            .into());
+        }
+
+        let inconsistent = Self::inconsistent_shares(threshold, &keys);
+        if !inconsistent.is_empty() {
+            return Err(CoconutErrorKind::InconsistentVerkeyShares { ids: inconsistent }.into());
+        }
+        Ok(Self::aggregate(threshold, keys))
+    }
+
+    fn equals(&self, other: &Verkey) -> bool {
+        self.X_tilde == other.X_tilde && self.Y_tilde == other.Y_tilde
+    }
+}
+
+/// Lagrange basis polynomial of `id` over `ids`, evaluated at `x`
+fn lagrange_basis_at(ids: &[usize], id: usize, x: usize) -> FieldElement {
+    let x = FieldElement::from(x as u64);
+    let i = FieldElement::from(id as u64);
+    let mut numerator = FieldElement::one();
+    let mut denominator = FieldElement::one();
+    for &other in ids.iter().filter(|&&other| other != id) {
+        let m = FieldElement::from(other as u64);
+        numerator = numerator * (&x - &m);
+        denominator = denominator * (&i - &m);
+    }
+    numerator * denominator.inverse()
+}
+
+/// Verkey share of signer `x` on the polynomial through the shares of `basis`
+fn interpolate_at(basis: &[(usize, &Verkey)], x: usize) -> Verkey {
+    let ids = basis.iter().map(|(id, _)| *id).collect::<Vec<usize>>();
+    let q = basis[0].1.Y_tilde.len();
+    let mut X_tilde = VerkeyGroup::identity();
+    let mut Y_tilde = vec![VerkeyGroup::identity(); q];
+    for (id, vk) in basis {
+        let l = lagrange_basis_at(&ids, *id, x);
+        X_tilde = &X_tilde + &(&vk.X_tilde * &l);
+        for (y, vk_y) in Y_tilde.iter_mut().zip(vk.Y_tilde.iter()) {
+            *y = &*y + &(vk_y * &l);
+        }
+    }
+    Verkey { X_tilde, Y_tilde }
+}
+
+/// All subsets of size `k` of `0..n`, as sorted indices. They are generated one at a time as
+/// there are `n` choose `k` of them.
+fn subsets(n: usize, k: usize) -> Subsets {
+    Subsets {
+        n,
+        k,
+        next: if k <= n { Some((0..k).collect()) } else { None },
+    }
+}
+
+struct Subsets {
+    n: usize,
+    k: usize,
+    next: Option<Vec<usize>>,
+}
+
+impl Iterator for Subsets {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        let current = self.next.take()?;
+        let (n, k) = (self.n, self.k);
+        // Rightmost index that can still be increased
+        if let Some(i) = (0..k).rev().find(|&i| current[i] < n - k + i) {
+            let mut next = current.clone();
+            next[i] += 1;
+            for j in i + 1..k {
+                next[j] = next[j - 1] + 1;
+            }
+            self.next = Some(next);
+        }
+        Some(current)
+    }
 }
 
 #[cfg(test)]
@@ -617,8 +926,8 @@ mod tests {
     fn check_key_aggregation(
         threshold: usize,
         msg_count: usize,
-        secret_x: FieldElement,
-        secret_y: FieldElementVector,
+        secret_x: SecretFieldElement,
+        secret_y: Vec<SecretFieldElement>,
         signers: &[Signer],
         params: &Params,
     ) {
@@ -631,11 +940,11 @@ mod tests {
                 .collect::<Vec<(usize, &Verkey)>>(),
         );
 
-        let expected_X_tilde = &params.g_tilde * &secret_x;
+        let expected_X_tilde = &params.g_tilde * secret_x.expose_secret();
         assert_eq!(expected_X_tilde, aggr_vk.X_tilde);
 
         for i in 0..msg_count {
-            let expected_Y_tilde_i = &params.g_tilde * &secret_y[i];
+            let expected_Y_tilde_i = &params.g_tilde * secret_y[i].expose_secret();
             assert_eq!(expected_Y_tilde_i, aggr_vk.Y_tilde[i]);
         }
     }
@@ -643,18 +952,18 @@ mod tests {
     fn check_key_aggregation_gaps_in_ids(
         threshold: usize,
         msg_count: usize,
-        secret_x: FieldElement,
-        secret_y: FieldElementVector,
+        secret_x: SecretFieldElement,
+        secret_y: Vec<SecretFieldElement>,
         keys_to_aggr: Vec<(usize, &Verkey)>,
         params: &Params,
     ) {
         let aggr_vk = Verkey::aggregate(threshold, keys_to_aggr);
 
-        let expected_X_tilde = &params.g_tilde * &secret_x;
+        let expected_X_tilde = &params.g_tilde * secret_x.expose_secret();
         assert_eq!(expected_X_tilde, aggr_vk.X_tilde);
 
         for i in 0..msg_count {
-            let expected_Y_tilde_i = &params.g_tilde * &secret_y[i];
+            let expected_Y_tilde_i = &params.g_tilde * secret_y[i].expose_secret();
             assert_eq!(expected_Y_tilde_i, aggr_vk.Y_tilde[i]);
         }
     }
@@ -665,24 +974,34 @@ mod tests {
         count_hidden: usize,
         signers: &[Signer],
         params: &Params,
+    ) {
+        let hidden_indices = (0..count_hidden).collect::<Vec<usize>>();
+        check_signing_with_hidden_indices(threshold, msg_count, &hidden_indices, signers, params)
+    }
+
+    fn check_signing_with_hidden_indices(
+        threshold: usize,
+        msg_count: usize,
+        hidden_indices: &[usize],
+        signers: &[Signer],
+        params: &Params,
     ) {
         let msgs = (0..msg_count).map(|_| FieldElement::random()).collect::<Vec<FieldElement>>();
 
         let (elg_sk, elg_pk) = elgamal_keygen!(&params.g);
 
-        let (sig_req, randomness) = SignatureRequest::new(&FieldElementVector::from(msgs.as_slice()), count_hidden, &elg_pk, &params);
+        let (sig_req, randomness) = SignatureRequest::new_with_hidden_indices(&FieldElementVector::from(msgs.as_slice()), hidden_indices, &elg_pk, &params).unwrap();
 
         // Initiate proof of knowledge of various items of Signature request
-        let sig_req_pok = SignatureRequestPoK::init(&sig_req, &elg_pk, &params);
+        let sig_req_pok = SignatureRequestPoK::init(&sig_req, &elg_pk, &params).unwrap();
 
         // The challenge can include other things also (if proving other predicates)
         let challenge_for_prover = FieldElement::from_msg_hash(&sig_req_pok.to_bytes());
 
         // Create proof once the challenge is finalized
-        let hidden_msgs: FieldElementVector = msgs
+        let hidden_msgs: FieldElementVector = hidden_indices
             .iter()
-            .take(count_hidden)
-            .map(|m| m.clone())
+            .map(|i| msgs[*i].clone())
             .collect::<Vec<FieldElement>>()
             .into();
         let sig_req_proof = sig_req_pok
@@ -697,7 +1016,7 @@ mod tests {
             assert!(sig_req_proof
                 .verify(&sig_req, &elg_pk, &challenge_for_verifier, &params)
                 .unwrap());
-            blinded_sigs.push(BlindSignature::new(&sig_req, &signers[i].sigkey));
+            blinded_sigs.push(BlindSignature::new(&sig_req, &signers[i].sigkey).unwrap());
         }
 
         let mut unblinded_sigs = vec![];
@@ -761,6 +1080,26 @@ mod tests {
         check_signing_on_random_msgs(threshold, msg_count, count_hidden, &signers, &params)
     }
 
+    #[test]
+    fn test_sign_verify_arbitrary_hidden_indices() {
+        let threshold = 3;
+        let total = 5;
+        let msg_count = 6;
+        let params = Params::new(msg_count, "test".as_bytes());
+
+        let (_, _, signers) = trusted_party_SSS_keygen(threshold, total, &params);
+
+        check_signing_with_hidden_indices(threshold, msg_count, &[1, 4], &signers, &params);
+        check_signing_with_hidden_indices(threshold, msg_count, &[5], &signers, &params);
+        check_signing_with_hidden_indices(threshold, msg_count, &[], &signers, &params);
+
+        assert!(check_hidden_indices(&[1, 4], msg_count).is_ok());
+        assert!(check_hidden_indices(&[4, 1], msg_count).is_err());
+        assert!(check_hidden_indices(&[1, 1], msg_count).is_err());
+        assert!(check_hidden_indices(&[6], msg_count).is_err());
+        assert_eq!(known_indices(&[1, 4], msg_count), vec![0, 2, 3, 5]);
+    }
+
     #[test]
     fn test_sign_verify_verifiable_secret_sharing_keygen() {
         let threshold = 3;
@@ -790,6 +1129,37 @@ mod tests {
         check_signing_on_random_msgs(threshold, msg_count, count_hidden, &signers, &params)
     }
 
+    #[test]
+    fn test_checked_verkey_aggregation() {
+        let threshold = 3;
+        let total = 5;
+        let msg_count = 4;
+        let params = Params::new(msg_count, "test".as_bytes());
+        let (_, _, signers) = trusted_party_SSS_keygen(threshold, total, &params);
+        let (_, _, other_signers) = trusted_party_SSS_keygen(threshold, total, &params);
+
+        let keys = signers.iter().map(|s| (s.id, &s.verkey)).collect::<Vec<(usize, &Verkey)>>();
+        assert!(Verkey::inconsistent_shares(threshold, &keys).is_empty());
+        let aggr_vk = Verkey::checked_aggregate(threshold, keys.clone()).unwrap();
+        assert!(aggr_vk.equals(&Verkey::aggregate(threshold, keys.clone())));
+
+        // A share from another key is found, also when it is among the first shares
+        for poisoned in &[1, 4] {
+            let mut keys = keys.clone();
+            keys[*poisoned].1 = &other_signers[*poisoned].verkey;
+            assert_eq!(Verkey::inconsistent_shares(threshold, &keys), vec![keys[*poisoned].0]);
+            assert_eq!(
+                Verkey::checked_aggregate(threshold, keys.clone()).unwrap_err().kind(),
+                CoconutErrorKind::InconsistentVerkeyShares { ids: vec![keys[*poisoned].0] }
+            );
+        }
+
+        // With one share more than the threshold the culprit cannot be told apart
+        let mut keys = keys[..threshold + 1].to_vec();
+        keys[0].1 = &other_signers[0].verkey;
+        assert_eq!(Verkey::inconsistent_shares(threshold, &keys).len(), threshold + 1);
+    }
+
     #[test]
     fn test_verkey_aggregation_gaps_in_ids_shamir_secret_sharing_keygen() {
         let threshold = 3;
@@ -856,7 +1226,7 @@ mod tests {
 
         let (elg_sk, elg_pk) = elgamal_keygen!(&params.g);
 
-        let (sig_req, randomness) = SignatureRequest::new(&FieldElementVector::from(msgs.as_slice()), count_hidden, &elg_pk, &params);
+        let (sig_req, randomness) = SignatureRequest::new(&FieldElementVector::from(msgs.as_slice()), count_hidden, &elg_pk, &params).unwrap();
 
         // Signers from which signature will be requested.
         let mut signer_ids = HashSet::new();
@@ -864,7 +1234,7 @@ mod tests {
         signer_ids.insert(3);
         signer_ids.insert(5);
 
-        let sig_req_pok = SignatureRequestPoK::init(&sig_req, &elg_pk, &params);
+        let sig_req_pok = SignatureRequestPoK::init(&sig_req, &elg_pk, &params).unwrap();
         let challenge = FieldElement::from_msg_hash(&sig_req_pok.to_bytes());
         let hidden_msgs: FieldElementVector = msgs
             .iter()
@@ -882,7 +1252,7 @@ mod tests {
                 .verify(&sig_req, &elg_pk, &challenge, &params)
                 .unwrap());
             // Keys at index i have id i+1
-            blinded_sigs.push(BlindSignature::new(&sig_req, &signers[*i - 1].sigkey));
+            blinded_sigs.push(BlindSignature::new(&sig_req, &signers[*i - 1].sigkey).unwrap());
         }
 
         let mut unblinded_sigs = vec![];
@@ -904,4 +1274,16 @@ mod tests {
 
         assert!(aggr_sig.verify(msgs.clone(), &aggr_vk, &params));
     }
+
+    #[test]
+    fn test_subsets() {
+        let all = subsets(5, 2).collect::<Vec<Vec<usize>>>();
+        assert_eq!(all.len(), 10);
+        assert_eq!(all[0], vec![0, 1]);
+        assert_eq!(all[9], vec![3, 4]);
+        assert_eq!(subsets(3, 3).count(), 1);
+        assert_eq!(subsets(2, 3).count(), 0);
+        // Only the subsets asked for are built
+        assert_eq!(subsets(1000, 500).nth(2), Some((0..499).chain(vec![501]).collect()));
+    }
 }
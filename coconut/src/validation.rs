@@ -0,0 +1,198 @@
+use crate::errors::{CoconutError, CoconutErrorKind};
+use crate::keygen::Signer;
+use crate::signature::{
+    check_hidden_indices, BlindSignature, Params, ProofSignatureGroup, Signature, SignatureRequest, SignatureRequestProof,
+    Verkey,
+};
+use amcl_wrapper::group_elem::{GroupElement, GroupElementVector};
+use amcl_wrapper::group_elem_g1::G1;
+use amcl_wrapper::group_elem_g2::G2;
+use ps_sig::keys::Verkey as PSVerkey;
+use ps_sig::signature::Signature as PSSignature;
+
+/// Checks of values that come from outside, like deserialized ones, before they are used. Group
+/// elements must be in the prime order subgroup and must not be the identity where that would
+/// make a check pass trivially. Lengths must be consistent.
+pub trait Validate {
+    fn validate(&self) -> Result<(), CoconutError>;
+}
+
+pub fn invalid(msg: String) -> CoconutError {
+    CoconutErrorKind::ValidationError { msg }.into()
+}
+
+/// `element` must be in the prime order subgroup, the identity is allowed
+pub fn check_subgroup<G: GroupElement>(element: &G, name: &str) -> Result<(), CoconutError> {
+    if !element.has_correct_order() {
+        return Err(invalid(format!("{} is not in the prime order subgroup", name)));
+    }
+    Ok(())
+}
+
+/// `element` must be in the prime order subgroup and must not be the identity
+pub fn check_element<G: GroupElement>(element: &G, name: &str) -> Result<(), CoconutError> {
+    if element.is_identity() {
+        return Err(invalid(format!("{} is the identity", name)));
+    }
+    check_subgroup(element, name)
+}
+
+impl Validate for G1 {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(self, "Group element")
+    }
+}
+
+impl Validate for G2 {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(self, "Group element")
+    }
+}
+
+impl<T: Validate> Validate for Vec<T> {
+    fn validate(&self) -> Result<(), CoconutError> {
+        self.iter().try_for_each(|v| v.validate())
+    }
+}
+
+impl<T: Validate> Validate for Option<T> {
+    fn validate(&self) -> Result<(), CoconutError> {
+        self.as_ref().map_or(Ok(()), |v| v.validate())
+    }
+}
+
+impl Validate for Params {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.g, "g")?;
+        check_element(&self.g_tilde, "g_tilde")?;
+        if self.h.as_slice().is_empty() {
+            return Err(invalid(String::from("Params for no messages")));
+        }
+        for h in self.h.as_slice() {
+            check_element(h, "h")?;
+        }
+        Ok(())
+    }
+}
+
+impl Validate for Verkey {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.X_tilde, "X_tilde")?;
+        if self.Y_tilde.is_empty() {
+            return Err(invalid(String::from("Verkey for no messages")));
+        }
+        self.Y_tilde.iter().try_for_each(|y| check_element(y, "Y_tilde"))
+    }
+}
+
+impl Validate for PSVerkey {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.X_tilde, "X_tilde")?;
+        if self.Y_tilde.is_empty() {
+            return Err(invalid(String::from("Verkey for no messages")));
+        }
+        self.Y_tilde.iter().try_for_each(|y| check_element(y, "Y_tilde"))
+    }
+}
+
+impl Validate for Signer {
+    fn validate(&self) -> Result<(), CoconutError> {
+        self.verkey.validate()?;
+        if self.sigkey.y.len() != self.verkey.Y_tilde.len() {
+            return Err(CoconutErrorKind::UnsupportedNoOfMessages {
+                expected: self.verkey.Y_tilde.len(),
+                given: self.sigkey.y.len(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl Validate for SignatureRequest {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.commitment, "commitment")?;
+        if self.hidden_indices.len() != self.ciphertexts.len() {
+            return Err(invalid(format!("{} hidden indices for {} ciphertexts", self.hidden_indices.len(), self.ciphertexts.len())));
+        }
+        check_hidden_indices(&self.hidden_indices, self.msg_count())?;
+        for (c1, c2) in &self.ciphertexts {
+            check_element(c1, "ciphertext")?;
+            check_element(c2, "ciphertext")?;
+        }
+        Ok(())
+    }
+}
+
+impl Validate for SignatureRequestProof {
+    fn validate(&self) -> Result<(), CoconutError> {
+        self.proof_elgamal_sk.validate()?;
+        self.proof_commitment.validate()?;
+        for (p1, p2) in &self.proof_ciphertexts {
+            p1.validate()?;
+            p2.validate()?;
+        }
+        self.proof_tag.validate()
+    }
+}
+
+impl Validate for BlindSignature {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.h, "h")?;
+        check_subgroup(&self.blinded.0, "blinded signature")?;
+        check_subgroup(&self.blinded.1, "blinded signature")
+    }
+}
+
+impl Validate for Signature {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.sigma_1, "sigma_1")?;
+        check_subgroup(&self.sigma_2, "sigma_2")
+    }
+}
+
+impl Validate for PSSignature {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.sigma_1, "sigma_1")?;
+        check_subgroup(&self.sigma_2, "sigma_2")
+    }
+}
+
+impl Validate for ProofSignatureGroup {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_subgroup(&self.commitment, "proof commitment")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keygen::trusted_party_SSS_keygen;
+    use crate::{SignatureGroup, VerkeyGroup};
+
+    #[test]
+    fn test_validate() {
+        let params = Params::new(3, "test".as_bytes());
+        params.validate().unwrap();
+        let (_, _, signers) = trusted_party_SSS_keygen(2, 3, &params);
+        signers.validate().unwrap();
+
+        let mut bad_params = params.clone();
+        bad_params.g = SignatureGroup::identity();
+        assert_eq!(
+            bad_params.validate().unwrap_err().kind(),
+            CoconutErrorKind::ValidationError { msg: String::from("g is the identity") }
+        );
+
+        let mut bad_vk = signers[0].verkey.clone();
+        bad_vk.Y_tilde[1] = VerkeyGroup::identity();
+        assert!(bad_vk.validate().is_err());
+        bad_vk.Y_tilde = vec![];
+        assert!(bad_vk.validate().is_err());
+
+        let mut bad_signer = signers[0].clone();
+        bad_signer.sigkey.y.pop();
+        assert!(bad_signer.validate().is_err());
+        assert!(SignatureGroup::identity().validate().is_err());
+    }
+}
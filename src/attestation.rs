@@ -0,0 +1,362 @@
+// Attestation of visible attributes. Before co-signing a request an IdP checks the visible claims
+// against evidence it holds itself. Which attribute needs which verifier is decided by an
+// `AttestationPolicy`, so an IdP only co-signs claims it has actually checked. Single use evidence
+// is only used up once the IdP has signed, so a request refused for another reason keeps it.
+
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::errors::IssuanceError;
+
+/// Checks a claimed visible attribute against evidence local to the IdP.
+pub trait AttributeVerifier: fmt::Debug + Send + Sync {
+    /// Check the value of visible attribute `index`. `visible` holds all visible attributes of the
+    /// request and `evidence` whatever the client supplied for this verifier. Returns the reason
+    /// for rejecting the claim on failure.
+    fn verify(&self, index: usize, visible: &[String], evidence: Option<&str>) -> Result<(), String>;
+
+    /// Use up the evidence of a claim that passed `verify`, once the request is signed. Fails if
+    /// it was used up in the meantime. Evidence that can be reused needs nothing.
+    fn consume(&self, _index: usize, _visible: &[String], _evidence: Option<&str>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Accepts only values from a fixed list
+#[derive(Clone, Debug)]
+pub struct AllowListVerifier {
+    allowed: HashSet<String>,
+}
+
+impl AllowListVerifier {
+    pub fn new(allowed: Vec<String>) -> Self {
+        AllowListVerifier {
+            allowed: allowed.into_iter().collect(),
+        }
+    }
+}
+
+impl AttributeVerifier for AllowListVerifier {
+    fn verify(&self, index: usize, visible: &[String], _evidence: Option<&str>) -> Result<(), String> {
+        if self.allowed.contains(&visible[index]) {
+            Ok(())
+        } else {
+            Err(format!("Value {:?} is not allowed", visible[index]))
+        }
+    }
+}
+
+/// Looks up a record in a local directory. The record is selected by the visible attribute at
+/// `key_index` (e.g. a user id) and the claimed attribute must equal the value on record.
+///
+/// The directory file has one record per line, `key<TAB>value`. Empty lines and lines starting
+/// with `#` are skipped.
+#[derive(Clone, Debug)]
+pub struct DirectoryVerifier {
+    key_index: usize,
+    records: HashMap<String, String>,
+}
+
+impl DirectoryVerifier {
+    pub fn new(key_index: usize, records: HashMap<String, String>) -> Self {
+        DirectoryVerifier { key_index, records }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(key_index: usize, path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Ok(Self::new(key_index, Self::parse(&contents)?))
+    }
+
+    fn parse(contents: &str) -> io::Result<HashMap<String, String>> {
+        let mut records = HashMap::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '\t');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => {
+                    records.insert(key.to_string(), value.to_string());
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Line {} of directory is not a `key<TAB>value` record", n + 1),
+                    ))
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
+impl AttributeVerifier for DirectoryVerifier {
+    fn verify(&self, index: usize, visible: &[String], _evidence: Option<&str>) -> Result<(), String> {
+        let key = visible
+            .get(self.key_index)
+            .ok_or_else(|| format!("No directory key at attribute {}", self.key_index))?;
+        match self.records.get(key) {
+            Some(value) if value == &visible[index] => Ok(()),
+            Some(_) => Err(format!("Value does not match the directory record for {:?}", key)),
+            None => Err(format!("No directory record for {:?}", key)),
+        }
+    }
+}
+
+/// Wrong codes allowed for a value before its code is revoked
+pub const MAX_FAILED_ATTEMPTS: usize = 5;
+
+#[derive(Clone, Debug)]
+struct IssuedCode {
+    code: String,
+    used: usize,
+    failed: usize,
+}
+
+/// Stand-in for an out of band one time password check, e.g. a code mailed to the claimed
+/// address. `issue` creates a code for a value and the client returns it as evidence. A code is
+/// used up once as many IdPs as it allows have signed with it, and is revoked after
+/// `MAX_FAILED_ATTEMPTS` wrong guesses.
+#[derive(Clone, Debug)]
+pub struct OtpVerifier {
+    uses: usize,
+    codes: Arc<Mutex<HashMap<String, IssuedCode>>>,
+}
+
+impl Default for OtpVerifier {
+    fn default() -> Self {
+        Self::with_uses(1)
+    }
+}
+
+impl OtpVerifier {
+    /// Verifier for a single IdP, each code can be used once
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Verifier shared by the IdPs of a deployment, each code can be used by `uses` of them,
+    /// usually the threshold
+    pub fn with_uses(uses: usize) -> Self {
+        OtpVerifier {
+            uses,
+            codes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Create a code for `value`, replacing any earlier one. It is to be delivered to the owner
+    /// of `value` out of band.
+    pub fn issue(&self, value: &str) -> String {
+        let code = format!("{:06}", rand::thread_rng().gen_range(0, 1_000_000));
+        self.codes.lock().unwrap().insert(
+            value.to_string(),
+            IssuedCode {
+                code: code.clone(),
+                used: 0,
+                failed: 0,
+            },
+        );
+        code
+    }
+
+    /// Check `evidence` against the code of `value`, counting wrong guesses
+    fn check_code(&self, codes: &mut HashMap<String, IssuedCode>, value: &str, evidence: Option<&str>) -> Result<(), String> {
+        let evidence = evidence.ok_or_else(|| String::from("No one time password given"))?;
+        let issued = codes
+            .get_mut(value)
+            .ok_or_else(|| String::from("No one time password issued for value"))?;
+        if issued.code != evidence {
+            issued.failed += 1;
+            if issued.failed >= MAX_FAILED_ATTEMPTS {
+                codes.remove(value);
+            }
+            return Err(String::from("Wrong one time password"));
+        }
+        if issued.used >= self.uses {
+            return Err(String::from("One time password used up"));
+        }
+        Ok(())
+    }
+}
+
+impl AttributeVerifier for OtpVerifier {
+    fn verify(&self, index: usize, visible: &[String], evidence: Option<&str>) -> Result<(), String> {
+        self.check_code(&mut self.codes.lock().unwrap(), &visible[index], evidence)
+    }
+
+    fn consume(&self, index: usize, visible: &[String], evidence: Option<&str>) -> Result<(), String> {
+        let mut codes = self.codes.lock().unwrap();
+        self.check_code(&mut codes, &visible[index], evidence)?;
+        let used = codes.get_mut(&visible[index]).map_or(0, |issued| {
+            issued.used += 1;
+            issued.used
+        });
+        if used >= self.uses {
+            codes.remove(&visible[index]);
+        }
+        Ok(())
+    }
+}
+
+/// Visible attribute `index` must be checked by the verifier registered as `verifier`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestationRule {
+    pub index: usize,
+    pub verifier: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestationPolicy {
+    pub rules: Vec<AttestationRule>,
+}
+
+impl AttestationPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn require(mut self, index: usize, verifier: &str) -> Self {
+        self.rules.push(AttestationRule {
+            index,
+            verifier: verifier.to_string(),
+        });
+        self
+    }
+}
+
+/// Verifiers registered by name and the policy saying which attributes they check
+#[derive(Clone, Debug, Default)]
+pub struct Attestor {
+    policy: AttestationPolicy,
+    verifiers: HashMap<String, Arc<dyn AttributeVerifier>>,
+}
+
+impl Attestor {
+    pub fn new(policy: AttestationPolicy) -> Self {
+        Attestor {
+            policy,
+            verifiers: HashMap::new(),
+        }
+    }
+
+    pub fn register(mut self, name: &str, verifier: Arc<dyn AttributeVerifier>) -> Self {
+        self.verifiers.insert(name.to_string(), verifier);
+        self
+    }
+
+    /// Run every rule of the policy. A rule naming a verifier that is not registered fails.
+    pub fn check(&self, visible: &[String], evidence: &BTreeMap<String, String>) -> Result<(), IssuanceError> {
+        for rule in &self.policy.rules {
+            let fail = |reason: String| IssuanceError::AttestationFailed {
+                index: rule.index,
+                verifier: rule.verifier.clone(),
+                reason,
+            };
+            if rule.index >= visible.len() {
+                return Err(fail(String::from("Attribute missing")));
+            }
+            let verifier = self
+                .verifiers
+                .get(&rule.verifier)
+                .ok_or_else(|| fail(String::from("Verifier not registered")))?;
+            verifier
+                .verify(rule.index, visible, evidence.get(&rule.verifier).map(|e| e.as_str()))
+                .map_err(fail)?;
+        }
+        Ok(())
+    }
+
+    /// Use up the evidence of every rule once the request passed `check` and was signed
+    pub fn consume(&self, visible: &[String], evidence: &BTreeMap<String, String>) -> Result<(), IssuanceError> {
+        for rule in &self.policy.rules {
+            if let Some(verifier) = self.verifiers.get(&rule.verifier) {
+                verifier
+                    .consume(rule.index, visible, evidence.get(&rule.verifier).map(|e| e.as_str()))
+                    .map_err(|reason| IssuanceError::AttestationFailed {
+                        index: rule.index,
+                        verifier: rule.verifier.clone(),
+                        reason,
+                    })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_allow_list_and_directory() {
+        let directory = DirectoryVerifier::parse("# user and country\nalice\tNL\nbob\tDE\n").unwrap();
+        let attestor = Attestor::new(AttestationPolicy::new().require(1, "directory").require(2, "allow"))
+            .register("directory", Arc::new(DirectoryVerifier::new(0, directory)))
+            .register("allow", Arc::new(AllowListVerifier::new(strings(&["gold", "silver"]))));
+
+        assert!(attestor.check(&strings(&["alice", "NL", "gold"]), &BTreeMap::new()).is_ok());
+        assert!(attestor.check(&strings(&["alice", "DE", "gold"]), &BTreeMap::new()).is_err());
+        assert!(attestor.check(&strings(&["carol", "NL", "gold"]), &BTreeMap::new()).is_err());
+        assert!(attestor.check(&strings(&["bob", "DE", "bronze"]), &BTreeMap::new()).is_err());
+        assert!(attestor.check(&strings(&["bob", "DE"]), &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_otp() {
+        let otp = Arc::new(OtpVerifier::new());
+        let attestor = Attestor::new(AttestationPolicy::new().require(0, "otp")).register("otp", otp.clone());
+        let visible = strings(&["alice@example.com"]);
+
+        let code = otp.issue("alice@example.com");
+        let mut evidence = BTreeMap::new();
+        assert!(attestor.check(&visible, &evidence).is_err());
+
+        evidence.insert(String::from("otp"), code);
+        assert!(attestor.check(&visible, &evidence).is_ok());
+        // Checking does not use up the code, signing does
+        assert!(attestor.check(&visible, &evidence).is_ok());
+        attestor.consume(&visible, &evidence).unwrap();
+        assert!(attestor.check(&visible, &evidence).is_err());
+        assert!(attestor.consume(&visible, &evidence).is_err());
+    }
+
+    #[test]
+    fn test_otp_failed_attempts() {
+        let otp = OtpVerifier::new();
+        let visible = strings(&["alice@example.com"]);
+        let code = otp.issue("alice@example.com");
+        let wrong = if code == "000000" { "000001" } else { "000000" };
+
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            assert_eq!(otp.verify(0, &visible, Some(wrong)).unwrap_err(), "Wrong one time password");
+        }
+        // The code is revoked, even the right one is refused now
+        assert_eq!(
+            otp.verify(0, &visible, Some(&code)).unwrap_err(),
+            "No one time password issued for value"
+        );
+    }
+
+    #[test]
+    fn test_unregistered_verifier() {
+        let attestor = Attestor::new(AttestationPolicy::new().require(0, "missing"));
+        assert_eq!(
+            attestor.check(&strings(&["x"]), &BTreeMap::new()).unwrap_err(),
+            IssuanceError::AttestationFailed {
+                index: 0,
+                verifier: String::from("missing"),
+                reason: String::from("Verifier not registered"),
+            }
+        );
+    }
+}
@@ -0,0 +1,110 @@
+// Credentials with fewer attributes than the public params have message slots.
+//
+// The client's attributes take the first slots. The unused slots after them hold a padding
+// attribute and the slot right before the issuer attributes (or the last slot) holds the
+// attribute count. All of these are visible. The count makes the layout unambiguous: a padded
+// credential cannot be read as one with more attributes that happen to equal the padding.
+
+use crate::client::ClientIdRequest;
+use crate::errors::IssuanceError;
+use crate::issuer_attributes::IssuerAttributes;
+use crate::js::Public as PublicParams;
+
+pub const PADDING_ATTRIBUTE: &str = "coconut : padding";
+
+/// Plaintext of the attribute count slot
+pub fn count_attribute(count: usize) -> String {
+    format!("coconut : attribute count {}", count)
+}
+
+/// Where the padding and the count go under the given params
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttributeLayout {
+    pub msg_count: usize,
+    pub issuer_count: usize,
+}
+
+impl AttributeLayout {
+    pub fn new(params: &PublicParams) -> Self {
+        AttributeLayout {
+            msg_count: params.msg_count,
+            issuer_count: params.issuer_attributes.as_ref().map_or(0, |_| IssuerAttributes::COUNT),
+        }
+    }
+
+    /// Slot of the attribute count, also the most attributes a client can have
+    pub fn count_slot(&self) -> usize {
+        self.msg_count.saturating_sub(self.issuer_count + 1)
+    }
+
+    pub fn max_attributes(&self) -> usize {
+        self.count_slot()
+    }
+
+    /// Append the padding and the count to the visible attributes of a client with
+    /// `hidden_count` hidden attributes
+    pub fn pad(&self, hidden_count: usize, mut visible: Vec<String>) -> Result<Vec<String>, String> {
+        let count = hidden_count + visible.len();
+        if count > self.max_attributes() {
+            return Err(format!("{} attributes but at most {} fit", count, self.max_attributes()));
+        }
+        visible.extend(vec![PADDING_ATTRIBUTE.to_string(); self.max_attributes() - count]);
+        visible.push(count_attribute(count));
+        Ok(visible)
+    }
+
+    /// Check the padding and count of a request and return its attribute count. The plaintext
+    /// visible attributes must have been checked against the request already.
+    pub fn check(&self, request: &ClientIdRequest) -> Result<usize, IssuanceError> {
+        let malformed = |msg: &str| IssuanceError::MalformedRequest { msg: msg.to_string() };
+        let hidden = request.sig_req.hidden_indices.len();
+        if request.sig_req.msg_count() != self.msg_count || request.sig_req.hidden_indices.contains(&self.count_slot()) {
+            return Err(malformed("Attribute count slot is not visible"));
+        }
+
+        // Visible attributes are in slot order, so the count is followed only by issuer attributes
+        let count_index = match request.visible.len().checked_sub(self.issuer_count + 1) {
+            Some(index) => index,
+            None => return Err(malformed("Request has no attribute count")),
+        };
+        let count = (0..=self.max_attributes())
+            .find(|n| request.visible[count_index] == count_attribute(*n))
+            .ok_or_else(|| malformed("Invalid attribute count"))?;
+        if count < hidden || request.sig_req.hidden_indices.iter().any(|i| *i >= count) {
+            return Err(malformed("Hidden attributes must be among the counted attributes"));
+        }
+        let padding_start = count - hidden;
+        if padding_start > count_index || request.visible[padding_start..count_index].iter().any(|v| v != PADDING_ATTRIBUTE) {
+            return Err(malformed("Unused slots are not padded"));
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+
+    #[test]
+    fn test_pad_and_check() {
+        let params = PublicParams::new(6, "test".as_bytes(), 3, 5).with_variable_attribute_count();
+        let layout = AttributeLayout::new(&params);
+        assert_eq!(layout.max_attributes(), 5);
+
+        let padded = layout.pad(1, vec![String::from("visible")]).unwrap();
+        assert_eq!(padded.len(), 5);
+        assert_eq!(padded[1..4].to_vec(), vec![PADDING_ATTRIBUTE.to_string(); 3]);
+        assert_eq!(padded[4], count_attribute(2));
+        assert!(layout.pad(3, vec![String::from("visible"); 3]).is_err());
+
+        let mut client = Client::new(params, String::from("cheese"));
+        let request = client.request_id(vec![String::from("visible")], vec![String::from("hidden")]).unwrap();
+        assert_eq!(layout.check(&request).unwrap(), 2);
+
+        // Claiming fewer attributes than were given turns an attribute into padding
+        let mut tampered = request.clone();
+        tampered.visible[4] = count_attribute(1);
+        assert!(layout.check(&tampered).is_err());
+    }
+}
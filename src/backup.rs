@@ -0,0 +1,247 @@
+// Password based authenticated encryption for wallet backups.
+//
+// A backup is a JSON document holding a versioned header and the ciphertext. The key is derived
+// from the password with Argon2id and the payload is encrypted with ChaCha20-Poly1305. The header
+// (version and KDF parameters) is passed as associated data so it cannot be tampered with.
+
+use argon2::{Config, ThreadMode, Variant, Version};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Version of the backup file format produced by `seal`.
+pub const BACKUP_FORMAT_VERSION: u16 = 1;
+
+pub const KDF_ARGON2ID: &str = "argon2id";
+pub const CIPHER_CHACHA20POLY1305: &str = "chacha20poly1305";
+
+/// Argon2 parameters written by `KdfParams::new`. Backups asking for more are refused, so a
+/// crafted header cannot make the key derivation allocate or run without bound.
+pub const MEM_COST_KIB: u32 = 65536;
+pub const TIME_COST: u32 = 3;
+pub const LANES: u32 = 1;
+
+const KEY_LEN: u32 = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum BackupError {
+    UnsupportedVersion { version: u16 },
+    UnsupportedAlgorithm { algorithm: String },
+    KdfParamsRejected { msg: String },
+    KeyDerivation { msg: String },
+    Decryption,
+    Serialization { msg: String },
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupError::UnsupportedVersion { version } => write!(f, "Unsupported backup format version {}", version),
+            BackupError::UnsupportedAlgorithm { algorithm } => write!(f, "Unsupported algorithm {:?}", algorithm),
+            BackupError::KdfParamsRejected { msg } => write!(f, "Key derivation parameters out of range {:?}", msg),
+            BackupError::KeyDerivation { msg } => write!(f, "Key derivation failed {:?}", msg),
+            BackupError::Decryption => write!(f, "Wrong password or corrupted backup"),
+            BackupError::Serialization { msg } => write!(f, "Error with serialization {:?}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<serde_json::Error> for BackupError {
+    fn from(err: serde_json::Error) -> Self {
+        BackupError::Serialization { msg: err.to_string() }
+    }
+}
+
+/// Parameters of the memory hard key derivation function
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub mem_cost_kib: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+    pub salt: Vec<u8>,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KdfParams {
+    /// Argon2id with 64 MiB of memory and 3 passes, with a fresh random salt.
+    pub fn new() -> Self {
+        let mut salt = vec![0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        KdfParams {
+            algorithm: KDF_ARGON2ID.to_string(),
+            mem_cost_kib: MEM_COST_KIB,
+            time_cost: TIME_COST,
+            lanes: LANES,
+            salt,
+        }
+    }
+
+    /// The key is zeroized when dropped. Parameters above those of `new` are refused.
+    pub fn derive_key(&self, password: &str) -> Result<Zeroizing<Vec<u8>>, BackupError> {
+        if self.algorithm != KDF_ARGON2ID {
+            return Err(BackupError::UnsupportedAlgorithm {
+                algorithm: self.algorithm.clone(),
+            });
+        }
+        if self.mem_cost_kib > MEM_COST_KIB || self.time_cost > TIME_COST || self.lanes > LANES || self.salt.len() > SALT_LEN {
+            return Err(BackupError::KdfParamsRejected {
+                msg: format!(
+                    "{} KiB, {} passes, {} lanes and {} bytes of salt",
+                    self.mem_cost_kib, self.time_cost, self.lanes, self.salt.len()
+                ),
+            });
+        }
+        let config = Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: self.mem_cost_kib,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            thread_mode: ThreadMode::Sequential,
+            hash_length: KEY_LEN,
+            ..Config::default()
+        };
+        argon2::hash_raw(password.as_bytes(), &self.salt, &config)
+            .map(Zeroizing::new)
+            .map_err(|e| BackupError::KeyDerivation { msg: e.to_string() })
+    }
+}
+
+/// Header of an encrypted backup. Authenticated but not encrypted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupHeader {
+    pub version: u16,
+    pub cipher: String,
+    pub kdf: KdfParams,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncryptedBackup {
+    pub header: BackupHeader,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl EncryptedBackup {
+    pub fn encrypt(plaintext: &[u8], password: &str) -> Result<Self, BackupError> {
+        let header = BackupHeader {
+            version: BACKUP_FORMAT_VERSION,
+            cipher: CIPHER_CHACHA20POLY1305.to_string(),
+            kdf: KdfParams::new(),
+        };
+        let key = header.kdf.derive_key(password)?;
+        let aad = serde_json::to_vec(&header)?;
+
+        let mut nonce = vec![0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let cipher = ChaCha20Poly1305::new(key.as_slice().into());
+        let ciphertext = cipher
+            .encrypt(
+                nonce.as_slice().into(),
+                Payload { msg: plaintext, aad: &aad },
+            )
+            .map_err(|_| BackupError::Serialization {
+                msg: String::from("Encryption failed"),
+            })?;
+
+        Ok(EncryptedBackup {
+            header,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// The plaintext is zeroized when dropped.
+    pub fn decrypt(&self, password: &str) -> Result<Zeroizing<Vec<u8>>, BackupError> {
+        if self.header.version != BACKUP_FORMAT_VERSION {
+            return Err(BackupError::UnsupportedVersion {
+                version: self.header.version,
+            });
+        }
+        if self.header.cipher != CIPHER_CHACHA20POLY1305 {
+            return Err(BackupError::UnsupportedAlgorithm {
+                algorithm: self.header.cipher.clone(),
+            });
+        }
+        if self.nonce.len() != NONCE_LEN {
+            return Err(BackupError::Decryption);
+        }
+        let key = self.header.kdf.derive_key(password)?;
+        let aad = serde_json::to_vec(&self.header)?;
+
+        let cipher = ChaCha20Poly1305::new(key.as_slice().into());
+        cipher
+            .decrypt(
+                self.nonce.as_slice().into(),
+                Payload { msg: &self.ciphertext, aad: &aad },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| BackupError::Decryption)
+    }
+}
+
+/// Serialize `value` and encrypt it under `password`. Returns the backup as a JSON string.
+pub fn seal<T: Serialize>(value: &T, password: &str) -> Result<String, BackupError> {
+    let plaintext = Zeroizing::new(serde_json::to_vec(value)?);
+    let backup = EncryptedBackup::encrypt(&plaintext, password)?;
+    Ok(serde_json::to_string(&backup)?)
+}
+
+/// Decrypt a backup created with `seal` and deserialize its contents.
+pub fn open<T: DeserializeOwned>(backup: &str, password: &str) -> Result<T, BackupError> {
+    let backup: EncryptedBackup = serde_json::from_str(backup)?;
+    let plaintext = backup.decrypt(password)?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open() {
+        let secrets = vec![String::from("top"), String::from("secret")];
+        let sealed = seal(&secrets, "hunter2").unwrap();
+
+        let opened: Vec<String> = open(&sealed, "hunter2").unwrap();
+        assert_eq!(opened, secrets);
+
+        let wrong: Result<Vec<String>, BackupError> = open(&sealed, "hunter3");
+        assert_eq!(wrong.unwrap_err(), BackupError::Decryption);
+    }
+
+    #[test]
+    fn test_tampered_header() {
+        let sealed = seal(&String::from("secret"), "hunter2").unwrap();
+        let mut backup: EncryptedBackup = serde_json::from_str(&sealed).unwrap();
+        backup.header.kdf.time_cost -= 1;
+
+        assert_eq!(backup.decrypt("hunter2").err(), Some(BackupError::Decryption));
+
+        // A header asking for more memory than `KdfParams::new` is refused before deriving
+        backup.header.kdf.mem_cost_kib = 1 << 24;
+        assert!(matches!(backup.decrypt("hunter2").err(), Some(BackupError::KdfParamsRejected { .. })));
+        backup.header.kdf.mem_cost_kib = MEM_COST_KIB;
+
+        backup.header.version = BACKUP_FORMAT_VERSION + 1;
+        assert_eq!(
+            backup.decrypt("hunter2").err(),
+            Some(BackupError::UnsupportedVersion { version: BACKUP_FORMAT_VERSION + 1 })
+        );
+    }
+}
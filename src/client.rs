@@ -1,19 +1,39 @@
 use amcl_wrapper::field_elem::{FieldElement};
+use amcl_wrapper::group_elem::GroupElement;
 use amcl_wrapper::group_elem_g2::G2;
 use coconut_sig::elgamal_keygen;
-use coconut_sig::signature::{Signature, Verkey, transform_to_PS_verkey, transform_to_PS_sig, transform_to_PS_params};
+use coconut_sig::errors::{CoconutError, CoconutErrorKind};
+use coconut_sig::secret::SecretFieldElement;
+use coconut_sig::validation::{check_element, Validate};
+use coconut_sig::signature::{
+    BlindSignature, Params as CParams, Signature, SignatureRequest, SignatureRequestPoK, SignatureRequestProof, Verkey, known_indices, transform_to_PS_verkey,
+    transform_to_PS_sig, transform_to_PS_params,
+};
 use ps_sig::keys::Verkey as PSVerkey;
 use ps_sig::signature as PSSignature;
-use std::collections::HashSet;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use zeroize::Zeroize;
 
+use crate::attribute_count::AttributeLayout;
+use crate::backup::{self, BackupError};
+use crate::errors::ThesisError;
+use crate::gate::{GateCredential, GatePresentation};
+use crate::identity_tag::tag_base;
+use crate::issuer_attributes::IssuerAttributes;
 use crate::js_pok_sig::{JSPoKOfSignature, JSPoKOfSignatureProof};
-use crate::js_signature::{JSBlindSignature, JSMessages, JSSignatureRequest, JSSignatureRequestPoK, JSSignatureRequestProof};
+use crate::js_signature::JSMessages;
 
 use crate::js::Public as PublicParams;
+use crate::one_show::{OneShowSpec, OneShowTagPoK};
+use crate::rate_limit::{RateLimit, RateLimitTagPoK};
+use crate::renewal::RenewalRequest;
+use crate::utils::{now_secs, random_id};
+use crate::verkey_distribution::verkey_id;
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct ElGamalKeys {
-    sk: FieldElement,
+    sk: SecretFieldElement,
     pub pk: G2,
 }
 
@@ -21,89 +41,659 @@ impl ElGamalKeys {
     pub fn new (param: &G2) -> Self {
         let (sk, pk) = elgamal_keygen!(param);
         ElGamalKeys {
-            sk,
+            sk: SecretFieldElement::new(sk),
             pk
         }
     }
+
+    /// Overwrite the secret key in place. The key is also zeroized when the keys are dropped.
+    pub fn zeroize (&mut self) {
+        self.sk.zeroize();
+    }
 }
 
+/// Request for a credential. Carries everything an IdP needs to verify the proof of knowledge
+/// of the request before signing.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ClientIdRequest {
-    pub sig_req: JSSignatureRequest, 
-    pub sig_req_proof: JSSignatureRequestProof,
+    /// Unique id of this request. A retry resends the identical request with the same id.
+    pub request_id: String,
+    pub session_id: String,
+    /// Fingerprint of the public parameters the request was made under
+    pub params_fingerprint: String,
+    pub elg_pk: G2,
+    /// Epoch the issuer attributes were derived for, if the deployment uses them
+    pub issuance_epoch: Option<u64>,
+    pub credential_type: String,
+    /// Plaintext of the visible attributes, for attestation by the IdPs
+    pub visible: Vec<String>,
+    /// Evidence for the IdPs' attribute verifiers, keyed by verifier name
+    pub evidence: BTreeMap<String, String>,
+    /// Identity tag computed from the first hidden message, if the deployment uses them
+    pub tag: Option<G2>,
+    pub sig_req: SignatureRequest,
+    pub sig_req_proof: SignatureRequestProof,
+    /// Presentation of a credential of another issuer, for IdPs that require one
+    #[serde(default)]
+    pub presentation: Option<GatePresentation>,
+}
+
+impl ClientIdRequest {
+    /// Bytes hashed to get the challenge of the request proof. The session id is included so a
+    /// proof cannot be replayed in another session, the params fingerprint so it only verifies
+    /// under the parameters it was made for. `cparams` are the params of the request's type.
+    pub fn get_bytes_for_challenge (&self, params: &PublicParams, cparams: &CParams) -> Vec<u8> {
+        let mut bytes = self.sig_req_proof.get_bytes_for_challenge(&self.sig_req, &self.elg_pk, cparams);
+        if let Some(base) = self.tag_base(params) {
+            bytes.append(&mut self.sig_req_proof.get_tag_bytes_for_challenge(&base));
+        }
+        if let Some(tag) = &self.tag {
+            bytes.append(&mut tag.to_bytes());
+        }
+        bytes.extend_from_slice(self.session_id.as_bytes());
+        bytes.extend_from_slice(params.fingerprint().as_bytes());
+        bytes
+    }
+
+    /// Base of the identity tag of this request, if the deployment uses identity tags
+    pub fn tag_base (&self, params: &PublicParams) -> Option<G2> {
+        params
+            .tag_scope
+            .as_ref()
+            .map(|scope| tag_base(scope, &self.credential_type))
+    }
+
+    /// The identity tag as hex, as kept in tag registries
+    pub fn tag_hex (&self) -> Option<String> {
+        self.tag.as_ref().map(|tag| tag.to_hex())
+    }
+
+    /// Digest of the commitment, ciphertexts, ElGamal public key and visible attributes, hex
+    /// encoded. Tag registries keep it with the tag so that no other request is signed for it.
+    pub fn tag_digest (&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.sig_req.commitment.to_bytes());
+        for (c_1, c_2) in &self.sig_req.ciphertexts {
+            hasher.update(c_1.to_bytes());
+            hasher.update(c_2.to_bytes());
+        }
+        hasher.update(self.elg_pk.to_bytes());
+        for v in &self.visible {
+            hasher.update((v.len() as u64).to_be_bytes());
+            hasher.update(v.as_bytes());
+        }
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Check that the plaintext visible attributes are the known messages of the request
+    pub fn check_visible (&self) -> bool {
+        let known = &self.sig_req.known_messages;
+        known.len() == self.visible.len()
+            && self.visible
+                .iter()
+                .enumerate()
+                .all(|(i, v)| FieldElement::from_msg_hash(v.as_bytes()) == known[i])
+    }
+
+    /// Verify the proof of knowledge of the hidden messages and the ElGamal secret key, under the
+    /// params of the request's credential type
+    pub fn verify (&self, params: &PublicParams) -> Result<(), CoconutError> {
+        let cparams = params.type_params(&self.credential_type)?;
+        let challenge = FieldElement::from_msg_hash(&self.get_bytes_for_challenge(params, &cparams));
+        self.verify_with_challenge(params, &cparams, &challenge)
+    }
+
+    /// Like `verify` with the given challenge, for requests proven together with other
+    /// statements under one challenge. `cparams` are the params of the request's type.
+    pub fn verify_with_challenge (&self, params: &PublicParams, cparams: &CParams, challenge: &FieldElement) -> Result<(), CoconutError> {
+        let tag_valid = match (self.tag_base(params), &self.tag) {
+            (Some(base), Some(tag)) => self.sig_req_proof.verify_tag(&base, tag, challenge)?,
+            (None, None) => true,
+            _ => false,
+        };
+        if tag_valid && self.sig_req_proof.verify(&self.sig_req, &self.elg_pk, challenge, cparams)? {
+            Ok(())
+        } else {
+            Err(CoconutErrorKind::GeneralError {
+                msg: String::from("Signature request proof failed verification"),
+            }.into())
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ServerBlindSignature {
     pub id: usize,
-    pub blind_sig: JSBlindSignature,
+    pub session_id: String,
+    /// Key id of the generation of the signing key
+    pub key_id: String,
+    pub params_fingerprint: String,
+    pub blind_sig: BlindSignature,
     pub vk_share: Verkey,
+    /// Attributes this IdP added to the credential
+    pub issuer_attributes: Option<IssuerAttributes>,
+}
+
+impl Validate for ClientIdRequest {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.elg_pk, "ElGamal public key")?;
+        self.tag.validate()?;
+        self.sig_req.validate()?;
+        self.sig_req_proof.validate()?;
+        match &self.presentation {
+            Some(presentation) => presentation.proof.validate(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Validate for ServerBlindSignature {
+    fn validate(&self) -> Result<(), CoconutError> {
+        self.blind_sig.validate()?;
+        self.vk_share.validate()
+    }
 }
 
+/// Optional parts of a credential request
+#[derive(Clone, Debug, Default)]
+pub struct RequestOptions {
+    /// Type of the credential. If the deployment has a credential type registry the attributes
+    /// must follow the type's schema, which also decides the hidden slots.
+    pub credential_type: String,
+    /// Evidence for the IdPs' attribute verifiers, keyed by verifier name
+    pub evidence: BTreeMap<String, String>,
+    /// Slots of the hidden messages in increasing order. By default they take the first slots.
+    pub hidden_indices: Option<Vec<usize>>,
+    /// Credential of another issuer to present, for IdPs that require one
+    pub presentation: Option<GateCredential>,
+}
+
+/// State of a single issuance. The ElGamal keys are generated for this session only so that
+/// requests from the same client cannot be linked through the ElGamal public key. The secret key
+/// is zeroized when the session is dropped, which happens once the blind signatures have been
+/// unblinded.
+#[derive(Debug)]
+pub struct IssuanceSession {
+    id: String,
+    elg_keys: ElGamalKeys,
+    messages: JSMessages,
+    issuance_epoch: Option<u64>,
+    issuer_attributes: Option<IssuerAttributes>,
+    /// Registered type of the credential, if the deployment has a credential type registry
+    credential_type: Option<String>,
+}
+
+impl IssuanceSession {
+    /// Start a session for the given attributes of type `credential_type`. If the deployment has
+    /// a credential type registry, the attributes must follow the type's schema and the type
+    /// attribute is appended to them. If it allows a variable number of attributes, the padding
+    /// and attribute count are appended to the visible messages. If it uses issuer attributes
+    /// they are derived for the current epoch and appended after that. The hidden messages can
+    /// only take the slots of the given attributes.
+    pub fn new (
+        hidden_messages: Vec<String>,
+        mut visible_messages: Vec<String>,
+        mut hidden_indices: Option<Vec<usize>>,
+        credential_type: &str,
+        params: &PublicParams,
+    ) -> Result<Self, ThesisError> {
+        let registered = match &params.credential_types {
+            Some(registry) => {
+                let t = registry.get(credential_type).ok_or_else(|| ThesisError::InvalidInput {
+                    msg: format!("Unknown credential type {:?}", credential_type),
+                })?;
+                if hidden_indices.as_ref().is_some_and(|indices| *indices != t.hidden_indices()) {
+                    return Err(ThesisError::InvalidInput {
+                        msg: format!("Type {} decides the hidden slots", t.id),
+                    });
+                }
+                visible_messages = t
+                    .append_type_attribute(hidden_messages.len(), visible_messages)
+                    .map_err(|msg| ThesisError::InvalidInput { msg })?;
+                hidden_indices = Some(t.hidden_indices());
+                Some(t.id.clone())
+            }
+            None => None,
+        };
+        let id = random_id();
+        let elg_keys = ElGamalKeys::new(&params.cparams.g);
+        let (issuance_epoch, issuer_attributes) = match &params.issuer_attributes {
+            Some(spec) => {
+                let epoch = spec.epoch_at(now_secs());
+                (Some(epoch), Some(spec.derive(epoch, &id, &elg_keys.pk)?))
+            }
+            None => (None, None),
+        };
+        // The client's own attributes come first, the padding, count and issuer attributes after
+        let attribute_count = hidden_messages.len() + visible_messages.len();
+        if params.variable_attribute_count {
+            visible_messages = AttributeLayout::new(params)
+                .pad(hidden_messages.len(), visible_messages)
+                .map_err(|msg| ThesisError::InvalidInput { msg })?;
+        }
+        if let Some(attrs) = &issuer_attributes {
+            visible_messages.append(&mut attrs.to_strings());
+        }
+        let messages = match hidden_indices {
+            Some(indices) => {
+                if indices.iter().any(|i| *i >= attribute_count) {
+                    return Err(ThesisError::InvalidInput {
+                        msg: String::from("Hidden messages must take the slots of the client's attributes"),
+                    });
+                }
+                JSMessages::with_hidden_indices(hidden_messages, visible_messages, indices)?
+            }
+            None => JSMessages::new(hidden_messages, visible_messages),
+        };
+        Ok(IssuanceSession {
+            id,
+            elg_keys,
+            messages,
+            issuance_epoch,
+            issuer_attributes,
+            credential_type: registered,
+        })
+    }
+
+    pub fn id (&self) -> &str {
+        &self.id
+    }
+
+    pub fn elgamal_pk (&self) -> &G2 {
+        &self.elg_keys.pk
+    }
+
+    pub fn messages (&self) -> &JSMessages {
+        &self.messages
+    }
+
+    pub fn issuer_attributes (&self) -> Option<&IssuerAttributes> {
+        self.issuer_attributes.as_ref()
+    }
+}
 
+/// Verkey shares and unblinded signatures of one key generation, with their signer ids
+type GenerationShares = (Vec<(usize, Verkey)>, Vec<(usize, Signature)>);
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Client {
     public: PublicParams,
-    secret: FieldElement,
-    pub elg_keys: ElGamalKeys,
+    secret: SecretFieldElement,
+    session: Option<IssuanceSession>,
     credential: Option<PSSignature::Signature>,
     ps_verkey: Option<PSVerkey>,
+    messages: Option<JSMessages>,
+    issuer_attributes: Option<IssuerAttributes>,
+    credential_type: Option<String>,
+    /// Key ids of the generations the client accepts credentials of, if restricted
+    known_key_ids: Option<HashSet<String>>,
+}
+
+/// Pending issuance session in an encrypted client backup
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SessionBackup {
+    id: String,
+    elg_sk: SecretFieldElement,
+    elg_pk: G2,
+    messages: JSMessages,
+    issuance_epoch: Option<u64>,
+    issuer_attributes: Option<IssuerAttributes>,
+    #[serde(default)]
+    credential_type: Option<String>,
+}
+
+/// Contents of an encrypted client backup
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ClientBackup {
+    secret: SecretFieldElement,
+    session: Option<SessionBackup>,
+    credential: Option<PSSignature::Signature>,
+    ps_verkey: Option<PSVerkey>,
+    messages: Option<JSMessages>,
+    issuer_attributes: Option<IssuerAttributes>,
+    #[serde(default)]
+    credential_type: Option<String>,
 }
 
 impl Client {
+    /// Client without a credential. `user_secret` is kept in backups; the link secret of identity
+    /// and rate limit tags is one of the hidden attributes passed with a request, by default the first.
     pub fn new (public: PublicParams, user_secret: String) -> Self {
-        let elg_keys = ElGamalKeys::new(&public.cparams.g);
         Client {
             public,
-            secret: FieldElement::from_msg_hash(user_secret.as_bytes()),
-            elg_keys,
+            secret: SecretFieldElement::new(FieldElement::from_msg_hash(user_secret.as_bytes())),
+            session: None,
             credential: None,
             ps_verkey: None,
+            messages: None,
+            issuer_attributes: None,
+            credential_type: None,
+            known_key_ids: None,
+        }
+    }
+
+    /// Only aggregate signatures of the key generations with these key ids, e.g. the ones pinned
+    /// by the RPs. Needed while several generations are in use.
+    pub fn with_known_key_ids (mut self, key_ids: Vec<String>) -> Self {
+        self.known_key_ids = Some(key_ids.into_iter().collect());
+        self
+    }
+
+    /// Encrypt the client secret, any pending issuance session and any stored credential with
+    /// its attributes under `password`.
+    pub fn export (&self, password: &str) -> Result<String, BackupError> {
+        let contents = ClientBackup {
+            secret: self.secret.clone(),
+            session: self.session.as_ref().map(|s| SessionBackup {
+                id: s.id.clone(),
+                elg_sk: s.elg_keys.sk.clone(),
+                elg_pk: s.elg_keys.pk.clone(),
+                messages: s.messages.clone(),
+                issuance_epoch: s.issuance_epoch,
+                issuer_attributes: s.issuer_attributes.clone(),
+                credential_type: s.credential_type.clone(),
+            }),
+            credential: self.credential.clone(),
+            ps_verkey: self.ps_verkey.clone(),
+            messages: self.messages.clone(),
+            issuer_attributes: self.issuer_attributes.clone(),
+            credential_type: self.credential_type.clone(),
+        };
+        backup::seal(&contents, password)
+    }
+
+    /// Restore a client from a backup created with `export`.
+    pub fn import (public: PublicParams, data: &str, password: &str) -> Result<Self, BackupError> {
+        let contents: ClientBackup = backup::open(data, password)?;
+        Ok(Client {
+            public,
+            secret: contents.secret,
+            session: contents.session.map(|s| IssuanceSession {
+                id: s.id,
+                elg_keys: ElGamalKeys {
+                    sk: s.elg_sk,
+                    pk: s.elg_pk,
+                },
+                messages: s.messages,
+                issuance_epoch: s.issuance_epoch,
+                issuer_attributes: s.issuer_attributes,
+                credential_type: s.credential_type,
+            }),
+            credential: contents.credential,
+            ps_verkey: contents.ps_verkey,
+            messages: contents.messages,
+            issuer_attributes: contents.issuer_attributes,
+            credential_type: contents.credential_type,
+            known_key_ids: None,
+        })
+    }
+
+    /// Attributes of the stored credential
+    pub fn messages (&self) -> Option<&JSMessages> {
+        self.messages.as_ref()
+    }
+
+    /// Attributes the IdPs added to the stored credential
+    pub fn issuer_attributes (&self) -> Option<&IssuerAttributes> {
+        self.issuer_attributes.as_ref()
+    }
+
+    /// The pending issuance session, if a request has been made and not yet unblinded
+    pub fn session (&self) -> Option<&IssuanceSession> {
+        self.session.as_ref()
+    }
+
+    /// Start a new issuance session with fresh ElGamal keys and create the request for it.
+    /// Any previously pending session is discarded.
+    pub fn request_id (&mut self, visible_messages: Vec<String>, hidden_messages: Vec<String>) -> Result<ClientIdRequest, ThesisError> {
+        self.request_id_with_options(visible_messages, hidden_messages, RequestOptions::default())
+    }
+
+    /// Like `request_id`, also setting the credential type, evidence for the IdPs' attribute
+    /// verifiers and a credential of another issuer to present
+    pub fn request_id_with_options (
+        &mut self,
+        visible_messages: Vec<String>,
+        hidden_messages: Vec<String>,
+        options: RequestOptions,
+    ) -> Result<ClientIdRequest, ThesisError> {
+        let session = IssuanceSession::new(
+            hidden_messages,
+            visible_messages,
+            options.hidden_indices.clone(),
+            &options.credential_type,
+            &self.public,
+        )?;
+        let gate = options.presentation.clone();
+        let presentation = match &gate {
+            Some(gate) => Some(gate.init(&session)?),
+            None => None,
+        };
+        let (mut request, proof) = self.request_for_session(session, options, presentation)?;
+        if let (Some(gate), Some(proof)) = (gate, proof) {
+            gate.attach(&mut request, proof);
         }
+        Ok(request)
+    }
+
+    /// Start a session for a fresh credential with the hidden attributes of the stored credential
+    /// and the new `visible_messages`, for instance with a new address, and create the renewal
+    /// request for it. The hidden attributes keep their slots and a typed credential keeps its
+    /// type. The request proves possession of the stored credential, revealing its visible
+    /// attributes only, and that its hidden attributes are the ones of the new request.
+    pub fn request_renewal (&mut self, visible_messages: Vec<String>, mut options: RequestOptions) -> Result<RenewalRequest, ThesisError> {
+        let (credential, verkey, current) = match (&self.credential, &self.ps_verkey, &self.messages) {
+            (Some(credential), Some(verkey), Some(messages)) => (credential.clone(), verkey.clone(), messages.clone()),
+            _ => return Err(ThesisError::InvalidState {
+                msg: String::from("No credential"),
+            }),
+        };
+        if let Some(credential_type) = &self.credential_type {
+            options.credential_type = credential_type.clone();
+        }
+        options.hidden_indices = Some(current.hidden_indices.clone());
+        let session = IssuanceSession::new(
+            current.hidden.clone(),
+            visible_messages,
+            options.hidden_indices.clone(),
+            &options.credential_type,
+            &self.public,
+        )?;
+
+        // The hidden attributes get the same blindings in both proofs
+        let blindings = (0..current.hidden_count()).map(|_| FieldElement::random()).collect::<Vec<FieldElement>>();
+        let revealed = known_indices(&current.hidden_indices, current.count()).into_iter().collect::<HashSet<usize>>();
+        let possession = JSPoKOfSignature::init(
+            &credential,
+            &verkey,
+            &transform_to_PS_params(&self.public.cparams),
+            current.all.as_slice().to_vec(),
+            Some(&blindings),
+            revealed,
+            &session.id,
+        )?;
+
+        let (request, possession) = self.request_for_session(session, options, Some((possession, blindings)))?;
+        let mut possession = possession.ok_or_else(|| ThesisError::InvalidState {
+            msg: String::from("No proof of possession"),
+        })?;
+        possession.key_id = Some(verkey_id(&verkey));
+        Ok(RenewalRequest {
+            request,
+            possession,
+            current_hidden_indices: current.hidden_indices.clone(),
+            current_visible: current.visible.clone(),
+        })
     }
 
-    pub fn request_id (&self, visible_messages: Vec<String>, hidden_messages: Vec<String>) -> ClientIdRequest {
-        let messages = JSMessages::new(visible_messages, hidden_messages);
-        let (sig_req, randomness) = JSSignatureRequest::new(messages.clone(), &self.elg_keys.pk, &self.public.cparams);
+    /// Create the request for `session` and make it the pending session. A proof of possession,
+    /// with the blindings of the hidden messages, is finished under the challenge of the request.
+    fn request_for_session (
+        &mut self,
+        session: IssuanceSession,
+        options: RequestOptions,
+        possession: Option<(JSPoKOfSignature, Vec<FieldElement>)>,
+    ) -> Result<(ClientIdRequest, Option<JSPoKOfSignatureProof>), ThesisError> {
+        let messages = session.messages.clone();
+        let cparams = self.public.type_params(&options.credential_type)?;
+        let (sig_req, randomness) = messages.signature_request(&session.elg_keys.pk, &cparams)?;
+
+        // The first hidden message is the link secret of the identity tag
+        let base = self
+            .public
+            .tag_scope
+            .as_ref()
+            .map(|scope| tag_base(scope, &options.credential_type));
 
         // Initiate proof of knowledge of various items of Signature request
-        let sig_req_pok = JSSignatureRequestPoK::init(&sig_req,&self.elg_keys.pk, &self.public.cparams);
+        let sig_req_pok = SignatureRequestPoK::init_with_blindings(
+            &sig_req,
+            &session.elg_keys.pk,
+            base.as_ref(),
+            possession.as_ref().map(|(_, blindings)| blindings.as_slice()),
+            &cparams,
+        )?;
+        let tag = base.as_ref().map(|base| base * &messages.hashed_hidden[0]);
 
-        // The challenge can include other things also (if proving other predicates)
-        let challenge_for_prover = FieldElement::from_msg_hash(&sig_req_pok.to_bytes());
+        // The challenge also covers the tag, the session id and the proof of possession
+        let mut challenge_bytes = sig_req_pok.to_bytes();
+        if let Some(tag) = &tag {
+            challenge_bytes.append(&mut tag.to_bytes());
+        }
+        challenge_bytes.extend_from_slice(session.id.as_bytes());
+        challenge_bytes.extend_from_slice(self.public.fingerprint().as_bytes());
+        if let Some((pok, _)) = &possession {
+            challenge_bytes.append(&mut pok.to_bytes());
+        }
+        let challenge_for_prover = FieldElement::from_msg_hash(&challenge_bytes);
 
         // Create proof once the challenge is finalized
         let sig_req_proof = sig_req_pok
-            .gen_proof(&messages.hashed_hidden, randomness, &self.elg_keys.sk, &challenge_for_prover)
-            .unwrap();
+            .gen_proof(&messages.hashed_hidden, randomness, session.elg_keys.sk.expose_secret(), &challenge_for_prover)?;
+        let possession_proof = match possession {
+            Some((pok, _)) => Some(pok.gen_proof(&challenge_for_prover)?),
+            None => None,
+        };
 
-        ClientIdRequest {
+        let request = ClientIdRequest {
+            request_id: random_id(),
+            session_id: session.id.clone(),
+            params_fingerprint: self.public.fingerprint(),
+            elg_pk: session.elg_keys.pk.clone(),
+            issuance_epoch: session.issuance_epoch,
+            credential_type: options.credential_type,
+            visible: messages.visible.clone(),
+            evidence: options.evidence,
+            tag,
             sig_req,
             sig_req_proof,
-        }
+            presentation: None,
+        };
+        self.session = Some(session);
+
+        Ok((request, possession_proof))
     }
 
-    pub fn verify_signatures (&mut self, mut blinded_sigs: Vec<ServerBlindSignature>) {
-        let mut unblinded_sigs = vec![];
-        let mut vk_pairs: Vec<(usize, Verkey)> = vec![];
-        for _i in 0..self.public.threshold {
-            let sbs = blinded_sigs.remove(0);
-            vk_pairs.push((sbs.id, sbs.vk_share.clone()));
+    /// Unblind and aggregate the signatures of the pending session. Each unblinded signature is
+    /// checked against the signer's verkey share over the expected messages, including the issuer
+    /// attributes. Signatures issued for other sessions, with unexpected issuer attributes or by a
+    /// signer id outside `1..=server_count` are ignored, as are signatures of IdPs whose verkey
+    /// share is inconsistent with the others. While keys are rotated the IdPs may sign with
+    /// different key generations. A generation is only aggregated if its key has the generation's
+    /// key id and it is the only generation with a threshold of valid signatures, or the only known
+    /// one (see `with_known_key_ids`). Once a threshold of valid signatures is aggregated the
+    /// session and its ElGamal keys are destroyed.
+    pub fn verify_signatures (&mut self, blinded_sigs: Vec<ServerBlindSignature>) -> Result<(), ThesisError> {
+        let threshold = self.public.threshold;
+        let session = self.session.as_ref().ok_or_else(|| ThesisError::InvalidState {
+            msg: String::from("No pending issuance session"),
+        })?;
+        let all_messages = session.messages.all.as_slice().to_vec();
+        let fingerprint = self.public.fingerprint();
+
+        // Valid shares by key id
+        let mut generations: BTreeMap<String, GenerationShares> = BTreeMap::new();
+        for sbs in blinded_sigs {
+            // Ids are bounded so a generation has at most `server_count` shares to check
+            if sbs.id == 0
+                || sbs.id > self.public.server_count
+                || sbs.session_id != session.id
+                || sbs.params_fingerprint != fingerprint
+                || sbs.validate().is_err()
+                || sbs.issuer_attributes != session.issuer_attributes
+                || sbs.vk_share.Y_tilde.len() != all_messages.len()
+            {
+                continue;
+            }
 
             // unblind signature
-            let unblinded_sig = sbs.blind_sig.unblind(&self.elg_keys.sk);
+            let unblinded_sig = sbs.blind_sig.unblind(session.elg_keys.sk.expose_secret());
+            if !unblinded_sig.verify(all_messages.clone(), &sbs.vk_share, &self.public.cparams) {
+                continue;
+            }
+            let signer_id = sbs.id;
+            let (vk_pairs, unblinded_sigs) = generations.entry(sbs.key_id).or_default();
+            // A response passed twice, e.g. a cached retry, counts once
+            if vk_pairs.iter().any(|(id, _)| *id == signer_id) {
+                continue;
+            }
+            vk_pairs.push((sbs.id, sbs.vk_share));
             unblinded_sigs.push((sbs.id, unblinded_sig));
         }
 
-        let aggr_sig = transform_to_PS_sig(&Signature::aggregate(self.public.threshold, unblinded_sigs));
-        self.credential = Some(aggr_sig);
+        // Shares of different key generations cannot be aggregated. A single responder can sign
+        // under made up signer ids and key id, so the number of shares does not tell which
+        // generation is genuine. Verkey shares must lie on one polynomial so a poisoned share is
+        // never aggregated.
+        let mut candidates: Vec<(PSVerkey, Vec<(usize, Signature)>)> = vec![];
+        let mut most_valid = 0;
+        for (key_id, (mut vk_pairs, mut unblinded_sigs)) in generations {
+            if self.known_key_ids.as_ref().is_some_and(|known| !known.contains(&key_id)) {
+                continue;
+            }
+            let inconsistent = Verkey::inconsistent_shares(
+                threshold,
+                &vk_pairs.iter().map(|(id, vk)| (*id, vk)).collect::<Vec<(usize, &Verkey)>>(),
+            );
+            vk_pairs.retain(|(id, _)| !inconsistent.contains(id));
+            unblinded_sigs.retain(|(id, _)| !inconsistent.contains(id));
+            most_valid = most_valid.max(unblinded_sigs.len());
+            if threshold == 0 || unblinded_sigs.len() < threshold {
+                continue;
+            }
+            let aggr_vk = match Verkey::checked_aggregate(threshold, vk_pairs.iter().map(|(id, vk)| (*id, vk)).collect()) {
+                Ok(aggr_vk) => transform_to_PS_verkey(&aggr_vk),
+                Err(_) => continue,
+            };
+            if verkey_id(&aggr_vk) == key_id {
+                candidates.push((aggr_vk, unblinded_sigs));
+            }
+        }
+        if candidates.len() > 1 {
+            return Err(ThesisError::VerkeyRejected {
+                msg: String::from("Signatures of several key generations, the known key ids must pick one"),
+            });
+        }
+        let (aggr_vk, unblinded_sigs) = candidates.pop().ok_or(ThesisError::NotEnoughSignatures {
+            threshold,
+            valid: most_valid,
+        })?;
+        let aggr_sig = transform_to_PS_sig(&Signature::aggregate(threshold, unblinded_sigs));
 
-        let aggr_vk = transform_to_PS_verkey(&Verkey::owned_aggregate(
-            self.public.threshold,
-            vk_pairs
-        ));
+        let session = self.session.take().ok_or_else(|| ThesisError::InvalidState {
+            msg: String::from("No pending issuance session"),
+        })?;
+        self.credential = Some(aggr_sig);
+        self.messages = Some(session.messages.clone());
+        self.issuer_attributes = session.issuer_attributes.clone();
+        self.credential_type = session.credential_type.clone();
         self.ps_verkey = Some(aggr_vk);
+        Ok(())
+    }
+
+    /// Key id of the generation of keys the stored credential was issued with
+    pub fn key_id (&self) -> Option<String> {
+        self.ps_verkey.as_ref().map(verkey_id)
     }
 
     pub fn offer_ps_verkey (&self) -> Option<PSVerkey> {
@@ -117,34 +707,175 @@ impl Client {
         self
     }
 
+    /// The stored credential, to present with a request to IdPs of another deployment that require
+    /// it. `revealed` are the slots revealed to them, `carried` the hidden attributes carried over
+    /// as pairs of the slot in this credential and in the new one. A typed credential always
+    /// reveals its type attribute.
+    pub fn gate_credential (&self, mut revealed: HashSet<usize>, carried: Vec<(usize, usize)>) -> Result<GateCredential, ThesisError> {
+        let (credential, vk, messages) = match (&self.credential, &self.ps_verkey, &self.messages) {
+            (Some(credential), Some(vk), Some(messages)) => (credential.clone(), vk.clone(), messages.clone()),
+            _ => return Err(ThesisError::InvalidState {
+                msg: String::from("No credential"),
+            }),
+        };
+        if let (Some(registry), Some(t)) = (&self.public.credential_types, &self.credential_type) {
+            if let Some((slot, _)) = registry.revealed_type_attribute(t) {
+                revealed.insert(slot);
+            }
+        }
+        Ok(GateCredential {
+            credential,
+            vk,
+            params: transform_to_PS_params(&self.public.cparams),
+            messages,
+            revealed,
+            carried,
+        })
+    }
+
+    /// Prove possession of the credential to the RP of `domain`. A typed credential always
+    /// reveals its type attribute.
     pub fn prove_id (&self,
         messages: JSMessages,
         revealed_msg_indices: HashSet<usize>,
         domain: &String,
-    ) -> Option<JSPoKOfSignatureProof> {
-        if let Some(verkey) = &self.ps_verkey {
-            let pok = JSPoKOfSignature::init(
-                self.credential.as_ref().unwrap(),
-                verkey,
-                &transform_to_PS_params(&self.public.cparams),
-                messages.all.as_slice().to_vec().clone(),
-                None,
-                revealed_msg_indices,
+    ) -> Result<JSPoKOfSignatureProof, ThesisError> {
+        self.prove_id_with_tags(messages, revealed_msg_indices, domain, None, None)
+    }
+
+    /// Like `prove_id` for an RP with rate limit `limit`, attaching the tag of the current epoch
+    /// for `counter`. Each counter below the limit can be used once per epoch.
+    pub fn prove_id_limited (&self,
+        messages: JSMessages,
+        revealed_msg_indices: HashSet<usize>,
+        domain: &String,
+        limit: &RateLimit,
+        counter: usize,
+    ) -> Result<JSPoKOfSignatureProof, ThesisError> {
+        self.prove_id_with_tags(messages, revealed_msg_indices, domain, Some((limit, counter)), None)
+    }
+
+    /// Like `prove_id` showing a one-show credential of `spec`. Showing it a second time in the
+    /// scope reveals the identity attribute.
+    pub fn prove_one_show (&self,
+        messages: JSMessages,
+        revealed_msg_indices: HashSet<usize>,
+        domain: &String,
+        spec: &OneShowSpec,
+    ) -> Result<JSPoKOfSignatureProof, ThesisError> {
+        self.prove_id_with_tags(messages, revealed_msg_indices, domain, None, Some(spec))
+    }
+
+    /// Like `prove_one_show` for an RP that also has rate limit `limit`
+    pub fn prove_one_show_limited (&self,
+        messages: JSMessages,
+        revealed_msg_indices: HashSet<usize>,
+        domain: &String,
+        spec: &OneShowSpec,
+        limit: &RateLimit,
+        counter: usize,
+    ) -> Result<JSPoKOfSignatureProof, ThesisError> {
+        self.prove_id_with_tags(messages, revealed_msg_indices, domain, Some((limit, counter)), Some(spec))
+    }
+
+    fn prove_id_with_tags (&self,
+        messages: JSMessages,
+        mut revealed_msg_indices: HashSet<usize>,
+        domain: &String,
+        rate_limit: Option<(&RateLimit, usize)>,
+        one_show: Option<&OneShowSpec>,
+    ) -> Result<JSPoKOfSignatureProof, ThesisError> {
+        let (credential, verkey) = match (&self.credential, &self.ps_verkey) {
+            (Some(credential), Some(verkey)) => (credential, verkey),
+            _ => return Err(ThesisError::InvalidState {
+                msg: String::from("No credential"),
+            }),
+        };
+        if let (Some(registry), Some(t)) = (&self.public.credential_types, &self.credential_type) {
+            let (slot, _) = registry.revealed_type_attribute(t).ok_or_else(|| ThesisError::InvalidState {
+                msg: format!("Credential of unknown type {:?}", t),
+            })?;
+            revealed_msg_indices.insert(slot);
+        }
+
+        // Hidden messages in tags get the same blinding in the proof of possession and of the tag
+        let mut tag_slots = vec![];
+        if let Some((limit, _)) = rate_limit {
+            tag_slots.push(limit.secret_slot);
+        }
+        if let Some(spec) = one_show {
+            spec.check_slots()?;
+            tag_slots.extend_from_slice(&[spec.identity_slot, spec.serial_slot]);
+        }
+        if tag_slots.iter().any(|i| *i >= messages.count() || revealed_msg_indices.contains(i)) {
+            return Err(ThesisError::InvalidInput {
+                msg: String::from("Messages in tags must be hidden"),
+            });
+        }
+        let blindings = (0..messages.count())
+            .filter(|i| !revealed_msg_indices.contains(i))
+            .map(|_| FieldElement::random())
+            .collect::<Vec<FieldElement>>();
+        let blinding = |slot: usize| &blindings[(0..slot).filter(|i| !revealed_msg_indices.contains(i)).count()];
+
+        let tag_pok = match rate_limit {
+            Some((limit, counter)) => Some(RateLimitTagPoK::init(
+                limit,
                 domain,
-            )
-            .expect("Could not initialize proof of knowledge.");
-    
-            let chal = FieldElement::from_msg_hash(&pok.to_bytes());
-            pok.gen_proof(&chal).ok()
-        } else {
-            None
+                limit.epoch_at(now_secs()),
+                &messages.all[limit.secret_slot],
+                blinding(limit.secret_slot),
+                counter,
+            )?),
+            None => None,
+        };
+        let pok = JSPoKOfSignature::init(
+            credential,
+            verkey,
+            &transform_to_PS_params(&self.public.cparams),
+            messages.all.as_slice().to_vec().clone(),
+            Some(&blindings),
+            revealed_msg_indices.clone(),
+            domain,
+        )?;
+        // The double-spend equation depends on the randomized signature
+        let one_show_pok = one_show.map(|spec| OneShowTagPoK::init(
+            spec,
+            &pok.sig,
+            domain,
+            &messages.all[spec.identity_slot],
+            blinding(spec.identity_slot),
+            &messages.all[spec.serial_slot],
+            blinding(spec.serial_slot),
+        ));
+
+        // The challenge covers the params fingerprint so the proof only verifies under these params
+        let mut chal_bytes = pok.to_bytes();
+        chal_bytes.extend_from_slice(self.public.fingerprint().as_bytes());
+        if let Some(tag_pok) = &tag_pok {
+            chal_bytes.append(&mut tag_pok.to_bytes());
         }
+        if let Some(one_show_pok) = &one_show_pok {
+            chal_bytes.append(&mut one_show_pok.to_bytes());
+        }
+        let chal = FieldElement::from_msg_hash(&chal_bytes);
+        let mut proof = pok.gen_proof(&chal)?;
+        proof.key_id = Some(verkey_id(verkey));
+        proof.credential_type = self.credential_type.clone();
+        proof.rate_limit_tag = match tag_pok {
+            Some(tag_pok) => Some(tag_pok.gen_proof(&chal)?),
+            None => None,
+        };
+        proof.one_show_tag = one_show_pok.map(|pok| pok.gen_proof());
+        Ok(proof)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::d_idp::DistributedIdP;
+    use crate::ttp::TrustedThirdParty;
     #[test]
     fn test_client_setup() {
         let threshold = 3;
@@ -152,15 +883,100 @@ mod tests {
         let msg_count = 6;
 
         let label = "test";
-        let user_secret = String::from("cheese");
         let public_params = PublicParams::new(msg_count, label.as_bytes(), threshold, total);
-        let client = Client::new(public_params, user_secret);
+        let mut client = Client::new(public_params, String::from("cheese"));
 
         let visible_strings = vec!["these", "are", "all", "visible"];
         let visible_messages = visible_strings.iter().map(|&s| s.to_string()).collect::<Vec<String>>();
         let hidden_strings = vec!["not", "these"];
         let hidden_messages = hidden_strings.iter().map(|&s| s.to_string()).collect::<Vec<String>>();
 
-        client.request_id(visible_messages, hidden_messages);
+        let request = client.request_id(visible_messages, hidden_messages).unwrap();
+        assert_eq!(&request.elg_pk, client.session().unwrap().elgamal_pk());
+        assert!(request.verify(&client.public).is_ok());
+        assert!(request.check_visible());
+
+        let mut replayed = request.clone();
+        replayed.session_id = String::from("another session");
+        assert!(replayed.verify(&client.public).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_client_export_import() {
+        let public_params = PublicParams::new(6, "test".as_bytes(), 3, 5);
+        let mut client = Client::new(public_params.clone(), String::from("cheese"));
+        client.request_id(vec![String::from("visible"); 4], vec![String::from("hidden"); 2]).unwrap();
+
+        let exported = client.export("correct horse").unwrap();
+        let imported = Client::import(public_params.clone(), &exported, "correct horse").unwrap();
+        assert_eq!(imported.secret.expose_secret(), client.secret.expose_secret());
+        assert_eq!(imported.session().unwrap().elgamal_pk(), client.session().unwrap().elgamal_pk());
+        assert_eq!(imported.session().unwrap().messages().all, client.session().unwrap().messages().all);
+
+        assert!(Client::import(public_params, &exported, "battery staple").is_err());
+    }
+
+    #[test]
+    fn test_fresh_elgamal_key_per_session() {
+        let public_params = PublicParams::new(6, "test".as_bytes(), 3, 5);
+        let mut client = Client::new(public_params, String::from("cheese"));
+
+        client.request_id(vec![String::from("visible"); 4], vec![String::from("hidden"); 2]).unwrap();
+        let first_pk = client.session().unwrap().elgamal_pk().clone();
+        client.request_id(vec![String::from("visible"); 4], vec![String::from("hidden"); 2]).unwrap();
+        assert_ne!(&first_pk, client.session().unwrap().elgamal_pk());
+    }
+
+    #[test]
+    fn test_errors_instead_of_panics() {
+        let public_params = PublicParams::new(6, "test".as_bytes(), 3, 5);
+        let mut client = Client::new(public_params, String::from("cheese"));
+
+        // Params are for 6 messages
+        let err = client.request_id(vec![String::from("visible"); 2], vec![String::from("hidden"); 2]).unwrap_err();
+        assert_eq!(err.code(), "CoconutError");
+        assert_eq!(client.verify_signatures(vec![]).unwrap_err().code(), "InvalidState");
+        assert_eq!(client.prove_id(JSMessages::new(vec![], vec![]), HashSet::new(), &String::from("rp")).unwrap_err().code(), "InvalidState");
+
+        client.request_id(vec![String::from("visible"); 4], vec![String::from("hidden"); 2]).unwrap();
+        assert_eq!(
+            client.verify_signatures(vec![]).unwrap_err(),
+            ThesisError::NotEnoughSignatures { threshold: 3, valid: 0 }
+        );
+    }
+
+    #[test]
+    fn test_duplicated_share() {
+        let public_params = PublicParams::new(6, "test".as_bytes(), 2, 3);
+        let ttp = TrustedThirdParty::new(2, 3, &public_params).unwrap();
+        let idps = (0..3)
+            .map(|i| DistributedIdP::from_serialized_signer(&ttp.serialize_server_i(i).unwrap(), &public_params).unwrap())
+            .collect::<Vec<DistributedIdP>>();
+        let mut client = Client::new(public_params, String::from("cheese"));
+        let request = client.request_id(vec![String::from("visible"); 4], vec![String::from("hidden"); 2]).unwrap();
+
+        let share = idps[0].verify_and_blind_sign(&request).unwrap();
+        assert_eq!(
+            client.verify_signatures(vec![share.clone(), share.clone()]).unwrap_err(),
+            ThesisError::NotEnoughSignatures { threshold: 2, valid: 1 }
+        );
+        // Shares of signers that are not part of the deployment are ignored
+        let mut outsider = share.clone();
+        outsider.id = 4;
+        assert_eq!(
+            client.verify_signatures(vec![share.clone(), outsider]).unwrap_err(),
+            ThesisError::NotEnoughSignatures { threshold: 2, valid: 1 }
+        );
+        let other = idps[1].verify_and_blind_sign(&request).unwrap();
+        client.verify_signatures(vec![share.clone(), share, other]).unwrap();
+        assert!(client.key_id().is_some());
+    }
+
+    #[test]
+    fn test_elgamal_keys_zeroize() {
+        let public_params = PublicParams::new(6, "test".as_bytes(), 3, 5);
+        let mut keys = ElGamalKeys::new(&public_params.cparams.g);
+        keys.zeroize();
+        assert!(keys.sk.is_zero());
+    }
+}
@@ -0,0 +1,231 @@
+// Registry of credential types.
+//
+// Each type has a schema listing its attributes and which of them are hidden. All types are
+// signed with the same IdP keys, but requests for a type are made with message generators
+// derived from the type id (see `ParamSpec::type_params`), so a request proof for one type does
+// not verify as one for another. The type is also signed into the credential: the slot after the
+// schema's attributes holds a visible type attribute. It is checked by the IdPs at issuance and
+// revealed by the client at every presentation, so a credential of one type cannot be presented
+// as another.
+
+use amcl_wrapper::field_elem::FieldElement;
+use coconut_sig::errors::CoconutError;
+use coconut_sig::validation::invalid;
+use std::collections::BTreeMap;
+
+use crate::attribute_count::AttributeLayout;
+use crate::client::ClientIdRequest;
+use crate::errors::IssuanceError;
+use crate::js::Public as PublicParams;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributeSpec {
+    pub name: String,
+    pub hidden: bool,
+}
+
+impl AttributeSpec {
+    pub fn hidden(name: &str) -> Self {
+        AttributeSpec {
+            name: name.to_string(),
+            hidden: true,
+        }
+    }
+
+    pub fn visible(name: &str) -> Self {
+        AttributeSpec {
+            name: name.to_string(),
+            hidden: false,
+        }
+    }
+}
+
+/// A credential type: its id and the client attributes of its credentials, in slot order
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialType {
+    pub id: String,
+    pub attributes: Vec<AttributeSpec>,
+}
+
+impl CredentialType {
+    pub fn new(id: &str, attributes: Vec<AttributeSpec>) -> Self {
+        CredentialType {
+            id: id.to_string(),
+            attributes,
+        }
+    }
+
+    /// Slots of the hidden attributes
+    pub fn hidden_indices(&self) -> Vec<usize> {
+        (0..self.attributes.len()).filter(|i| self.attributes[*i].hidden).collect()
+    }
+
+    pub fn hidden_count(&self) -> usize {
+        self.attributes.iter().filter(|a| a.hidden).count()
+    }
+
+    pub fn visible_count(&self) -> usize {
+        self.attributes.len() - self.hidden_count()
+    }
+
+    /// Slot of the type attribute, right after the schema's attributes
+    pub fn type_slot(&self) -> usize {
+        self.attributes.len()
+    }
+
+    /// Plaintext of the type attribute
+    pub fn type_attribute(&self) -> String {
+        format!("coconut : credential type {}", self.id)
+    }
+
+    /// Append the type attribute to the visible attributes of a credential of this type
+    pub fn append_type_attribute(&self, hidden_count: usize, mut visible: Vec<String>) -> Result<Vec<String>, String> {
+        if hidden_count != self.hidden_count() || visible.len() != self.visible_count() {
+            return Err(format!(
+                "Type {} has {} hidden and {} visible attributes but got {} and {}",
+                self.id, self.hidden_count(), self.visible_count(), hidden_count, visible.len()
+            ));
+        }
+        visible.push(self.type_attribute());
+        Ok(visible)
+    }
+
+    /// Check that a request is laid out as this type: hidden attributes in the schema's slots and
+    /// the type attribute in its slot. The plaintext visible attributes must be checked against the
+    /// request separately.
+    pub fn check(&self, request: &ClientIdRequest) -> Result<(), IssuanceError> {
+        let hidden = &request.sig_req.hidden_indices;
+        if *hidden != self.hidden_indices() {
+            return Err(IssuanceError::MalformedRequest {
+                msg: format!("Hidden attributes not in the slots of type {}", self.id),
+            });
+        }
+        // Visible attributes are in slot order and all hidden slots come before the type slot
+        match request.visible.get(self.type_slot() - hidden.len()) {
+            Some(attr) if *attr == self.type_attribute() => Ok(()),
+            _ => Err(IssuanceError::MalformedRequest {
+                msg: format!("Request does not carry the attribute of type {}", self.id),
+            }),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.id.len() as u64).to_be_bytes().to_vec();
+        bytes.extend_from_slice(self.id.as_bytes());
+        bytes.extend_from_slice(&(self.attributes.len() as u64).to_be_bytes());
+        for attr in &self.attributes {
+            bytes.extend_from_slice(&(attr.name.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(attr.name.as_bytes());
+            bytes.push(attr.hidden as u8);
+        }
+        bytes
+    }
+}
+
+/// The credential types of a deployment, part of the public params
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CredentialTypeRegistry {
+    types: BTreeMap<String, CredentialType>,
+}
+
+impl CredentialTypeRegistry {
+    pub fn new() -> Self {
+        CredentialTypeRegistry::default()
+    }
+
+    /// Register `credential_type`, replacing any type with the same id
+    pub fn with_type(mut self, credential_type: CredentialType) -> Self {
+        self.types.insert(credential_type.id.clone(), credential_type);
+        self
+    }
+
+    pub fn get(&self, id: &str) -> Option<&CredentialType> {
+        self.types.get(id)
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.types.keys().cloned().collect()
+    }
+
+    /// Look up the type of a request
+    pub fn type_of(&self, request: &ClientIdRequest) -> Result<&CredentialType, IssuanceError> {
+        self.get(&request.credential_type).ok_or_else(|| IssuanceError::UnknownCredentialType {
+            credential_type: request.credential_type.clone(),
+        })
+    }
+
+    /// Slot and hashed value of the type attribute a presentation of type `id` reveals
+    pub fn revealed_type_attribute(&self, id: &str) -> Option<(usize, FieldElement)> {
+        self.get(id)
+            .map(|t| (t.type_slot(), FieldElement::from_msg_hash(t.type_attribute().as_bytes())))
+    }
+
+    /// Check that every type fits the message slots of `params`. Without a variable attribute
+    /// count a type must fill all slots before the issuer attributes.
+    pub fn check_layout(&self, params: &PublicParams) -> Result<(), CoconutError> {
+        let layout = AttributeLayout::new(params);
+        for t in self.types.values() {
+            let needed = t.attributes.len() + 1;
+            let fits = if params.variable_attribute_count {
+                needed <= layout.max_attributes()
+            } else {
+                needed + layout.issuer_count == params.msg_count
+            };
+            if t.id.is_empty() || !fits {
+                return Err(invalid(format!("Credential type {:?} does not fit the message slots", t.id)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes of all types, for the params fingerprint
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.types.len() as u64).to_be_bytes().to_vec();
+        for t in self.types.values() {
+            bytes.append(&mut t.to_bytes());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, RequestOptions};
+
+    #[test]
+    fn test_typed_request() {
+        let passport = CredentialType::new("passport", vec![AttributeSpec::visible("name"), AttributeSpec::hidden("number")]);
+        let registry = CredentialTypeRegistry::new()
+            .with_type(passport.clone())
+            .with_type(CredentialType::new("license", vec![AttributeSpec::hidden("number"), AttributeSpec::visible("class")]));
+        let params = PublicParams::new(3, "test".as_bytes(), 3, 5).with_credential_types(registry.clone());
+        registry.check_layout(&params).unwrap();
+
+        let mut client = Client::new(params.clone(), String::from("cheese"));
+        let options = RequestOptions {
+            credential_type: String::from("passport"),
+            ..RequestOptions::default()
+        };
+        let request = client
+            .request_id_with_options(vec![String::from("alice")], vec![String::from("P123")], options.clone())
+            .unwrap();
+        assert_eq!(request.sig_req.hidden_indices, vec![1]);
+        assert_eq!(request.visible[1], passport.type_attribute());
+        passport.check(&request).unwrap();
+        request.verify(&params).unwrap();
+
+        // The request does not verify or check as another type
+        let mut relabelled = request.clone();
+        relabelled.credential_type = String::from("license");
+        assert!(relabelled.verify(&params).is_err());
+        assert!(registry.get("license").unwrap().check(&relabelled).is_err());
+        relabelled.credential_type = String::from("unknown");
+        assert!(registry.type_of(&relabelled).is_err());
+
+        // Attributes must follow the schema
+        assert!(client
+            .request_id_with_options(vec![String::from("alice"); 2], vec![], options)
+            .is_err());
+    }
+}
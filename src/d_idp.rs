@@ -1,57 +1,381 @@
-use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::group_elem::GroupElementVector;
 use coconut_sig::keygen::Signer;
 use coconut_sig::signature::Verkey;
-use ps_sig::SignatureGroup;
+use coconut_sig::validation::Validate;
+use ps_sig::keys::Verkey as PSVerkey;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::client::ServerBlindSignature;
-use crate::js_signature::{JSBlindSignature, JSSignatureRequest, JSSignatureRequestProof};
+use crate::SignatureGroup;
+use crate::attestation::Attestor;
+use crate::client::{ClientIdRequest, ServerBlindSignature};
+use crate::errors::{IssuanceError, ThesisError};
+use crate::gate::CredentialGate;
+use crate::identity_tag::TagRegistry;
+use crate::attribute_count::AttributeLayout;
+use crate::issuer_attributes::IssuerAttributes;
+use crate::key_store::{KeyMetadata, KeyStore, MemoryKeyStore, StoredKey};
 use crate::js::Public as PublicParams;
+use crate::policy::{IssuancePolicy, PolicyEngine};
+use crate::renewal::RenewalRequest;
+use crate::response_cache::ResponseCache;
+use crate::share_transport::{EncryptedShare, KeyPair};
+use crate::utils::now_secs;
+use crate::verkey_distribution::{verkey_id, SignedVerkeyShare};
 
 
 #[derive(Clone, Debug)]
 pub struct DistributedIdP {
     id: usize,
-    keys: Signer,
+    keys: Arc<dyn KeyStore>,
+    /// Key of the store this IdP signs with
+    key: KeyMetadata,
+    verkey: Verkey,
     public: PublicParams,
+    attestor: Attestor,
+    policy: PolicyEngine,
+    responses: ResponseCache,
+    tags: Option<Arc<dyn TagRegistry>>,
+    /// Keys the verification key share is published with
+    identity: Option<KeyPair>,
+    /// Aggregated verification keys of credentials accepted for renewal, by key id
+    renewal_keys: HashMap<String, PSVerkey>,
+    /// Credential of another issuer every fresh request must present
+    gate: Option<CredentialGate>,
 }
 
 impl DistributedIdP {
+    /// IdP keeping `keys` in a `MemoryKeyStore`, under a random key id and without an epoch
     pub fn new (keys: Signer, public: PublicParams) -> Self {
+        Self::with_stored_key(StoredKey::new(keys), public)
+    }
+
+    /// IdP keeping `stored` in a `MemoryKeyStore`
+    fn with_stored_key (stored: StoredKey, public: PublicParams) -> Self {
+        let key = stored.metadata.clone();
+        let verkey = stored.signer.verkey.clone();
         DistributedIdP {
-            id: keys.id,
-            keys,
+            id: key.signer_id,
+            keys: Arc::new(MemoryKeyStore::with_key(stored)),
+            key,
+            verkey,
             public,
+            attestor: Attestor::default(),
+            policy: PolicyEngine::default(),
+            responses: ResponseCache::default(),
+            tags: None,
+            identity: None,
+            renewal_keys: HashMap::new(),
+            gate: None,
+        }
+    }
+
+    /// Check visible attributes with `attestor` before signing. Its verifiers may be shared with
+    /// the other IdPs of the deployment, see `OtpVerifier::with_uses`.
+    pub fn with_attestor (mut self, attestor: Attestor) -> Self {
+        self.attestor = attestor;
+        self
+    }
+
+    /// Run every request through `policy` before signing. Fails if the policy allows only some
+    /// credential types but the params have no credential type registry.
+    pub fn with_policy (mut self, policy: IssuancePolicy) -> Result<Self, ThesisError> {
+        self.policy = PolicyEngine::new(policy, &self.public)?;
+        Ok(self)
+    }
+
+    /// Keep responses for retried requests for `retention_secs` seconds
+    pub fn with_response_cache (mut self, retention_secs: u64) -> Self {
+        self.responses = ResponseCache::new(retention_secs);
+        self
+    }
+
+    /// Require an identity tag with every request and refuse tags already seen with another
+    /// request. The registry may be shared with the other IdPs of the deployment.
+    pub fn with_tag_registry (mut self, tags: Arc<dyn TagRegistry>) -> Self {
+        self.tags = Some(tags);
+        self
+    }
+
+    /// Sign the published verification key share with the identity keys `identity`
+    pub fn with_identity_keys (mut self, identity: KeyPair) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Require every fresh request to present a credential of another issuer as set by `gate`.
+    /// Renewals do not present one: the current credential stands in for it, so only accept
+    /// renewal keys of deployments that enforced the same gate.
+    pub fn with_credential_gate (mut self, gate: CredentialGate) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    /// Renew credentials issued under the aggregated verification key `vk`, e.g. that of the
+    /// previous generation of keys. With a credential gate, the credentials of `vk` must have
+    /// been issued under the same gate.
+    pub fn accept_renewal_key (&mut self, vk: PSVerkey) {
+        self.renewal_keys.insert(verkey_id(&vk), vk);
+    }
+
+    pub fn id (&self) -> usize {
+        self.id
+    }
+
+    /// Id of the key this IdP signs with
+    pub fn key_id (&self) -> &str {
+        &self.key.key_id
+    }
+
+    /// IdP signing with the active key of `keys`
+    pub fn from_key_store (keys: Arc<dyn KeyStore>, params: &PublicParams) -> Result<Self, ThesisError> {
+        params.validate()?;
+        let key = keys.active()?;
+        let verkey = keys.verkey(&key.key_id)?;
+        verkey.validate()?;
+        DistributedIdP {
+            id: key.signer_id,
+            keys,
+            key,
+            verkey,
+            public: params.clone(),
+            attestor: Attestor::default(),
+            policy: PolicyEngine::default(),
+            responses: ResponseCache::default(),
+            tags: None,
+            identity: None,
+            renewal_keys: HashMap::new(),
+            gate: None,
         }
+        .check_msg_count()
+    }
+
+    /// Switch to the active key of the key store, after a key was rotated or the window of a
+    /// newer key started
+    pub fn reload_key (&mut self) -> Result<(), ThesisError> {
+        let key = self.keys.active()?;
+        check_msg_count(&key, &self.public)?;
+        let verkey = self.keys.verkey(&key.key_id)?;
+        verkey.validate()?;
+        self.verkey = verkey;
+        self.id = key.signer_id;
+        self.key = key;
+        Ok(())
+    }
+
+    /// Load a key serialized with `TrustedThirdParty::serialize_server_i`, or a bare `Signer`
+    pub fn from_serialized_signer (signer: &str, params: &PublicParams) -> Result<Self, ThesisError> {
+        let key = match serde_json::from_str::<StoredKey>(signer) {
+            Ok(key) => key,
+            Err(_) => StoredKey::new(serde_json::from_str::<Signer>(signer)?),
+        };
+        Self::from_stored_key(key, params)
+    }
+
+    /// Load the keys from a share encrypted by the dealer with `TrustedThirdParty::encrypt_server_i`.
+    /// The share must be signed by the pinned `dealer` key and encrypted to `identity`.
+    pub fn from_encrypted_share (share: &str, identity: &KeyPair, dealer: &SignatureGroup, params: &PublicParams) -> Result<Self, ThesisError> {
+        let share: EncryptedShare = serde_json::from_str(share)?;
+        share.validate()?;
+        Ok(Self::from_stored_key(share.open(identity, dealer)?, params)?.with_identity_keys(identity.clone()))
     }
 
-    pub fn from_serialized_signer (signer: &String, params: &PublicParams) -> Self {
-        let keys: Signer = serde_json::from_str(signer).expect("Failed to deserialize signer");
-        DistributedIdP::new(keys, params.clone())
+    fn from_stored_key (key: StoredKey, params: &PublicParams) -> Result<Self, ThesisError> {
+        params.validate()?;
+        key.signer.validate()?;
+        DistributedIdP::with_stored_key(key, params.clone()).check_msg_count()
     }
 
-    pub fn blind_sign (&self, sig_req: &JSSignatureRequest) -> ServerBlindSignature {
-        ServerBlindSignature {
+    fn check_msg_count (self) -> Result<Self, ThesisError> {
+        check_msg_count(&self.key, &self.public)?;
+        Ok(self)
+    }
+
+    /// Issue credential share blindly over request. Only called once the request proof is verified.
+    fn blind_sign (&self, request: &ClientIdRequest, issuer_attributes: Option<IssuerAttributes>) -> Result<ServerBlindSignature, IssuanceError> {
+        Ok(ServerBlindSignature {
             id: self.id, 
-            blind_sig: JSBlindSignature::new(sig_req, &self.keys.sigkey),
-            vk_share: self.keys.verkey.clone(),
+            session_id: request.session_id.clone(),
+            key_id: self.key.key_id.clone(),
+            params_fingerprint: self.public.fingerprint(),
+            blind_sig: self.keys.blind_sign(&self.key.key_id, &request.sig_req)?,
+            vk_share: self.verkey.clone(),
+            issuer_attributes,
+        })
+    }
+
+    /// Derive the issuer attributes for the request and check that the request's known messages
+    /// end with them and take the last slots. The requested issuance epoch must be close to this IdP's clock.
+    fn issuer_attributes (&self, request: &ClientIdRequest) -> Result<Option<IssuerAttributes>, IssuanceError> {
+        let spec = match &self.public.issuer_attributes {
+            Some(spec) => spec,
+            None => return Ok(None),
+        };
+        let epoch = match request.issuance_epoch {
+            Some(epoch) if spec.accepts_epoch(epoch, now_secs()) => epoch,
+            _ => return Err(IssuanceError::IssuanceEpochRejected {
+                epoch: request.issuance_epoch,
+            }),
+        };
+
+        // The issuer attributes take the last slots, so none of them may be hidden
+        let last_free = request.sig_req.msg_count().saturating_sub(IssuerAttributes::COUNT);
+        let attrs = spec.derive(epoch, &request.session_id, &request.elg_pk)?;
+        if request.sig_req.hidden_indices.iter().any(|i| *i >= last_free) || !attrs.matches(&request.sig_req.known_messages) {
+            return Err(IssuanceError::IssuerAttributesMismatch);
+        }
+        Ok(Some(attrs))
+    }
+
+    /// Check that the request's identity tag has not been seen with another request
+    fn check_tag (&self, tags: &dyn TagRegistry, request: &ClientIdRequest) -> Result<String, IssuanceError> {
+        let tag = request.tag_hex().ok_or(IssuanceError::IdentityTagRequired)?;
+        let digest = tags
+            .request_of(&tag)
+            .map_err(|msg| IssuanceError::TagRegistryError { msg })?;
+        match digest {
+            Some(digest) if digest != request.tag_digest() => Err(IssuanceError::DuplicateIdentityTag { tag }),
+            _ => Ok(tag),
         }
     }
 
     pub fn get_id_vk (&self) -> (usize, Verkey) {
-        (self.id, self.keys.verkey.clone())
+        (self.id, self.verkey.clone())
     }
 
-    pub fn verify_and_blind_sign (&self, sig_req: &JSSignatureRequest, sig_req_proof: &JSSignatureRequestProof, elg_pk: &SignatureGroup) -> ServerBlindSignature {
-        // Verify siqnature request proof
-        let challenge_for_verifier = FieldElement::from_msg_hash(&sig_req_proof.get_bytes_for_challenge(sig_req, &elg_pk, &self.public.cparams));
-        assert!(sig_req_proof
-            .verify(&sig_req, &elg_pk, &challenge_for_verifier, &self.public.cparams)
-            .unwrap());
+    /// Verification key share signed with the identity keys, for the RPs to aggregate
+    pub fn publish_vk_share (&self) -> Result<SignedVerkeyShare, ThesisError> {
+        let identity = self.identity.as_ref().ok_or_else(|| ThesisError::InvalidState {
+            msg: String::from("No identity keys"),
+        })?;
+        Ok(SignedVerkeyShare::new(self.id, self.key.epoch(), &self.public.fingerprint(), self.verkey.clone(), identity))
+    }
 
-        // Issue credential share blindly over request
-        self.blind_sign(sig_req)
+    /// Verify the request and issue a share over it. A retry of a request that was already signed
+    /// gets the same response without running the checks again, so it does not count twice
+    /// towards the quota or consume evidence a second time. With a credential gate the request
+    /// must carry the presentation it requires.
+    pub fn verify_and_blind_sign (&self, request: &ClientIdRequest) -> Result<ServerBlindSignature, IssuanceError> {
+        self.verify_and_sign(request, None)
     }
-}
 
+    /// Verify the renewal of a credential issued under one of the renewal keys and issue a share
+    /// over the new request. The request goes through the same checks as a fresh one, except that
+    /// its identity tag is that of the current credential and is not registered again.
+    pub fn verify_and_renew (&self, renewal: &RenewalRequest) -> Result<ServerBlindSignature, IssuanceError> {
+        renewal.validate()?;
+        self.verify_and_sign(&renewal.request, Some(renewal))
+    }
+
+    fn verify_and_sign (&self, request: &ClientIdRequest, renewal: Option<&RenewalRequest>) -> Result<ServerBlindSignature, IssuanceError> {
+        request.validate()?;
+        if request.params_fingerprint != self.public.fingerprint() {
+            return Err(IssuanceError::ParamsMismatch {
+                fingerprint: request.params_fingerprint.clone(),
+            });
+        }
+        if let Some(response) = self.responses.lookup(request, now_secs())? {
+            return Ok(response);
+        }
+        // The request id stays reserved while the request is checked and signed
+        match self.check_and_sign(request, renewal) {
+            Ok(response) => {
+                self.responses.store(request, &response, now_secs())?;
+                Ok(response)
+            }
+            Err(e) => {
+                self.responses.release(request);
+                Err(e)
+            }
+        }
+    }
+
+    fn check_and_sign (&self, request: &ClientIdRequest, renewal: Option<&RenewalRequest>) -> Result<ServerBlindSignature, IssuanceError> {
+        if !self.key.validity.contains(now_secs()) {
+            return Err(IssuanceError::KeyNotValid {
+                key_id: self.key.key_id.clone(),
+            });
+        }
+
+        // Requests for registered types must follow the type's layout
+        if let Some(registry) = &self.public.credential_types {
+            registry.type_of(request)?.check(request)?;
+        }
+
+        // Verify siqnature request proof, together with the proof of possession for a renewal or
+        // the presentation the gate requires
+        if request.presentation.is_some() && (renewal.is_some() || self.gate.is_none()) {
+            return Err(IssuanceError::MalformedRequest {
+                msg: String::from("Unexpected presentation"),
+            });
+        }
+        match (renewal, &self.gate) {
+            (Some(renewal), _) => {
+                let vk = renewal
+                    .key_id()
+                    .and_then(|key_id| self.renewal_keys.get(key_id))
+                    .ok_or_else(|| IssuanceError::RenewalKeyRejected {
+                        key_id: renewal.key_id().cloned(),
+                    })?;
+                renewal.verify(&self.public, vk)?;
+            }
+            (None, Some(gate)) => gate.verify(request, &self.public)?,
+            (None, None) => request.verify(&self.public).map_err(|_| IssuanceError::InvalidRequestProof)?,
+        }
+        if !request.check_visible() {
+            return Err(IssuanceError::MalformedRequest {
+                msg: String::from("Visible attributes do not match the known messages"),
+            });
+        }
+
+        if self.public.variable_attribute_count {
+            AttributeLayout::new(&self.public).check(request)?;
+        }
+        let issuer_attributes = self.issuer_attributes(request)?;
+        self.policy.check(request)?;
+        // A renewal keeps the link secret, so its tag must already be registered and is not
+        // registered again
+        let tag = match (&self.tags, renewal) {
+            (Some(tags), Some(_)) => {
+                let tag = request.tag_hex().ok_or(IssuanceError::IdentityTagRequired)?;
+                let registered = tags
+                    .request_of(&tag)
+                    .map_err(|msg| IssuanceError::TagRegistryError { msg })?;
+                if registered.is_none() {
+                    return Err(IssuanceError::UnregisteredIdentityTag { tag });
+                }
+                None
+            }
+            (Some(tags), None) => Some(self.check_tag(tags.as_ref(), request)?),
+            (None, _) => None,
+        };
+
+        // Only co-sign claims that have been checked
+        self.attestor.check(&request.visible, &request.evidence)?;
+        if let (Some(tags), Some(tag)) = (&self.tags, tag) {
+            let registered = tags
+                .register(&tag, &request.tag_digest())
+                .map_err(|msg| IssuanceError::TagRegistryError { msg })?;
+            if !registered {
+                return Err(IssuanceError::DuplicateIdentityTag { tag });
+            }
+        }
+        self.policy.record(request)?;
 
+        // Issue credential share blindly over request. Single use evidence is only used up once
+        // the share exists.
+        let response = self.blind_sign(request, issuer_attributes)?;
+        self.attestor.consume(&request.visible, &request.evidence)?;
+        Ok(response)
+    }
+}
 
+/// Check that `key` signs as many messages as the params have
+fn check_msg_count (key: &KeyMetadata, public: &PublicParams) -> Result<(), ThesisError> {
+    if key.msg_count != public.cparams.h.len() {
+        return Err(ThesisError::InvalidInput {
+            msg: format!("Signer keys for {} messages but params for {}", key.msg_count, public.cparams.h.len()),
+        });
+    }
+    Ok(())
+}
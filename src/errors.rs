@@ -0,0 +1,254 @@
+use coconut_sig::errors::{CoconutError, CoconutErrorKind};
+use ps_sig::errors::PSError;
+use std::fmt;
+
+use crate::backup::BackupError;
+use crate::key_store::KeyStoreError;
+use crate::policy::PolicyViolation;
+use crate::share_transport::ShareError;
+
+/// Reasons an IdP refuses to sign a request
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum IssuanceError {
+    InvalidRequestProof,
+    MalformedRequest { msg: String },
+    ParamsMismatch { fingerprint: String },
+    UnknownCredentialType { credential_type: String },
+    IssuanceEpochRejected { epoch: Option<u64> },
+    IssuerAttributesMismatch,
+    AttestationFailed {
+        index: usize,
+        verifier: String,
+        reason: String,
+    },
+    PolicyViolation { violation: PolicyViolation },
+    RequestIdConflict { request_id: String },
+    RequestInProgress { request_id: String },
+    IdentityTagRequired,
+    DuplicateIdentityTag { tag: String },
+    UnregisteredIdentityTag { tag: String },
+    TagRegistryError { msg: String },
+    KeyNotValid { key_id: String },
+    PresentationRequired,
+    InvalidPresentation,
+    InvalidPossessionProof,
+    RenewalKeyRejected { key_id: Option<String> },
+    KeyStoreError { msg: String },
+    CoconutError { msg: String },
+}
+
+impl fmt::Display for IssuanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IssuanceError::InvalidRequestProof => write!(f, "Signature request proof failed verification"),
+            IssuanceError::MalformedRequest { msg } => write!(f, "Malformed request {:?}", msg),
+            IssuanceError::ParamsMismatch { fingerprint } => write!(f, "Request made under other public parameters {:?}", fingerprint),
+            IssuanceError::UnknownCredentialType { credential_type } => write!(f, "Unknown credential type {:?}", credential_type),
+            IssuanceError::IssuanceEpochRejected { epoch } => write!(f, "Issuance epoch {:?} not accepted", epoch),
+            IssuanceError::IssuerAttributesMismatch => write!(f, "Request does not carry the issuer attributes"),
+            IssuanceError::AttestationFailed { index, verifier, reason } => write!(f, "Attribute {} not attested by verifier {:?}: {}", index, verifier, reason),
+            IssuanceError::PolicyViolation { violation } => write!(f, "Request violates the issuance policy: {:?}", violation),
+            IssuanceError::RequestIdConflict { request_id } => write!(f, "Request id {:?} was already used for a different request", request_id),
+            IssuanceError::RequestInProgress { request_id } => write!(f, "Request {:?} is already being signed", request_id),
+            IssuanceError::IdentityTagRequired => write!(f, "Request has no identity tag"),
+            IssuanceError::DuplicateIdentityTag { tag } => write!(f, "A credential was already issued for identity tag {:?}", tag),
+            IssuanceError::UnregisteredIdentityTag { tag } => write!(f, "Identity tag {:?} of the renewed credential is not registered", tag),
+            IssuanceError::TagRegistryError { msg } => write!(f, "Tag registry failed {:?}", msg),
+            IssuanceError::KeyNotValid { key_id } => write!(f, "Signing key {:?} is outside its validity window", key_id),
+            IssuanceError::PresentationRequired => write!(f, "Request has no presentation of the required credential"),
+            IssuanceError::InvalidPresentation => write!(f, "Presentation of the required credential failed verification"),
+            IssuanceError::InvalidPossessionProof => write!(f, "Proof of possession of the current credential failed verification"),
+            IssuanceError::RenewalKeyRejected { key_id } => write!(f, "Key {:?} of the current credential not accepted for renewal", key_id),
+            IssuanceError::KeyStoreError { msg } => write!(f, "Key store failed {:?}", msg),
+            IssuanceError::CoconutError { msg } => write!(f, "Error from coconut crate {:?}", msg),
+        }
+    }
+}
+
+impl std::error::Error for IssuanceError {}
+
+impl From<KeyStoreError> for IssuanceError {
+    fn from(err: KeyStoreError) -> Self {
+        match err {
+            KeyStoreError::CoconutError { msg } => IssuanceError::CoconutError { msg },
+            _ => IssuanceError::KeyStoreError {
+                msg: err.to_string(),
+            },
+        }
+    }
+}
+
+impl From<CoconutError> for IssuanceError {
+    fn from(err: CoconutError) -> Self {
+        match err.kind() {
+            CoconutErrorKind::ValidationError { msg } => IssuanceError::MalformedRequest { msg },
+            _ => IssuanceError::CoconutError {
+                msg: err.to_string(),
+            },
+        }
+    }
+}
+
+impl From<PolicyViolation> for IssuanceError {
+    fn from(violation: PolicyViolation) -> Self {
+        IssuanceError::PolicyViolation { violation }
+    }
+}
+
+/// Copy of a `PSError`, which is neither `Clone` nor `Eq`
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PSErrorKind {
+    UnsupportedNoOfMessages { expected: usize, given: usize },
+    UnequalNoOfBasesExponents { bases: usize, exponents: usize },
+    IncompatibleVerkeysForAggregation,
+    IncompatibleSigsForAggregation,
+    GeneralError { msg: String },
+}
+
+impl fmt::Display for PSErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PSErrorKind::UnsupportedNoOfMessages { expected, given } => write!(f, "Verkey valid for {} messages but given {} messages", expected, given),
+            PSErrorKind::UnequalNoOfBasesExponents { bases, exponents } => write!(f, "Same no of bases and exponents required. {} bases and {} exponents", bases, exponents),
+            PSErrorKind::IncompatibleVerkeysForAggregation => write!(f, "All verification keys should have equal number of Y_tilde elements"),
+            PSErrorKind::IncompatibleSigsForAggregation => write!(f, "All signatures should have same first element (sigma_1)"),
+            PSErrorKind::GeneralError { msg } => write!(f, "Error with message {:?}", msg),
+        }
+    }
+}
+
+impl From<PSError> for PSErrorKind {
+    fn from(err: PSError) -> Self {
+        match err {
+            PSError::UnsupportedNoOfMessages { expected, given } => PSErrorKind::UnsupportedNoOfMessages { expected, given },
+            PSError::UnequalNoOfBasesExponents { bases, exponents } => PSErrorKind::UnequalNoOfBasesExponents { bases, exponents },
+            PSError::IncompatibleVerkeysForAggregation => PSErrorKind::IncompatibleVerkeysForAggregation,
+            PSError::IncompatibleSigsForAggregation => PSErrorKind::IncompatibleSigsForAggregation,
+            PSError::GeneralError { msg } => PSErrorKind::GeneralError { msg },
+        }
+    }
+}
+
+/// Returned to the client when an IdP refuses to sign, so it can tell which IdPs refused and why
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct IssuanceRejection {
+    pub idp: usize,
+    pub error: IssuanceError,
+}
+
+/// Error of any operation of this crate. Every variant has a stable `code` that is passed on to
+/// JavaScript along with the message. Not a `std::error::Error`, as wasm_bindgen would then
+/// convert it to a `JsError` without the code.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ThesisError {
+    CoconutError { kind: CoconutErrorKind },
+    PSError { kind: PSErrorKind },
+    IssuanceError { error: IssuanceError },
+    BackupError { error: BackupError },
+    ShareError { error: ShareError },
+    KeyStoreError { error: KeyStoreError },
+    SerializationError { msg: String },
+    InvalidInput { msg: String },
+    InvalidState { msg: String },
+    NotEnoughSignatures { threshold: usize, valid: usize },
+    VerkeyRejected { msg: String },
+    CredentialTypeRejected { credential_type: Option<String> },
+    RateLimitTagRejected { msg: String },
+    RateLimitExceeded { tag: String },
+    OneShowTagRejected { msg: String },
+    TokenReused { serial: String, identity: Option<String> },
+}
+
+impl fmt::Display for ThesisError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ThesisError::CoconutError { kind } => write!(f, "Error from coconut crate: {}", kind),
+            ThesisError::PSError { kind } => write!(f, "Error from PS sig crate: {}", kind),
+            ThesisError::IssuanceError { error } => write!(f, "Issuance refused: {}", error),
+            ThesisError::BackupError { error } => write!(f, "Backup failed: {}", error),
+            ThesisError::ShareError { error } => write!(f, "Signer share rejected: {}", error),
+            ThesisError::KeyStoreError { error } => write!(f, "Key store failed: {}", error),
+            ThesisError::SerializationError { msg } => write!(f, "Error with serialization {:?}", msg),
+            ThesisError::InvalidInput { msg } => write!(f, "Invalid input {:?}", msg),
+            ThesisError::InvalidState { msg } => write!(f, "Operation not possible in current state {:?}", msg),
+            ThesisError::NotEnoughSignatures { threshold, valid } => write!(f, "Need {} valid signatures but got {}", threshold, valid),
+            ThesisError::VerkeyRejected { msg } => write!(f, "Verification key rejected {:?}", msg),
+            ThesisError::CredentialTypeRejected { credential_type } => write!(f, "Credential type {:?} not accepted", credential_type),
+            ThesisError::RateLimitTagRejected { msg } => write!(f, "Rate limit tag rejected {:?}", msg),
+            ThesisError::RateLimitExceeded { tag } => write!(f, "Rate limit exceeded, tag {:?} was already used", tag),
+            ThesisError::OneShowTagRejected { msg } => write!(f, "One-show tag rejected {:?}", msg),
+            ThesisError::TokenReused { serial, identity } => write!(f, "Token {:?} was already shown, identity {:?}", serial, identity),
+        }
+    }
+}
+
+impl ThesisError {
+    /// Stable code of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ThesisError::CoconutError { .. } => "CoconutError",
+            ThesisError::PSError { .. } => "PSError",
+            ThesisError::IssuanceError { .. } => "IssuanceError",
+            ThesisError::BackupError { .. } => "BackupError",
+            ThesisError::ShareError { .. } => "ShareError",
+            ThesisError::KeyStoreError { .. } => "KeyStoreError",
+            ThesisError::SerializationError { .. } => "SerializationError",
+            ThesisError::InvalidInput { .. } => "InvalidInput",
+            ThesisError::InvalidState { .. } => "InvalidState",
+            ThesisError::NotEnoughSignatures { .. } => "NotEnoughSignatures",
+            ThesisError::VerkeyRejected { .. } => "VerkeyRejected",
+            ThesisError::CredentialTypeRejected { .. } => "CredentialTypeRejected",
+            ThesisError::RateLimitTagRejected { .. } => "RateLimitTagRejected",
+            ThesisError::RateLimitExceeded { .. } => "RateLimitExceeded",
+            ThesisError::OneShowTagRejected { .. } => "OneShowTagRejected",
+            ThesisError::TokenReused { .. } => "TokenReused",
+        }
+    }
+}
+
+impl From<CoconutError> for ThesisError {
+    fn from(err: CoconutError) -> Self {
+        match err.kind() {
+            CoconutErrorKind::ValidationError { msg } => ThesisError::InvalidInput { msg },
+            kind => ThesisError::CoconutError { kind },
+        }
+    }
+}
+
+impl From<PSError> for ThesisError {
+    fn from(err: PSError) -> Self {
+        ThesisError::PSError { kind: err.into() }
+    }
+}
+
+impl From<IssuanceError> for ThesisError {
+    fn from(error: IssuanceError) -> Self {
+        ThesisError::IssuanceError { error }
+    }
+}
+
+impl From<BackupError> for ThesisError {
+    fn from(error: BackupError) -> Self {
+        ThesisError::BackupError { error }
+    }
+}
+
+impl From<ShareError> for ThesisError {
+    fn from(error: ShareError) -> Self {
+        ThesisError::ShareError { error }
+    }
+}
+
+impl From<KeyStoreError> for ThesisError {
+    fn from(error: KeyStoreError) -> Self {
+        ThesisError::KeyStoreError { error }
+    }
+}
+
+impl From<serde_json::Error> for ThesisError {
+    fn from(err: serde_json::Error) -> Self {
+        ThesisError::SerializationError {
+            msg: err.to_string(),
+        }
+    }
+}
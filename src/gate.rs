@@ -0,0 +1,189 @@
+// Credential-gated issuance.
+//
+// An IdP can require a presentation of a credential from another issuer before signing, e.g. a
+// government ID before a bank KYC credential. The client presents the other credential for the
+// session of its request and may carry hidden attributes of it over into the new credential. The
+// presentation and the request proof are made under one challenge, and a carried attribute gets
+// the same blinding in both, so equal responses show that the new ciphertext encrypts the hidden
+// attribute of the presented credential.
+
+use amcl_wrapper::field_elem::FieldElement;
+use coconut_sig::signature::{known_indices, transform_to_PS_params};
+use ps_sig::keys::{Params as PSParams, Verkey as PSVerkey};
+use ps_sig::signature::Signature;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::client::{ClientIdRequest, IssuanceSession};
+use crate::errors::{IssuanceError, ThesisError};
+use crate::js::Public as PublicParams;
+use crate::js_pok_sig::{JSPoKOfSignature, JSPoKOfSignatureProof};
+use crate::js_signature::JSMessages;
+use crate::verkey_distribution::verkey_id;
+
+/// Presentation of a credential of another issuer attached to a request
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GatePresentation {
+    /// Proof of possession of the credential, for the session of the request
+    pub proof: JSPoKOfSignatureProof,
+    /// Revealed attributes of the credential, by slot
+    pub revealed: BTreeMap<usize, String>,
+}
+
+/// A credential of another issuer held by the client, to present with a request. Made with
+/// `Client::gate_credential`.
+#[derive(Clone, Debug)]
+pub struct GateCredential {
+    pub(crate) credential: Signature,
+    pub(crate) vk: PSVerkey,
+    pub(crate) params: PSParams,
+    pub(crate) messages: JSMessages,
+    /// Slots of the credential that are revealed
+    pub revealed: HashSet<usize>,
+    /// Hidden attributes carried over, as pairs of the slot in this credential and in the new one
+    pub carried: Vec<(usize, usize)>,
+}
+
+impl GateCredential {
+    /// Start the presentation for `session`. Returns it with the blindings of the hidden messages
+    /// of the new request, which are shared with the carried attributes.
+    pub(crate) fn init(&self, session: &IssuanceSession) -> Result<(JSPoKOfSignature, Vec<FieldElement>), ThesisError> {
+        let new = session.messages();
+        let blindings = (0..new.hidden_count()).map(|_| FieldElement::random()).collect::<Vec<FieldElement>>();
+        let mut shared = HashMap::new();
+        for (from, to) in &self.carried {
+            let position = new.hidden_indices.iter().position(|i| i == to);
+            match position {
+                Some(k) if !self.revealed.contains(from)
+                    && *from < self.messages.count()
+                    && self.messages.all[*from] == new.all[*to] => {
+                    shared.insert(*from, blindings[k].clone());
+                }
+                _ => return Err(ThesisError::InvalidInput {
+                    msg: format!("Attribute {} cannot be carried over to slot {}", from, to),
+                }),
+            }
+        }
+        let presented_blindings = (0..self.messages.count())
+            .filter(|i| !self.revealed.contains(i))
+            .map(|i| shared.remove(&i).unwrap_or_else(FieldElement::random))
+            .collect::<Vec<FieldElement>>();
+        let pok = JSPoKOfSignature::init(
+            &self.credential,
+            &self.vk,
+            &self.params,
+            self.messages.all.as_slice().to_vec(),
+            Some(&presented_blindings),
+            self.revealed.clone(),
+            session.id(),
+        )?;
+        Ok((pok, blindings))
+    }
+
+    /// Attach the finished proof to `request`
+    pub(crate) fn attach(&self, request: &mut ClientIdRequest, mut proof: JSPoKOfSignatureProof) {
+        proof.key_id = Some(verkey_id(&self.vk));
+        let slots = self
+            .messages
+            .hidden_indices
+            .iter()
+            .zip(self.messages.hidden.iter())
+            .chain(known_indices(&self.messages.hidden_indices, self.messages.count()).iter().zip(self.messages.visible.iter()))
+            .filter(|(i, _)| self.revealed.contains(i))
+            .map(|(i, m)| (*i, m.clone()))
+            .collect::<BTreeMap<usize, String>>();
+        request.presentation = Some(GatePresentation { proof, revealed: slots });
+    }
+}
+
+/// Requirement of an IdP for a presentation of a credential of another issuer
+#[derive(Clone, Debug)]
+pub struct CredentialGate {
+    vk: PSVerkey,
+    params: PSParams,
+    carried: Vec<(usize, usize)>,
+    required: BTreeMap<usize, String>,
+}
+
+impl CredentialGate {
+    /// Require a credential issued under the aggregated verification key `vk` of the deployment
+    /// with params `params`, with the hidden attributes `carried` carried over. These are pairs of
+    /// the slot in the presented credential and in the new one.
+    pub fn new(params: &PublicParams, vk: PSVerkey, carried: Vec<(usize, usize)>) -> Self {
+        CredentialGate {
+            vk,
+            params: transform_to_PS_params(&params.cparams),
+            carried,
+            required: BTreeMap::new(),
+        }
+    }
+
+    /// Require the presented credential to reveal `value` in `slot`, e.g. its type attribute
+    pub fn with_revealed(mut self, slot: usize, value: &str) -> Self {
+        self.required.insert(slot, value.to_string());
+        self
+    }
+
+    /// Verify the presentation of `request` together with the request proof, and that the
+    /// carried attributes are hidden messages of the request equal to the presented ones
+    pub fn verify(&self, request: &ClientIdRequest, params: &PublicParams) -> Result<(), IssuanceError> {
+        let presentation = request.presentation.as_ref().ok_or(IssuanceError::PresentationRequired)?;
+        let proof = &presentation.proof;
+        let msg_count = self.vk.Y_tilde.len();
+        let key_matches = proof.key_id.as_ref().is_none_or(|key_id| *key_id == verkey_id(&self.vk));
+        let required_revealed = self
+            .required
+            .iter()
+            .all(|(slot, value)| presentation.revealed.get(slot) == Some(value));
+        if !key_matches
+            || !required_revealed
+            || proof.target_domain != request.session_id
+            || presentation.revealed.keys().any(|i| *i >= msg_count)
+        {
+            return Err(IssuanceError::InvalidPresentation);
+        }
+
+        // Positions of the carried attributes among the responses of both proofs. The responses of
+        // the presentation start with the one for t.
+        let hidden = &request.sig_req.hidden_indices;
+        let unrevealed = (0..msg_count).filter(|i| !presentation.revealed.contains_key(i)).collect::<Vec<usize>>();
+        let mut positions = vec![];
+        for (from, to) in &self.carried {
+            match (unrevealed.iter().position(|i| i == from), hidden.iter().position(|i| i == to)) {
+                (Some(p), Some(k)) => positions.push((p + 1, k)),
+                _ => return Err(IssuanceError::InvalidPresentation),
+            }
+        }
+
+        let cparams = params.type_params(&request.credential_type)?;
+        let revealed_msgs = presentation
+            .revealed
+            .iter()
+            .map(|(i, m)| (*i, FieldElement::from_msg_hash(m.as_bytes())))
+            .collect::<HashMap<usize, FieldElement>>();
+        let mut challenge_bytes = request.get_bytes_for_challenge(params, &cparams);
+        challenge_bytes.append(&mut proof.get_bytes_for_challenge(
+            presentation.revealed.keys().cloned().collect::<HashSet<usize>>(),
+            &self.vk,
+            &self.params,
+        ));
+        let challenge = FieldElement::from_msg_hash(&challenge_bytes);
+
+        request
+            .verify_with_challenge(params, &cparams, &challenge)
+            .map_err(|_| IssuanceError::InvalidRequestProof)?;
+        if !proof.verify(&self.vk, &self.params, revealed_msgs, &challenge).unwrap_or(false) {
+            return Err(IssuanceError::InvalidPresentation);
+        }
+
+        let presented = &proof.proof_vc.responses;
+        let new = &request.sig_req_proof.proof_commitment.responses;
+        let carried = positions
+            .iter()
+            .all(|(p, k)| *p < presented.len() && *k < new.len() && presented[*p] == new[*k]);
+        if carried {
+            Ok(())
+        } else {
+            Err(IssuanceError::InvalidPresentation)
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Identity tags for one credential per identity. The first hidden message of a request is the
+// client's link secret `s` and the client sends the tag `T = B^s` where the base `B` is hashed from
+// the deployment's tag scope and the credential type. The request proof shows that `T` was
+// computed from the same `s` that is in the commitment, so a client cannot pick a fresh tag for a
+// second credential of the same type with the same link secret.
+//
+// The link secret is an attribute like any other and the client chooses it, so a tag is only as
+// unique as that attribute. A client with two different link secrets gets two credentials.
+// Deployments that need one credential per person must bind the link secret to the person, e.g.
+// by carrying it over from an attested credential with a `CredentialGate`.
+//
+// IdPs keep the tags in a `TagRegistry`, together with the digest of the request a tag was first
+// seen in, see `ClientIdRequest::tag_digest`. All IdPs of a session get the identical request and
+// sign for the same tag; any other request with the tag is refused, even in the same session.
+
+use amcl_wrapper::group_elem::GroupElement;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::SignatureGroup;
+
+/// Base of the tags for credentials of `credential_type` under `scope`
+pub fn tag_base(scope: &str, credential_type: &str) -> SignatureGroup {
+    let bytes = [
+        b"tag : ".as_ref(),
+        scope.as_bytes(),
+        b" : ".as_ref(),
+        credential_type.as_bytes(),
+    ]
+    .concat();
+    SignatureGroup::from_msg_hash(&bytes)
+}
+
+/// Tags seen by an IdP. Implementations shared between IdPs must be safe to call concurrently.
+pub trait TagRegistry: fmt::Debug + Send + Sync {
+    /// Digest of the request the tag was registered for, if any
+    fn request_of(&self, tag: &str) -> Result<Option<String>, String>;
+
+    /// Register `tag` for the request with digest `request_digest`. Returns false if the tag is
+    /// already registered for a different request.
+    fn register(&self, tag: &str, request_digest: &str) -> Result<bool, String>;
+}
+
+/// Registry local to a single IdP process
+#[derive(Debug, Default)]
+pub struct MemoryTagRegistry {
+    tags: Mutex<HashMap<String, String>>,
+}
+
+impl MemoryTagRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TagRegistry for MemoryTagRegistry {
+    fn request_of(&self, tag: &str) -> Result<Option<String>, String> {
+        Ok(self.tags.lock().unwrap().get(tag).cloned())
+    }
+
+    fn register(&self, tag: &str, request_digest: &str) -> Result<bool, String> {
+        let mut tags = self.tags.lock().unwrap();
+        let digest = tags.entry(tag.to_string()).or_insert_with(|| request_digest.to_string());
+        Ok(digest.as_str() == request_digest)
+    }
+}
+
+/// Registry in a file shared by the IdPs of a deployment, e.g. on a shared volume. The file is
+/// append only with one `tag<TAB>request digest` record per line and is read again on every lookup, so
+/// tags registered by other IdPs are seen.
+#[derive(Debug)]
+pub struct FileTagRegistry {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileTagRegistry {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        FileTagRegistry {
+            path: path.as_ref().to_path_buf(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> io::Result<HashMap<String, String>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut tags = HashMap::new();
+        for line in contents.lines() {
+            let mut parts = line.splitn(2, '\t');
+            if let (Some(tag), Some(digest)) = (parts.next(), parts.next()) {
+                // The first record of a tag wins
+                tags.entry(tag.to_string()).or_insert_with(|| digest.to_string());
+            }
+        }
+        Ok(tags)
+    }
+}
+
+impl TagRegistry for FileTagRegistry {
+    fn request_of(&self, tag: &str) -> Result<Option<String>, String> {
+        let _guard = self.lock.lock().unwrap();
+        self.read().map(|tags| tags.get(tag).cloned()).map_err(|e| e.to_string())
+    }
+
+    fn register(&self, tag: &str, request_digest: &str) -> Result<bool, String> {
+        let _guard = self.lock.lock().unwrap();
+        if let Some(digest) = self.read().map_err(|e| e.to_string())?.get(tag) {
+            return Ok(digest == request_digest);
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| e.to_string())?;
+        writeln!(file, "{}\t{}", tag, request_digest).map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amcl_wrapper::field_elem::FieldElement;
+    use std::env;
+
+    #[test]
+    fn test_tag_is_deterministic() {
+        let s = FieldElement::from_msg_hash(b"link secret");
+        let tag_1 = &tag_base("idp", "id-card") * &s;
+        let tag_2 = &tag_base("idp", "id-card") * &s;
+        assert_eq!(tag_1, tag_2);
+        assert_ne!(tag_1, &tag_base("idp", "library-card") * &s);
+        assert_ne!(tag_1, &tag_base("other idp", "id-card") * &s);
+    }
+
+    fn check_registry(registry: &dyn TagRegistry) {
+        assert_eq!(registry.request_of("tag").unwrap(), None);
+        assert!(registry.register("tag", "request 1").unwrap());
+        // Other IdPs signing the same request
+        assert!(registry.register("tag", "request 1").unwrap());
+        // Another request with the tag, whatever its session
+        assert!(!registry.register("tag", "request 2").unwrap());
+        assert_eq!(registry.request_of("tag").unwrap(), Some(String::from("request 1")));
+    }
+
+    #[test]
+    fn test_memory_registry() {
+        check_registry(&MemoryTagRegistry::new());
+    }
+
+    #[test]
+    fn test_file_registry() {
+        let path = env::temp_dir().join(format!("tags-{}", crate::utils::random_id()));
+        check_registry(&FileTagRegistry::new(&path));
+        // A second IdP using the same file sees the tags
+        assert!(!FileTagRegistry::new(&path).register("tag", "request 2").unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use amcl_wrapper::field_elem::{FieldElement, FieldElementVector};
+use amcl_wrapper::group_elem::GroupElement;
+
+use crate::SignatureGroup;
+use crate::errors::IssuanceError;
+
+/// Attributes decided by the IdPs rather than the client: issuance date, expiry, serial and
+/// assurance level. They are signed as the last known messages of the credential.
+///
+/// All IdPs of a deployment share the same `IssuerAttributeSpec` (it is part of the public
+/// params) and derive the attributes deterministically from the issuance epoch and the session,
+/// so every IdP signs the same values. The client derives them as well since the values enter
+/// the commitment hash `h`, and checks the values returned by each IdP after unblinding.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IssuerAttributeSpec {
+    /// Length of an issuance epoch in seconds. The issuance date is the start of the epoch.
+    pub epoch_secs: u64,
+    /// Seconds from the issuance date until the credential expires
+    pub validity_secs: u64,
+    /// Number of epochs the requested issuance epoch may differ from an IdP's clock
+    pub epoch_tolerance: u64,
+    pub assurance_level: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IssuerAttributes {
+    pub issued_at: u64,
+    pub expires_at: u64,
+    pub serial: String,
+    pub assurance_level: String,
+}
+
+impl IssuerAttributeSpec {
+    pub fn new(epoch_secs: u64, validity_secs: u64, assurance_level: &str) -> Self {
+        IssuerAttributeSpec {
+            epoch_secs,
+            validity_secs,
+            epoch_tolerance: 1,
+            assurance_level: assurance_level.to_string(),
+        }
+    }
+
+    /// Bytes of the spec, for the params fingerprint
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.epoch_secs.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.validity_secs.to_be_bytes());
+        bytes.extend_from_slice(&self.epoch_tolerance.to_be_bytes());
+        bytes.extend_from_slice(&(self.assurance_level.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(self.assurance_level.as_bytes());
+        bytes
+    }
+
+    pub fn epoch_at(&self, now: u64) -> u64 {
+        now / self.epoch_secs.max(1)
+    }
+
+    /// Whether an IdP whose clock reads `now` accepts the requested issuance `epoch`
+    pub fn accepts_epoch(&self, epoch: u64, now: u64) -> bool {
+        let current = self.epoch_at(now);
+        current.abs_diff(epoch) <= self.epoch_tolerance
+    }
+
+    /// Derive the issuer attributes for a session. The serial is bound to the session id and the
+    /// session's ElGamal public key. An epoch whose dates do not fit in a `u64` is rejected.
+    pub fn derive(&self, epoch: u64, session_id: &str, elg_pk: &SignatureGroup) -> Result<IssuerAttributes, IssuanceError> {
+        let issued_at = epoch.checked_mul(self.epoch_secs);
+        let (issued_at, expires_at) = match (issued_at, issued_at.and_then(|t| t.checked_add(self.validity_secs))) {
+            (Some(issued_at), Some(expires_at)) => (issued_at, expires_at),
+            _ => return Err(IssuanceError::IssuanceEpochRejected { epoch: Some(epoch) }),
+        };
+        let mut serial_bytes = b"serial : ".to_vec();
+        serial_bytes.extend_from_slice(session_id.as_bytes());
+        serial_bytes.append(&mut elg_pk.to_bytes());
+        Ok(IssuerAttributes {
+            issued_at,
+            expires_at,
+            serial: FieldElement::from_msg_hash(&serial_bytes).to_hex(),
+            assurance_level: self.assurance_level.clone(),
+        })
+    }
+}
+
+impl IssuerAttributes {
+    /// Number of messages the issuer attributes take in a credential
+    pub const COUNT: usize = 4;
+
+    pub fn to_strings(&self) -> Vec<String> {
+        vec![
+            self.issued_at.to_string(),
+            self.expires_at.to_string(),
+            self.serial.clone(),
+            self.assurance_level.clone(),
+        ]
+    }
+
+    pub fn hashed(&self) -> FieldElementVector {
+        self.to_strings()
+            .iter()
+            .map(|s| FieldElement::from_msg_hash(s.as_bytes()))
+            .collect::<Vec<FieldElement>>()
+            .into()
+    }
+
+    /// Whether the known messages of a request end with these attributes
+    pub fn matches(&self, known_messages: &FieldElementVector) -> bool {
+        let hashed = self.hashed();
+        if known_messages.len() < hashed.len() {
+            return false;
+        }
+        let offset = known_messages.len() - hashed.len();
+        (0..hashed.len()).all(|i| known_messages[offset + i] == hashed[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derivation_is_deterministic() {
+        let spec = IssuerAttributeSpec::new(86400, 365 * 86400, "substantial");
+        let elg_pk = SignatureGroup::random();
+        let epoch = spec.epoch_at(1_600_000_000);
+
+        let attrs_1 = spec.derive(epoch, "session", &elg_pk).unwrap();
+        let attrs_2 = spec.derive(epoch, "session", &elg_pk).unwrap();
+        assert_eq!(attrs_1, attrs_2);
+        assert_eq!(attrs_1.expires_at - attrs_1.issued_at, 365 * 86400);
+        assert_ne!(attrs_1.serial, spec.derive(epoch, "other session", &elg_pk).unwrap().serial);
+
+        let mut known: FieldElementVector = vec![FieldElement::random()].into();
+        known.append(&mut attrs_1.hashed());
+        assert!(attrs_1.matches(&known));
+        assert!(!spec.derive(epoch + 1, "session", &elg_pk).unwrap().matches(&known));
+
+        // Dates past the end of time are refused rather than wrapping around
+        assert_eq!(
+            spec.derive(u64::MAX / 86400, "session", &elg_pk).unwrap_err(),
+            IssuanceError::IssuanceEpochRejected { epoch: Some(u64::MAX / 86400) }
+        );
+    }
+
+    #[test]
+    fn test_epoch_tolerance() {
+        let spec = IssuerAttributeSpec::new(100, 1000, "low");
+        assert!(spec.accepts_epoch(10, 1050));
+        assert!(spec.accepts_epoch(11, 1050));
+        assert!(spec.accepts_epoch(9, 1050));
+        assert!(!spec.accepts_epoch(12, 1050));
+    }
+}
@@ -1,8 +1,15 @@
 
+use coconut_sig::errors::CoconutError;
+use coconut_sig::param_spec::ParamSpec;
 use coconut_sig::signature::Params as CParams;
+use coconut_sig::validation::{check_element, invalid, Validate};
 
 use crate::SignatureGroup;
-use crate::amcl_wrapper::group_elem::GroupElement;
+use crate::amcl_wrapper::group_elem::{GroupElement, GroupElementVector};
+use crate::attribute_count::AttributeLayout;
+use crate::credential_type::CredentialTypeRegistry;
+use crate::issuer_attributes::{IssuerAttributes, IssuerAttributeSpec};
+use sha2::{Digest, Sha256};
 
 /// Utility module to help with JS/WASM interfacing
 
@@ -14,21 +21,163 @@ pub struct Public {
     pub msg_count: usize,
     pub server_count: usize,
     pub threshold: usize,
+    /// How the generators were derived, see `verify_derivation`
+    pub spec: ParamSpec,
+    /// Attributes the IdPs add to every credential, if any
+    #[serde(default)]
+    pub issuer_attributes: Option<IssuerAttributeSpec>,
+    /// Scope of the identity tags sent with every request, if any
+    #[serde(default)]
+    pub tag_scope: Option<String>,
+    /// Whether credentials may have fewer attributes than `msg_count`, see `attribute_count`
+    #[serde(default)]
+    pub variable_attribute_count: bool,
+    /// Credential types and their schemas, if the deployment issues typed credentials
+    #[serde(default)]
+    pub credential_types: Option<CredentialTypeRegistry>,
 }
 
 impl Public {
     pub fn new(msg_count: usize, label: &[u8], threshold: usize, server_count: usize) -> Self {
-        let g = SignatureGroup::from_msg_hash(&[label, " : g".as_bytes()].concat());
-        let h = SignatureGroup::from_msg_hash(&[label, " : h".as_bytes()].concat());
-
-        let cparams = CParams::new(msg_count, label);
+        let spec = ParamSpec::new(msg_count, label);
+        let cparams = spec.params();
         Public {
+            g: cparams.g.clone(),
+            h: spec.signature_group_generator("h", None),
+            cparams,
+            server_count,
+            msg_count,
+            threshold,
+            spec,
+            issuer_attributes: None,
+            tag_scope: None,
+            variable_attribute_count: false,
+            credential_types: None,
+        }
+    }
+
+    /// Have the IdPs add the attributes described by `spec` to every credential. These take the
+    /// last `IssuerAttributes::COUNT` message slots.
+    pub fn with_issuer_attributes(mut self, spec: IssuerAttributeSpec) -> Self {
+        self.issuer_attributes = Some(spec);
+        self
+    }
+
+    /// Have clients send an identity tag under `scope` with every request, computed from the first
+    /// hidden message as the link secret. See `identity_tag`.
+    pub fn with_identity_tags(mut self, scope: &str) -> Self {
+        self.tag_scope = Some(scope.to_string());
+        self
+    }
+
+    /// Let credentials have fewer attributes than there are message slots. The unused slots are
+    /// padded and one slot holds the attribute count, see `AttributeLayout`.
+    pub fn with_variable_attribute_count(mut self) -> Self {
+        self.variable_attribute_count = true;
+        self
+    }
+
+    /// Issue only credentials of the types in `registry`. Requests are made with the generators of
+    /// their type and carry a type attribute, see `credential_type`.
+    pub fn with_credential_types(mut self, registry: CredentialTypeRegistry) -> Self {
+        self.credential_types = Some(registry);
+        self
+    }
+
+    /// Params a request for `credential_type` is made with. Without a registry all requests use
+    /// `cparams`.
+    pub fn type_params(&self, credential_type: &str) -> Result<CParams, CoconutError> {
+        match &self.credential_types {
+            Some(registry) => match registry.get(credential_type) {
+                Some(t) => Ok(self.spec.type_params(&t.id)),
+                None => Err(invalid(format!("Unknown credential type {:?}", credential_type))),
+            },
+            None => Ok(self.cparams.clone()),
+        }
+    }
+
+    /// Recompute all generators from the spec and check they are the ones in use, so no one knows
+    /// a discrete log relation between them.
+    pub fn verify_derivation(&self) -> Result<(), CoconutError> {
+        if self.spec.msg_count != self.msg_count {
+            return Err(invalid(format!("Spec for {} messages but msg_count {}", self.spec.msg_count, self.msg_count)));
+        }
+        self.spec.verify_derivation(&self.cparams)?;
+        if self.g != self.cparams.g || self.h != self.spec.signature_group_generator("h", None) {
+            return Err(invalid(String::from("Params are not derived from the spec")));
+        }
+        Ok(())
+    }
+
+    /// Fingerprint of all the params, hex encoded. Embedded in requests, blind signatures,
+    /// verification key shares and proofs so that parties using different parameters notice.
+    pub fn fingerprint(&self) -> String {
+        // Destructured so that a new field cannot be left out
+        let Public {
             g,
             h,
             cparams,
-            server_count,
             msg_count,
+            server_count,
             threshold,
+            spec,
+            issuer_attributes,
+            tag_scope,
+            variable_attribute_count,
+            credential_types,
+        } = self;
+        let fields = [
+            Some(spec.fingerprint(cparams)),
+            Some(g.to_bytes()),
+            Some(h.to_bytes()),
+            Some((*msg_count as u64).to_be_bytes().to_vec()),
+            Some((*server_count as u64).to_be_bytes().to_vec()),
+            Some((*threshold as u64).to_be_bytes().to_vec()),
+            issuer_attributes.as_ref().map(|spec| spec.to_bytes()),
+            tag_scope.as_ref().map(|scope| scope.as_bytes().to_vec()),
+            Some(vec![*variable_attribute_count as u8]),
+            credential_types.as_ref().map(|registry| registry.to_bytes()),
+        ];
+        let mut hasher = Sha256::new();
+        for field in fields.iter() {
+            match field {
+                Some(bytes) => {
+                    hasher.update([1]);
+                    hasher.update((bytes.len() as u64).to_be_bytes());
+                    hasher.update(bytes);
+                }
+                None => hasher.update([0]),
+            }
+        }
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+impl Validate for Public {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.g, "g")?;
+        check_element(&self.h, "h")?;
+        self.cparams.validate()?;
+        if self.msg_count != self.cparams.h.len() {
+            return Err(invalid(format!("msg_count {} but params for {} messages", self.msg_count, self.cparams.h.len())));
+        }
+        self.verify_derivation()?;
+        if self.threshold == 0 || self.threshold > self.server_count {
+            return Err(invalid(format!("Threshold {} not possible with {} servers", self.threshold, self.server_count)));
+        }
+        if let Some(spec) = &self.issuer_attributes {
+            if self.msg_count < IssuerAttributes::COUNT {
+                return Err(invalid(format!("Issuer attributes need {} messages", IssuerAttributes::COUNT)));
+            }
+            if spec.epoch_secs == 0 {
+                return Err(invalid(String::from("Issuance epochs of 0 seconds")));
+            }
         }
-    }   
-}
\ No newline at end of file
+        if self.variable_attribute_count && AttributeLayout::new(self).max_attributes() == 0 {
+            return Err(invalid(String::from("No message slot left for the attribute count")));
+        }
+        if let Some(registry) = &self.credential_types {
+            registry.check_layout(self)?;
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
 // Proof of knowledge of signature for signature from 2016 paper, CT-RSA 2016 (eprint 2015/525), section 6.2
 
+use coconut_sig::errors::CoconutError;
+use coconut_sig::validation::{check_element, check_subgroup, Validate};
 use ps_sig::errors::PSError;
 use amcl_wrapper::field_elem::{FieldElement, FieldElementVector};
 use amcl_wrapper::group_elem::{GroupElement, GroupElementVector};
@@ -8,6 +10,8 @@ use ps_sig::signature::Signature;
 use std::collections::{HashMap, HashSet};
 
 use crate::{ate_2_pairing, VerkeyGroup, VerkeyGroupVec, SignatureGroup};
+use crate::one_show::OneShowTag;
+use crate::rate_limit::RateLimitTag;
 
 // Implement proof of knowledge of committed values in a vector commitment for `SignatureGroup`
 
@@ -65,6 +69,18 @@ pub struct JSPoKOfSignatureProof {
     pub phi: FieldElement,
     pub shared_randomness: SharedRandomness,
     pub target_domain: String,
+    /// Key id of the verification key the proof is made for
+    #[serde(default)]
+    pub key_id: Option<String>,
+    /// Type of the credential, whose type attribute is revealed
+    #[serde(default)]
+    pub credential_type: Option<String>,
+    /// Tag for RPs with a rate limit
+    #[serde(default)]
+    pub rate_limit_tag: Option<RateLimitTag>,
+    /// Serial number and double-spend equation of a one-show presentation
+    #[serde(default)]
+    pub one_show_tag: Option<OneShowTag>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -157,7 +173,7 @@ impl JSPoKOfSignature {
         let shared_randomness = SharedRandomness::random(4);
         let phi = shared_randomness.calculate_verification_phi(&target_domain.to_string());
 
-        let (exponents, J, committed) = Self::commit_for_pok(messages, blindings, &revealed_msg_indices, t, vk, params);
+        let (exponents, J, committed) = Self::commit_for_pok(messages, blindings, &revealed_msg_indices, t, vk, params)?;
 
 
         Ok(Self {
@@ -200,6 +216,10 @@ impl JSPoKOfSignature {
             phi: self.phi,
             shared_randomness: self.shared_randomness,
             target_domain: self.target_domain,
+            key_id: None,
+            credential_type: None,
+            rate_limit_tag: None,
+            one_show_tag: None,
         })
     }
 
@@ -256,7 +276,7 @@ impl JSPoKOfSignature {
     }
 
     pub(crate) fn commit_for_pok(messages: Vec<FieldElement>, mut blindings: Vec<Option<&FieldElement>>, revealed_msg_indices: &HashSet<usize>,
-                                 t: FieldElement, vk: &Verkey, params: &Params) -> (FieldElementVector, VerkeyGroup, ProverCommittedOtherGroup) {
+                                 t: FieldElement, vk: &Verkey, params: &Params) -> Result<(FieldElementVector, VerkeyGroup, ProverCommittedOtherGroup), PSError> {
         // +1 for `t`
         let hidden_msg_count = vk.Y_tilde.len() - revealed_msg_indices.len() + 2;
         let mut bases = VerkeyGroupVec::with_capacity(hidden_msg_count);
@@ -272,7 +292,9 @@ impl JSPoKOfSignature {
         }
 
         // Prove knowledge of m_1, m_2, ... for all hidden m_i and t in J = Y_tilde_1^m_1 * Y_tilde_2^m_2 * ..... * g_tilde^t
-        let J = bases.multi_scalar_mul_const_time(&exponents).unwrap();
+        let J = bases.multi_scalar_mul_const_time(&exponents).map_err(|_| PSError::GeneralError {
+            msg: format!("{} bases but {} exponents", bases.len(), exponents.len()),
+        })?;
 
         // For proving knowledge of messages in J.
         let mut committing = ProverCommittingOtherGroup::new();
@@ -281,7 +303,7 @@ impl JSPoKOfSignature {
         }
         let committed = committing.finish();
 
-        (exponents, J, committed)
+        Ok((exponents, J, committed))
     }
 }
 
@@ -329,12 +351,12 @@ impl JSPoKOfSignatureProof {
     /// Used when comparing message equality
     pub fn get_resp_for_message(&self, msg_idx: usize) -> Result<FieldElement, PSError> {
         // 1 element in self.proof_vc.responses is reserved for the random `t`
-        if msg_idx >= (self.proof_vc.responses.len() - 1) {
+        if msg_idx + 1 >= self.proof_vc.responses.len() {
             return Err(PSError::GeneralError {
                 msg: format!(
                     "Message index was given {} but should be less than {}",
                     msg_idx,
-                    self.proof_vc.responses.len() - 1
+                    self.proof_vc.responses.len().saturating_sub(1)
                 ),
             });
         }
@@ -352,6 +374,11 @@ impl JSPoKOfSignatureProof {
         if self.sig.is_identity() {
             return Ok(false);
         }
+        if revealed_msgs.keys().any(|i| *i >= vk.Y_tilde.len()) {
+            return Err(PSError::GeneralError {
+                msg: format!("Revealed message index should be less than {}", vk.Y_tilde.len()),
+            });
+        }
         // +1 for `t`
         // +1 for V_E2
         let hidden_msg_count = vk.Y_tilde.len() - revealed_msgs.len() + 1;
@@ -378,7 +405,9 @@ impl JSPoKOfSignatureProof {
                 b.push(vk.Y_tilde[i].clone());
                 e.push(m.clone());
             }
-            j += b.multi_scalar_mul_var_time(&e).unwrap();
+            j += b.multi_scalar_mul_var_time(&e).map_err(|_| PSError::GeneralError {
+                msg: String::from("Unequal number of revealed messages and generators"),
+            })?;
             &j
         };
 
@@ -397,6 +426,21 @@ impl JSPoKOfSignatureProof {
 
 
 
+impl Validate for JSPoKOfSignatureProof {
+    fn validate(&self) -> Result<(), CoconutError> {
+        self.sig.validate()?;
+        check_element(&self.J, "J")?;
+        check_subgroup(&self.proof_vc.commitment, "proof commitment")?;
+        if let Some(tag) = &self.rate_limit_tag {
+            tag.validate()?;
+        }
+        match &self.one_show_tag {
+            Some(tag) => tag.validate(),
+            None => Ok(()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::SignatureGroupVec;
@@ -1,42 +1,70 @@
 use amcl_wrapper::field_elem::{FieldElement, FieldElementVector};
-use amcl_wrapper::group_elem::{GroupElement, GroupElementVector};
-use coconut_sig::errors::CoconutError;
-use coconut_sig::signature::*;
+use coconut_sig::errors::{CoconutError, CoconutErrorKind};
+use coconut_sig::secret::zeroize_vector;
+use coconut_sig::signature::{check_hidden_indices, Params, SignatureRequest};
 
-use crate::{SignatureGroup, SignatureGroupVec};
+use zeroize::Zeroize;
+
+use crate::SignatureGroup;
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct JSMessages {
+    /// Slots of the hidden messages in increasing order, the visible messages take the others
+    pub hidden_indices: Vec<usize>,
     pub hashed_hidden: FieldElementVector,
     pub hashed_visible: FieldElementVector,
     pub hidden: Vec<String>,
     pub visible: Vec<String>,
+    /// All messages in slot order
     pub all: FieldElementVector,
 }
 
 impl JSMessages {
+    /// The hidden messages take the first slots
     pub fn new(hidden: Vec<String>, visible: Vec<String>) -> Self {
+        let hidden_indices = (0..hidden.len()).collect::<Vec<usize>>();
+        Self::build(hidden, visible, hidden_indices)
+    }
+
+    /// The hidden messages take the slots `hidden_indices`, in increasing order
+    pub fn with_hidden_indices(hidden: Vec<String>, visible: Vec<String>, hidden_indices: Vec<usize>) -> Result<Self, CoconutError> {
+        if hidden_indices.len() != hidden.len() {
+            return Err(CoconutErrorKind::ValidationError {
+                msg: format!("{} hidden indices for {} hidden messages", hidden_indices.len(), hidden.len()),
+            }.into());
+        }
+        check_hidden_indices(&hidden_indices, hidden.len() + visible.len())?;
+        Ok(Self::build(hidden, visible, hidden_indices))
+    }
+
+    fn build(hidden: Vec<String>, visible: Vec<String>, hidden_indices: Vec<usize>) -> Self {
         let hashed_hidden: FieldElementVector=
             hidden
-            .clone()
-            .into_iter()
+            .iter()
             .map(|x| FieldElement::from_msg_hash(x.as_bytes()))
             .collect::<Vec<FieldElement>>()
             .into();
 
         let hashed_visible: FieldElementVector= 
             visible
-            .clone()
-            .into_iter()
+            .iter()
             .map(|x| FieldElement::from_msg_hash(x.as_bytes()))
             .collect::<Vec<FieldElement>>()
             .into();
 
-        let mut all = hashed_hidden.clone();
-        all.append(&mut hashed_visible.clone());
+        let mut hidden_iter = hashed_hidden.iter();
+        let mut visible_iter = hashed_visible.iter();
+        let all: FieldElementVector = (0..hidden.len() + visible.len())
+            .map(|i| {
+                let next = if hidden_indices.contains(&i) { hidden_iter.next() } else { visible_iter.next() };
+                next.unwrap().clone()
+            })
+            .collect::<Vec<FieldElement>>()
+            .into();
 
         JSMessages {
+            hidden_indices,
             hashed_hidden,
             hashed_visible,
             hidden,
@@ -55,465 +83,84 @@ impl JSMessages {
     pub fn visible_count (&self) -> usize {
         self.visible.len()
     }
-}
-
-
- 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct HiddenMessages {
-    pub messages: FieldElementVector,
-}
-
-/// Created by entity requesting a signature
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct JSSignatureRequest {
-    pub known_messages: FieldElementVector,
-    pub commitment: SignatureGroup,
-    pub ciphertexts: Vec<(SignatureGroup, SignatureGroup)>,
-}
 
-impl JSSignatureRequest {
-    /// First `count_hidden` messages are hidden from signer and thus need to be encrypted using Elgamal.
-    /// "PrepareBlindSign" from paper.
-    pub fn new(
-        messages: JSMessages,
+    /// Request a blind signature over the messages, with the hidden messages encrypted to
+    /// `elgamal_pubkey`. "PrepareBlindSign" from paper.
+    pub fn signature_request(
+        &self,
         elgamal_pubkey: &SignatureGroup,
         params: &Params,
-    ) -> (Self, FieldElementVector) {
-        
-        let count_hidden = messages.hidden_count();
-        let count_messages = messages.count();
-        assert!(count_messages >= count_hidden);
-        assert_eq!(count_messages, params.h.len());
-
-        // Randomness for commitment and ciphertexts. Used to prove knowleddge later on
-        let mut randomness = FieldElementVector::with_capacity(count_hidden + 1);
-
-        // Commit to the hidden messages
-        let mut bases: SignatureGroupVec = params
-            .h
-            .iter()
-            .take(count_hidden)
-            .map(|g| g.clone())
-            .collect::<Vec<SignatureGroup>>()
-            .into();
-        bases.push(params.g.clone());
-        let mut exponents: FieldElementVector = messages.hashed_hidden.clone();
-
-        let r = FieldElement::random();
-        exponents.push(r.clone());
-        // commitment = h_1^m_1.h_2^m_2...h_count_hidden^m_count_hidden.g_1^r
-        let commitment = bases.multi_scalar_mul_const_time(&exponents).unwrap();
-
-        randomness.push(r);
-
-        // Each element of `ciphertexts` is the elgamal ciphertext and the randomness used during encryption.
-        // The randomness is used for proof of knowledge
-        let ciphertexts = if count_hidden > 0 {
-            let h = Self::compute_h(&commitment, &messages.hashed_visible.as_slice());
-                messages.hashed_hidden
-                .iter()
-                .map(|m| {
-                    let (c1, c2, k) = coconut_sig::elgamal_encrypt!(&params.g, elgamal_pubkey, &(&h * m));
-                    randomness.push(k);
-                    (c1, c2)
-                })
-                .collect::<Vec<(SignatureGroup, SignatureGroup)>>()
-        } else {
-            vec![]
-        };
-
-        (
-            Self {
-                known_messages: messages.hashed_visible,
-                commitment,
-                ciphertexts,
-            },
-            randomness,
-        )
-    }
-
-    /// Compute a generator in SignatureGroup by hashing commitment to hidden messages and all known messages.
-    /// It is important that the for computing h, all messages in the signature are taken into account to
-    /// prevent malleability.
-    pub fn compute_h(
-        commitment: &SignatureGroup,
-        known_messages: &[FieldElement],
-    ) -> SignatureGroup {
-        let mut bytes = commitment.to_bytes();
-        for m in known_messages {
-            bytes.append(&mut m.to_bytes());
-        }
-        SignatureGroup::from_msg_hash(&bytes)
-    }
-
-    pub fn h(&self) -> SignatureGroup {
-        Self::compute_h(&self.commitment, self.known_messages.as_slice())
+    ) -> Result<(SignatureRequest, FieldElementVector), CoconutError> {
+        SignatureRequest::new_with_hidden_indices(&self.all, &self.hidden_indices, elgamal_pubkey, params)
     }
 }
 
-/// Created by entity requesting a signature to prove knowledge of hidden elements used in SignatureRequest.
-/// Represents the commitment phase of Schnoor protocol
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct JSSignatureRequestPoK {
-    pub pok_vc_elgamal_sk: ProverCommittedSignatureGroup,
-    pub pok_vc_commitment: ProverCommittedSignatureGroup,
-    pub pok_vc_ciphertext: Vec<(ProverCommittedSignatureGroup, ProverCommittedSignatureGroup)>,
-}
-
-impl JSSignatureRequestPoK {
-    // Proof of knowledge using Schnorr protocol. There are multiple proof of knowledge protocols being done.
-    // 1 for knowledge of Elgamal secret key, 1 for knowledge of hidden messages and randomness in the
-    // commitment and 2 for each ciphertext. The protocol is broken down in 2 steps, pre-challenge and post challenge
-    // so that it can be used in combination with other protocols
-    // XXX Optimization idea: Since there are multiple Schnorr protocol executions resulting in a linear cost,
-    // the inner product argument protocol from Bulletproofs can be used to make the cost logarithmic.
-    pub fn init(
-        sig_req: &JSSignatureRequest,
-        elgamal_pk: &SignatureGroup,
-        params: &Params,
-    ) -> JSSignatureRequestPoK {
-        assert_eq!(
-            sig_req.known_messages.len() + sig_req.ciphertexts.len(),
-            params.h.len()
-        );
-
-        // For knowledge of Elgamal secret key
-        let mut committing_elgamal_sk = ProverCommittingSignatureGroup::new();
-        committing_elgamal_sk.commit(&params.g, None);
-        let committed_elgamal_sk = committing_elgamal_sk.finish();
-
-        // For knowledge of hidden messages and randomness in the commitment
-        let mut committing_comm = ProverCommittingSignatureGroup::new();
-        // Since the hidden messages are same inside this commitment and ciphertexts, same blinding needs to be used.
-        let mut hidden_msg_blindings = vec![];
-        for h in params.h.iter().take(sig_req.ciphertexts.len()) {
-            let b = FieldElement::random();
-            committing_comm.commit(h, Some(&b));
-            hidden_msg_blindings.push(b);
-        }
-        // For randomness
-        committing_comm.commit(&params.g, None);
-        let committed_comm = committing_comm.finish();
-
-        let ciphertext_commts = if sig_req.ciphertexts.len() > 0 {
-            // XXX: This computation can be avoided if h is persisted from `new`
-            let h = SignatureRequest::compute_h(&sig_req.commitment, sig_req.known_messages.as_slice());
-
-            let mut ciphertext_commts = vec![];
-            for i in 0..sig_req.ciphertexts.len() {
-                let mut committing_1 = ProverCommittingSignatureGroup::new();
-                committing_1.commit(&params.g, None);
-
-                let mut committing_2 = ProverCommittingSignatureGroup::new();
-                committing_2.commit(elgamal_pk, None);
-                // Use the same blinding for the hidden message used in the commitment
-                committing_2.commit(&h, Some(&hidden_msg_blindings[i]));
-                ciphertext_commts.push((committing_1.finish(), committing_2.finish()));
+/// Overwrite the hidden messages, such as the link secret, and their hashes, also in `all`
+impl Drop for JSMessages {
+    fn drop(&mut self) {
+        self.hidden.iter_mut().zeroize();
+        zeroize_vector(&mut self.hashed_hidden);
+        for i in &self.hidden_indices {
+            if *i < self.all.len() {
+                self.all[*i].zeroize();
             }
-            ciphertext_commts
-        } else {
-            vec![]
-        };
-
-        JSSignatureRequestPoK {
-            pok_vc_elgamal_sk: committed_elgamal_sk,
-            pok_vc_commitment: committed_comm,
-            pok_vc_ciphertext: ciphertext_commts,
         }
     }
-
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = vec![];
-        bytes.append(&mut self.pok_vc_elgamal_sk.to_bytes()); // append all gens + committment
-        bytes.append(&mut self.pok_vc_commitment.to_bytes()); //
-        for (pok_vc_1, pok_vc_2) in &self.pok_vc_ciphertext {
-            bytes.append(&mut pok_vc_1.to_bytes());
-            bytes.append(&mut pok_vc_2.to_bytes());
-        }
-        bytes
-    }
-
-    pub fn gen_proof(
-        self,
-        hidden_messages: &FieldElementVector,
-        randomness: FieldElementVector,
-        elgamal_sk: &FieldElement,
-        challenge: &FieldElement,
-    ) -> Result<JSSignatureRequestProof, CoconutError> {
-        assert_eq!(self.pok_vc_ciphertext.len(), hidden_messages.len());
-        assert_eq!(self.pok_vc_ciphertext.len(), randomness.len() - 1);
-
-        // Proof of knowledge of Elgamal secret key.
-        let proof_elgamal_sk = self
-            .pok_vc_elgamal_sk
-            .gen_proof(challenge, &[elgamal_sk.clone()])?;
-
-        let mut secrets_commitment = vec![];
-        for i in 0..hidden_messages.len() {
-            secrets_commitment.push(hidden_messages[i].clone());
-        }
-        secrets_commitment.push(randomness[0].clone());
-        let proof_commitment = self
-            .pok_vc_commitment
-            .gen_proof(challenge, &secrets_commitment)?;
-
-        let mut proof_ciphertexts = vec![];
-        for (i, (pok_vc_1, pok_vc_2)) in self.pok_vc_ciphertext.into_iter().enumerate() {
-            let proof_1 = pok_vc_1.gen_proof(challenge, &[randomness[i + 1].clone()])?;
-            let proof_2 = pok_vc_2.gen_proof(
-                challenge,
-                &[randomness[i + 1].clone(), hidden_messages[i].clone()],
-            )?;
-            proof_ciphertexts.push((proof_1, proof_2));
-        }
-        Ok(JSSignatureRequestProof {
-            proof_elgamal_sk,
-            proof_commitment,
-            proof_ciphertexts,
-        })
-    }
-}
-
-
-/// Created by entity requesting a signature to prove knowledge of hidden elements used in SignatureRequest.
-/// Represents the response phase of Schnoor protocol
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct JSSignatureRequestProof {
-    pub proof_elgamal_sk: ProofSignatureGroup,
-    pub proof_commitment: ProofSignatureGroup,
-    pub proof_ciphertexts: Vec<(ProofSignatureGroup, ProofSignatureGroup)>,
 }
 
 
-impl JSSignatureRequestProof {
-    pub fn get_bytes_for_challenge(
-        &self,
-        sig_req: &JSSignatureRequest,
-        elgamal_pk: &SignatureGroup,
-        params: &Params,
-    ) -> Vec<u8> {
-        let mut bytes = vec![];
-        let g_bytes = params.g.to_bytes();
-        bytes.extend_from_slice(&g_bytes);
-        bytes.append(&mut self.proof_elgamal_sk.commitment.to_bytes());
-
-        for h in params.h.iter().take(sig_req.ciphertexts.len()) {
-            bytes.append(&mut h.to_bytes());
-        }
-        bytes.extend_from_slice(&g_bytes);
-        bytes.append(&mut self.proof_commitment.commitment.to_bytes());
-
-        let h = sig_req.h();
-        let h_bytes = h.to_bytes();
-        for (pok_vc_1, pok_vc_2) in self.proof_ciphertexts.iter() {
-            bytes.extend_from_slice(&g_bytes);
-            bytes.append(&mut pok_vc_1.commitment.to_bytes());
-
-            bytes.append(&mut elgamal_pk.to_bytes());
-            bytes.extend_from_slice(&h_bytes);
-            bytes.append(&mut pok_vc_2.commitment.to_bytes());
-        }
-        bytes
-    }
-
-    pub fn verify(
-        &self,
-        sig_req: &JSSignatureRequest,
-        elgamal_pk: &SignatureGroup,
-        challenge: &FieldElement,
-        params: &Params,
-    ) -> Result<bool, CoconutError> {
-        assert_eq!(self.proof_ciphertexts.len(), sig_req.ciphertexts.len());
-        assert_eq!(
-            self.proof_commitment.responses.len(),
-            self.proof_ciphertexts.len() + 1
-        );
-
-        // Verify proof of knowledge of Elgamal secret key
-        if !self
-            .proof_elgamal_sk
-            .verify(&[params.g.clone()], elgamal_pk, challenge)?
-        {
-            return Ok(false);
-        }
-
-        // Verify proof of knowledge of hidden messages in the commitment
-        let mut bases = params
-            .h
-            .iter()
-            .take(sig_req.ciphertexts.len())
-            .map(|h| h.clone())
-            .collect::<Vec<SignatureGroup>>();
-        bases.push(params.g.clone());
-        if !self
-            .proof_commitment
-            .verify(&bases, &sig_req.commitment, challenge)?
-        {
-            return Ok(false);
-        }
-
-        // XXX: This computation can be avoided if h is persisted`
-        let h = JSSignatureRequest::compute_h(&sig_req.commitment, sig_req.known_messages.as_slice());
-        let bases = vec![elgamal_pk.clone(), h];
-        for (i, (proof_1, proof_2)) in self.proof_ciphertexts.iter().enumerate() {
-            // The response for the hidden message should be same as that in the commitment.
-            if proof_2.responses[1] != self.proof_commitment.responses[i] {
-                return Ok(false);
-            }
-
-            if !proof_1.verify(&[params.g.clone()], &sig_req.ciphertexts[i].0, challenge)? {
-                return Ok(false);
-            }
-            if !proof_2.verify(&bases, &sig_req.ciphertexts[i].1, challenge)? {
-                return Ok(false);
-            }
-        }
-        Ok(true)
-    }
-}
-
-/// Created by the signer
+ 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct JSBlindSignature {
-    pub h: SignatureGroup,
-    pub blinded: (SignatureGroup, SignatureGroup),
-}
-
-impl JSBlindSignature {
-    /// Signed creates a blinded signature. "BlindSign" from paper.
-    pub fn new(sig_request: &JSSignatureRequest, sigkey: &Sigkey) -> Self {
-        let hidden_msg_count = sig_request.ciphertexts.len();
-
-        assert_eq!(
-            hidden_msg_count + sig_request.known_messages.len(),
-            sigkey.y.len()
-        );
-
-        let h = JSSignatureRequest::compute_h(
-            &sig_request.commitment,
-            sig_request.known_messages.as_slice(),
-        );
-
-        // The blinded signature is (h, c_tilde).
-        // c_tilde = (a_1^y_1.a_2^y_2...a_hidden_msg_count^y_hidden_msg_count, b_1^y_1.b_2^y_2....b_hidden_msg_count^y_hidden_msg_count . h^(x + y_{hidden_msg_count+1}*m_{hidden_msg_count+1} + y_{hidden_msg_count+2}*m_{hidden_msg_count+2} + .. y_n*m_n))
-        // where each (a_i, b_i) forms an element in `sig_request.ciphertexts`
-
-        // c_tilde_1 = a_1^y_1.a_2^y_2...a_hidden_msg_count^y_hidden_msg_count
-        let mut c_tilde_1_bases = SignatureGroupVec::with_capacity(hidden_msg_count);
-        let mut c_tilde_1_exps = FieldElementVector::with_capacity(hidden_msg_count);
-
-        // c_tilde_2 = b_1^y_1.b_2^y_2....b_hidden_msg_count^y_hidden_msg_count . h^(x + y_{hidden_msg_count+1}*m_{hidden_msg_count+1} + y_{hidden_msg_count+2}*m_{hidden_msg_count+2} + .. y_n*m_n)
-        let mut c_tilde_2_bases = SignatureGroupVec::with_capacity(hidden_msg_count + 1);
-        let mut c_tilde_2_exps = FieldElementVector::with_capacity(hidden_msg_count + 1);
-
-        for (i, (a, b)) in sig_request.ciphertexts.iter().enumerate() {
-            c_tilde_1_bases.push(a.clone());
-            c_tilde_1_exps.push(sigkey.y[i].clone());
-
-            c_tilde_2_bases.push(b.clone());
-            c_tilde_2_exps.push(sigkey.y[i].clone());
-        }
-
-        // h^(x + y_j*m_j + y_{j+1}*m_{j+1}) for all known messages
-        c_tilde_2_bases.push(h.clone());
-        let mut exp = sigkey.x.clone();
-        let known_message_field_elements = &sig_request.known_messages;
-        for i in 0..known_message_field_elements.len() {
-            exp += &sigkey.y[hidden_msg_count + i] * &known_message_field_elements[i];
-        }
-        c_tilde_2_exps.push(exp);
-
-        let c_tilde_1 = c_tilde_1_bases
-            .multi_scalar_mul_const_time(&c_tilde_1_exps)
-            .unwrap();
-        let c_tilde_2 = c_tilde_2_bases
-            .multi_scalar_mul_const_time(&c_tilde_2_exps)
-            .unwrap();
-        Self {
-            h,
-            blinded: (c_tilde_1, c_tilde_2),
-        }
-    }
-
-    /// User unblinds the blinded signature received from a signer. "Unblind" from paper.
-    pub fn unblind(self, elgamal_sk: &FieldElement) -> Signature {
-        let a_sk = &self.blinded.0 * elgamal_sk;
-        let sigma_2 = &self.blinded.1 - &a_sk;
-        Signature {
-            sigma_1: self.h,
-            sigma_2,
-        }
-    }
+pub struct HiddenMessages {
+    pub messages: FieldElementVector,
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::HashSet;
     use super::*;
     use coconut_sig::keygen::{trusted_party_SSS_keygen};
+    use coconut_sig::signature::{BlindSignature, SignatureRequestPoK};
+    use coconut_sig::validation::Validate;
 
     #[test]
-    fn test_sign_verify_1() {
-        // Request signature from 1 threshold group of signers and form aggregate verkey from
-        // different threshold group of signers.
-        let threshold = 3;
-        let total = 6;
-        let msg_count = 6;
-        let count_hidden = 2;
-        let params = Params::new(msg_count, "test".as_bytes());
-        let (_, _, signers) = trusted_party_SSS_keygen(threshold, total, &params);
-
-        let msgs = (0..msg_count).map(|_| FieldElement::random()).collect::<Vec<FieldElement>>();
+    fn test_hidden_messages_in_any_slot() {
+        let params = Params::new(5, "test".as_bytes());
+        let (_, _, signers) = trusted_party_SSS_keygen(2, 3, &params);
+        let hidden = vec![String::from("secret"), String::from("link")];
+        let visible = vec![String::from("slot 0"), String::from("slot 2"), String::from("slot 4")];
+
+        assert!(JSMessages::with_hidden_indices(hidden.clone(), visible.clone(), vec![3, 1]).is_err());
+        assert!(JSMessages::with_hidden_indices(hidden.clone(), visible.clone(), vec![1, 5]).is_err());
+        let messages = JSMessages::with_hidden_indices(hidden, visible, vec![1, 3]).unwrap();
+        assert_eq!(messages.all[0], messages.hashed_visible[0]);
+        assert_eq!(messages.all[1], messages.hashed_hidden[0]);
+        assert_eq!(messages.all[3], messages.hashed_hidden[1]);
 
         let (elg_sk, elg_pk) = coconut_sig::elgamal_keygen!(&params.g);
+        let (sig_req, randomness) = messages.signature_request(&elg_pk, &params).unwrap();
+        assert_eq!(sig_req.known_indices(), vec![0, 2, 4]);
+        sig_req.validate().unwrap();
 
-        let (sig_req, randomness) = SignatureRequest::new(&FieldElementVector::from(msgs.as_slice()), count_hidden, &elg_pk, &params);
-
-        // Signers from which signature will be requested.
-        let mut signer_ids = HashSet::new();
-        signer_ids.insert(1);
-        signer_ids.insert(3);
-        signer_ids.insert(5);
-
-        let sig_req_pok = SignatureRequestPoK::init(&sig_req, &elg_pk, &params);
+        let sig_req_pok = SignatureRequestPoK::init(&sig_req, &elg_pk, &params).unwrap();
         let challenge = FieldElement::from_msg_hash(&sig_req_pok.to_bytes());
-        let hidden_msgs: FieldElementVector = msgs
-            .iter()
-            .take(count_hidden)
-            .map(|m| m.clone())
-            .collect::<Vec<FieldElement>>()
-            .into();
         let sig_req_proof = sig_req_pok
-            .gen_proof(&hidden_msgs, randomness, &elg_sk, &challenge)
+            .gen_proof(&messages.hashed_hidden, randomness, &elg_sk, &challenge)
             .unwrap();
+        assert_eq!(
+            challenge,
+            FieldElement::from_msg_hash(&sig_req_proof.get_bytes_for_challenge(&sig_req, &elg_pk, &params))
+        );
+        assert!(sig_req_proof.verify(&sig_req, &elg_pk, &challenge, &params).unwrap());
 
-        let mut blinded_sigs = vec![];
-        for i in &signer_ids {
-            assert!(sig_req_proof
-                .verify(&sig_req, &elg_pk, &challenge, &params)
-                .unwrap());
-            // Keys at index i have id i+1
-            blinded_sigs.push(BlindSignature::new(&sig_req, &signers[*i - 1].sigkey));
-        }
+        // Moving the hidden messages to other slots breaks the proof
+        let mut moved = sig_req.clone();
+        moved.hidden_indices = vec![0, 1];
+        assert!(!sig_req_proof.verify(&moved, &elg_pk, &challenge, &params).unwrap());
 
-        let mut unblinded_sigs = vec![];
-        for i in &signer_ids {
-            let unblinded_sig = blinded_sigs.remove(0).unblind(&elg_sk);
-            // Keys at index i have id i+1
-            assert!(unblinded_sig.verify(msgs.clone(), &signers[*i - 1].verkey, &params));
-            unblinded_sigs.push((signers[*i - 1].id, unblinded_sig));
+        for signer in &signers {
+            let blind_sig = BlindSignature::new(&sig_req, &signer.sigkey).unwrap();
+            let sig = blind_sig.unblind(&elg_sk);
+            assert!(sig.verify(messages.all.as_slice().to_vec(), &signer.verkey, &params));
         }
-
-        let aggr_sig = Signature::aggregate(threshold, unblinded_sigs);
-
-        let mut keys_to_aggr = vec![];
-        keys_to_aggr.push((signers[1].id, &signers[1].verkey));     // signer id is 2
-        keys_to_aggr.push((signers[3].id, &signers[3].verkey));     // signer id is 4
-        keys_to_aggr.push((signers[5].id, &signers[5].verkey));     // signer id is 6
-
-        let aggr_vk = Verkey::aggregate(threshold, keys_to_aggr);
-
-        assert!(aggr_sig.verify(msgs.clone(), &aggr_vk, &params));
     }
 }
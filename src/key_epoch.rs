@@ -0,0 +1,75 @@
+// Key epochs.
+//
+// Signing keys are rotated by dealing a new generation of signer shares. Every generation has a
+// key id, the id of its aggregated verification key (see `verkey_id`), and a validity window. The
+// IdPs stamp the key id into their blind signatures and the clients into their presentations, so
+// an RP holding the keys of several generations picks the right one. Windows of consecutive
+// generations overlap: IdPs sign with the newest key whose window has started, while RPs keep
+// accepting credentials of the previous generation until its window ends.
+
+use ps_sig::keys::Verkey as PSVerkey;
+
+use crate::verkey_distribution::verkey_id;
+
+/// Window in which a key is used, in seconds since the unix epoch. `not_after` is exclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validity {
+    pub not_before: u64,
+    pub not_after: Option<u64>,
+}
+
+impl Validity {
+    pub fn new(not_before: u64, not_after: Option<u64>) -> Self {
+        Validity { not_before, not_after }
+    }
+
+    pub fn contains(&self, now: u64) -> bool {
+        now >= self.not_before && self.not_after.is_none_or(|end| now < end)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.not_before.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.not_after.unwrap_or(u64::MAX).to_be_bytes());
+        bytes
+    }
+}
+
+/// One generation of signing keys
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyEpoch {
+    pub key_id: String,
+    pub validity: Validity,
+}
+
+impl KeyEpoch {
+    /// Epoch of the generation with aggregated verification key `vk`
+    pub fn new(vk: &PSVerkey, validity: Validity) -> Self {
+        KeyEpoch {
+            key_id: verkey_id(vk),
+            validity,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.key_id.len() as u64).to_be_bytes().to_vec();
+        bytes.extend_from_slice(self.key_id.as_bytes());
+        bytes.append(&mut self.validity.to_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validity() {
+        let validity = Validity::new(100, Some(200));
+        assert!(!validity.contains(99));
+        assert!(validity.contains(100));
+        assert!(validity.contains(199));
+        assert!(!validity.contains(200));
+        assert!(Validity::default().contains(0));
+        assert_ne!(Validity::new(0, None).to_bytes(), Validity::new(0, Some(0)).to_bytes());
+    }
+}
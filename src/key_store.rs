@@ -0,0 +1,531 @@
+// Storage of IdP signing shares.
+//
+// A `DistributedIdP` does not hold its `Signer` but asks a `KeyStore` to sign. Every stored key
+// has a key id and creation metadata so keys can be rotated and backed up without handling JSON
+// blobs by hand. The most recently imported key whose validity window has started is the active
+// key, so the key of the next epoch can be imported ahead of time.
+//
+// Three backends are provided: `MemoryKeyStore`, `FileKeyStore` which keeps the keys in a file
+// encrypted under a passphrase, and `MockPkcs11KeyStore` which behaves like a PKCS#11 token whose
+// keys cannot be extracted.
+
+use coconut_sig::errors::CoconutError;
+use coconut_sig::keygen::Signer;
+use coconut_sig::secret::SecretFieldElement;
+use coconut_sig::signature::{BlindSignature, SignatureRequest, Verkey};
+use amcl_wrapper::field_elem::FieldElement;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+use crate::backup::{self, BackupError};
+use crate::key_epoch::{KeyEpoch, Validity};
+use crate::utils::{now_secs, random_id};
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum KeyStoreError {
+    UnknownKey { key_id: String },
+    DuplicateKey { key_id: String },
+    NoActiveKey,
+    NotExtractable,
+    NotLoggedIn,
+    IncorrectPin,
+    BackupError { error: BackupError },
+    CoconutError { msg: String },
+    StorageError { msg: String },
+}
+
+impl fmt::Display for KeyStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyStoreError::UnknownKey { key_id } => write!(f, "No key with id {:?}", key_id),
+            KeyStoreError::DuplicateKey { key_id } => write!(f, "Key store already holds a key with id {:?}", key_id),
+            KeyStoreError::NoActiveKey => write!(f, "Key store holds no key"),
+            KeyStoreError::NotExtractable => write!(f, "Keys of this store cannot be exported"),
+            KeyStoreError::NotLoggedIn => write!(f, "Key store needs a login"),
+            KeyStoreError::IncorrectPin => write!(f, "Incorrect PIN"),
+            KeyStoreError::BackupError { error } => write!(f, "Encryption of the key store failed: {}", error),
+            KeyStoreError::CoconutError { msg } => write!(f, "Error from coconut crate {:?}", msg),
+            KeyStoreError::StorageError { msg } => write!(f, "Key store storage failed {:?}", msg),
+        }
+    }
+}
+
+impl std::error::Error for KeyStoreError {}
+
+impl From<BackupError> for KeyStoreError {
+    fn from(error: BackupError) -> Self {
+        KeyStoreError::BackupError { error }
+    }
+}
+
+impl From<CoconutError> for KeyStoreError {
+    fn from(err: CoconutError) -> Self {
+        KeyStoreError::CoconutError {
+            msg: err.to_string(),
+        }
+    }
+}
+
+impl From<io::Error> for KeyStoreError {
+    fn from(err: io::Error) -> Self {
+        KeyStoreError::StorageError {
+            msg: err.to_string(),
+        }
+    }
+}
+
+/// Description of a stored key. Contains no secrets.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyMetadata {
+    /// Key id of the epoch the key was dealt in, or a random id for keys without an epoch
+    pub key_id: String,
+    /// Id of the signer in the threshold setup
+    pub signer_id: usize,
+    /// Number of messages the key signs
+    pub msg_count: usize,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+    /// When the key may be used for signing
+    #[serde(default)]
+    pub validity: Validity,
+}
+
+impl KeyMetadata {
+    /// Epoch of the key, as published with its verification key share
+    pub fn epoch(&self) -> KeyEpoch {
+        KeyEpoch {
+            key_id: self.key_id.clone(),
+            validity: self.validity,
+        }
+    }
+}
+
+/// A signing share with its metadata, as imported into and exported from a store
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredKey {
+    pub metadata: KeyMetadata,
+    pub signer: Signer,
+}
+
+impl StoredKey {
+    /// Key with a fresh key id, created now
+    pub fn new(signer: Signer) -> Self {
+        StoredKey {
+            metadata: KeyMetadata {
+                key_id: random_id(),
+                signer_id: signer.id,
+                msg_count: signer.sigkey.y.len(),
+                created_at: now_secs(),
+                validity: Validity::default(),
+            },
+            signer,
+        }
+    }
+
+    /// Key dealt in `epoch`, with the epoch's key id and validity
+    pub fn in_epoch(signer: Signer, epoch: &KeyEpoch) -> Self {
+        let mut key = StoredKey::new(signer);
+        key.metadata.key_id = epoch.key_id.clone();
+        key.metadata.validity = epoch.validity;
+        key
+    }
+
+    /// Restore a key from a backup created with `KeyStore::export`
+    pub fn from_backup(data: &str, password: &str) -> Result<Self, KeyStoreError> {
+        Ok(backup::open(data, password)?)
+    }
+}
+
+pub trait KeyStore: fmt::Debug + Send + Sync {
+    /// Add `key` to the store. It becomes the active key. Key ids are unique within a store.
+    fn import(&self, key: StoredKey) -> Result<KeyMetadata, KeyStoreError>;
+
+    /// All keys, oldest first
+    fn list(&self) -> Result<Vec<KeyMetadata>, KeyStoreError>;
+
+    fn remove(&self, key_id: &str) -> Result<(), KeyStoreError>;
+
+    fn verkey(&self, key_id: &str) -> Result<Verkey, KeyStoreError>;
+
+    /// Blind signature share over `request` with the key `key_id`
+    fn blind_sign(&self, key_id: &str, request: &SignatureRequest) -> Result<BlindSignature, KeyStoreError>;
+
+    /// Backup of the key `key_id` encrypted under `password`
+    fn export(&self, key_id: &str, password: &str) -> Result<String, KeyStoreError>;
+
+    /// The most recently imported key that is valid now
+    fn active(&self) -> Result<KeyMetadata, KeyStoreError> {
+        let now = now_secs();
+        self.list()?
+            .into_iter()
+            .rev()
+            .find(|key| key.validity.contains(now))
+            .ok_or(KeyStoreError::NoActiveKey)
+    }
+}
+
+fn find<'a>(keys: &'a [StoredKey], key_id: &str) -> Result<&'a StoredKey, KeyStoreError> {
+    keys.iter()
+        .find(|key| key.metadata.key_id == key_id)
+        .ok_or_else(|| KeyStoreError::UnknownKey {
+            key_id: key_id.to_string(),
+        })
+}
+
+/// Refuse `key` if the store already holds a key with its id
+fn check_unique(keys: &[StoredKey], key: &StoredKey) -> Result<(), KeyStoreError> {
+    if keys.iter().any(|k| k.metadata.key_id == key.metadata.key_id) {
+        return Err(KeyStoreError::DuplicateKey {
+            key_id: key.metadata.key_id.clone(),
+        });
+    }
+    Ok(())
+}
+
+fn position(keys: &[StoredKey], key_id: &str) -> Result<usize, KeyStoreError> {
+    keys.iter()
+        .position(|key| key.metadata.key_id == key_id)
+        .ok_or_else(|| KeyStoreError::UnknownKey {
+            key_id: key_id.to_string(),
+        })
+}
+
+/// Keys held in memory
+#[derive(Debug, Default)]
+pub struct MemoryKeyStore {
+    keys: Mutex<Vec<StoredKey>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store holding only `key`
+    pub fn with_key(key: StoredKey) -> Self {
+        MemoryKeyStore {
+            keys: Mutex::new(vec![key]),
+        }
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn import(&self, key: StoredKey) -> Result<KeyMetadata, KeyStoreError> {
+        let mut keys = self.keys.lock().unwrap();
+        check_unique(&keys, &key)?;
+        let metadata = key.metadata.clone();
+        keys.push(key);
+        Ok(metadata)
+    }
+
+    fn list(&self) -> Result<Vec<KeyMetadata>, KeyStoreError> {
+        Ok(self.keys.lock().unwrap().iter().map(|key| key.metadata.clone()).collect())
+    }
+
+    fn remove(&self, key_id: &str) -> Result<(), KeyStoreError> {
+        let mut keys = self.keys.lock().unwrap();
+        let idx = position(&keys, key_id)?;
+        keys.remove(idx);
+        Ok(())
+    }
+
+    fn verkey(&self, key_id: &str) -> Result<Verkey, KeyStoreError> {
+        Ok(find(&self.keys.lock().unwrap(), key_id)?.signer.verkey.clone())
+    }
+
+    fn blind_sign(&self, key_id: &str, request: &SignatureRequest) -> Result<BlindSignature, KeyStoreError> {
+        let keys = self.keys.lock().unwrap();
+        Ok(BlindSignature::new(request, &find(&keys, key_id)?.signer.sigkey)?)
+    }
+
+    fn export(&self, key_id: &str, password: &str) -> Result<String, KeyStoreError> {
+        Ok(backup::seal(find(&self.keys.lock().unwrap(), key_id)?, password)?)
+    }
+}
+
+/// Keys kept in a file encrypted under a passphrase, in the format of `backup`. The file is read
+/// when the store is opened and rewritten on every change.
+pub struct FileKeyStore {
+    path: PathBuf,
+    passphrase: Zeroizing<String>,
+    keys: Mutex<Vec<StoredKey>>,
+}
+
+impl FileKeyStore {
+    /// Open the store at `path`, or start an empty one if there is no file yet
+    pub fn open<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Self, KeyStoreError> {
+        let keys = match fs::read_to_string(&path) {
+            Ok(contents) => backup::open(&contents, passphrase)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        Ok(FileKeyStore {
+            path: path.as_ref().to_path_buf(),
+            passphrase: Zeroizing::new(passphrase.to_string()),
+            keys: Mutex::new(keys),
+        })
+    }
+
+    /// Write the keys to a temporary file first so a failed write does not lose the store
+    fn write(&self, keys: &[StoredKey]) -> Result<(), KeyStoreError> {
+        let sealed = backup::seal(&keys, &self.passphrase)?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, sealed)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FileKeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileKeyStore")
+            .field("path", &self.path)
+            .field("keys", &self.keys)
+            .finish()
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn import(&self, key: StoredKey) -> Result<KeyMetadata, KeyStoreError> {
+        let mut keys = self.keys.lock().unwrap();
+        check_unique(&keys, &key)?;
+        let metadata = key.metadata.clone();
+        keys.push(key);
+        if let Err(e) = self.write(&keys) {
+            keys.pop();
+            return Err(e);
+        }
+        Ok(metadata)
+    }
+
+    fn list(&self) -> Result<Vec<KeyMetadata>, KeyStoreError> {
+        Ok(self.keys.lock().unwrap().iter().map(|key| key.metadata.clone()).collect())
+    }
+
+    fn remove(&self, key_id: &str) -> Result<(), KeyStoreError> {
+        let mut keys = self.keys.lock().unwrap();
+        let idx = position(&keys, key_id)?;
+        let removed = keys.remove(idx);
+        if let Err(e) = self.write(&keys) {
+            keys.insert(idx, removed);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn verkey(&self, key_id: &str) -> Result<Verkey, KeyStoreError> {
+        Ok(find(&self.keys.lock().unwrap(), key_id)?.signer.verkey.clone())
+    }
+
+    fn blind_sign(&self, key_id: &str, request: &SignatureRequest) -> Result<BlindSignature, KeyStoreError> {
+        let keys = self.keys.lock().unwrap();
+        Ok(BlindSignature::new(request, &find(&keys, key_id)?.signer.sigkey)?)
+    }
+
+    fn export(&self, key_id: &str, password: &str) -> Result<String, KeyStoreError> {
+        Ok(backup::seal(find(&self.keys.lock().unwrap(), key_id)?, password)?)
+    }
+}
+
+/// Stand-in for a PKCS#11 token, for testing deployments that keep IdP keys in an HSM. Keys can be
+/// imported but never read back: signing happens on the token, `export` is refused and every
+/// operation needs a session logged in with the user PIN.
+#[derive(Debug)]
+pub struct MockPkcs11KeyStore {
+    token_label: String,
+    pin: SecretFieldElement,
+    logged_in: AtomicBool,
+    keys: MemoryKeyStore,
+}
+
+impl MockPkcs11KeyStore {
+    pub fn new(token_label: &str, pin: &str) -> Self {
+        MockPkcs11KeyStore {
+            token_label: token_label.to_string(),
+            pin: SecretFieldElement::new(FieldElement::from_msg_hash(pin.as_bytes())),
+            logged_in: AtomicBool::new(false),
+            keys: MemoryKeyStore::new(),
+        }
+    }
+
+    pub fn token_label(&self) -> &str {
+        &self.token_label
+    }
+
+    pub fn login(&self, pin: &str) -> Result<(), KeyStoreError> {
+        if &FieldElement::from_msg_hash(pin.as_bytes()) != self.pin.expose_secret() {
+            return Err(KeyStoreError::IncorrectPin);
+        }
+        self.logged_in.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn logout(&self) {
+        self.logged_in.store(false, Ordering::SeqCst);
+    }
+
+    fn session(&self) -> Result<&MemoryKeyStore, KeyStoreError> {
+        if self.logged_in.load(Ordering::SeqCst) {
+            Ok(&self.keys)
+        } else {
+            Err(KeyStoreError::NotLoggedIn)
+        }
+    }
+}
+
+impl KeyStore for MockPkcs11KeyStore {
+    fn import(&self, key: StoredKey) -> Result<KeyMetadata, KeyStoreError> {
+        self.session()?.import(key)
+    }
+
+    fn list(&self) -> Result<Vec<KeyMetadata>, KeyStoreError> {
+        self.session()?.list()
+    }
+
+    fn remove(&self, key_id: &str) -> Result<(), KeyStoreError> {
+        self.session()?.remove(key_id)
+    }
+
+    fn verkey(&self, key_id: &str) -> Result<Verkey, KeyStoreError> {
+        self.session()?.verkey(key_id)
+    }
+
+    fn blind_sign(&self, key_id: &str, request: &SignatureRequest) -> Result<BlindSignature, KeyStoreError> {
+        self.session()?.blind_sign(key_id, request)
+    }
+
+    fn export(&self, _key_id: &str, _password: &str) -> Result<String, KeyStoreError> {
+        self.session()?;
+        Err(KeyStoreError::NotExtractable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::d_idp::DistributedIdP;
+    use crate::js::Public as PublicParams;
+    use crate::ttp::TrustedThirdParty;
+    use std::env;
+    use std::sync::Arc;
+
+    fn signers() -> Vec<Signer> {
+        let params = PublicParams::new(6, "test".as_bytes(), 3, 5);
+        let ttp = TrustedThirdParty::new(3, 5, &params).unwrap();
+        (0..2)
+            .map(|i| ttp.deserialize_server_i(&ttp.serialize_server_i(i).unwrap()).unwrap())
+            .collect()
+    }
+
+    fn check_store(store: &dyn KeyStore) {
+        let signers = signers();
+        assert_eq!(store.active().unwrap_err(), KeyStoreError::NoActiveKey);
+
+        let first = store.import(StoredKey::new(signers[0].clone())).unwrap();
+        assert_eq!(first.signer_id, signers[0].id);
+        assert_eq!(first.msg_count, 6);
+        assert_eq!(store.active().unwrap(), first);
+
+        // Rotation
+        let second = store.import(StoredKey::new(signers[1].clone())).unwrap();
+        assert_eq!(store.active().unwrap(), second);
+        assert_eq!(store.list().unwrap(), vec![first.clone(), second.clone()]);
+
+        // A second key under an id in use is refused
+        let mut duplicate = StoredKey::new(signers[0].clone());
+        duplicate.metadata.key_id = first.key_id.clone();
+        assert_eq!(
+            store.import(duplicate).unwrap_err(),
+            KeyStoreError::DuplicateKey { key_id: first.key_id.clone() }
+        );
+        assert_eq!(store.active().unwrap(), second);
+
+        // The key of the next epoch is imported ahead of time
+        let mut next = StoredKey::new(signers[1].clone());
+        next.metadata.validity = Validity::new(now_secs() + 3600, None);
+        let next = store.import(next).unwrap();
+        assert_eq!(store.active().unwrap(), second);
+        store.remove(&next.key_id).unwrap();
+        store.remove(&first.key_id).unwrap();
+        assert_eq!(store.list().unwrap(), vec![second.clone()]);
+        assert_eq!(
+            store.verkey(&first.key_id).unwrap_err(),
+            KeyStoreError::UnknownKey { key_id: first.key_id.clone() }
+        );
+    }
+
+    #[test]
+    fn test_memory_key_store() {
+        let store = MemoryKeyStore::new();
+        check_store(&store);
+
+        let active = store.active().unwrap();
+        let exported = store.export(&active.key_id, "hunter2").unwrap();
+        let restored = StoredKey::from_backup(&exported, "hunter2").unwrap();
+        assert_eq!(restored.metadata, active);
+        assert!(StoredKey::from_backup(&exported, "hunter3").is_err());
+    }
+
+    #[test]
+    fn test_file_key_store() {
+        let path = env::temp_dir().join(format!("keys-{}", random_id()));
+        let store = FileKeyStore::open(&path, "hunter2").unwrap();
+        check_store(&store);
+
+        // Reopening the file gives the same keys, but not with another passphrase
+        let reopened = FileKeyStore::open(&path, "hunter2").unwrap();
+        assert_eq!(reopened.list().unwrap(), store.list().unwrap());
+        assert_eq!(
+            FileKeyStore::open(&path, "hunter3").unwrap_err(),
+            KeyStoreError::BackupError { error: BackupError::Decryption }
+        );
+        assert!(!format!("{:?}", store).contains("hunter2"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_mock_pkcs11_key_store() {
+        let store = MockPkcs11KeyStore::new("idp-token", "1234");
+        assert_eq!(store.list().unwrap_err(), KeyStoreError::NotLoggedIn);
+        assert_eq!(store.login("0000").unwrap_err(), KeyStoreError::IncorrectPin);
+        store.login("1234").unwrap();
+        check_store(&store);
+
+        let active = store.active().unwrap();
+        assert_eq!(store.export(&active.key_id, "hunter2").unwrap_err(), KeyStoreError::NotExtractable);
+        store.logout();
+        assert_eq!(store.verkey(&active.key_id).unwrap_err(), KeyStoreError::NotLoggedIn);
+    }
+
+    #[test]
+    fn test_idp_key_rotation() {
+        let params = PublicParams::new(6, "test".as_bytes(), 3, 5);
+        let signers = signers();
+        let store = Arc::new(MemoryKeyStore::new());
+        store.import(StoredKey::new(signers[0].clone())).unwrap();
+
+        let mut idp = DistributedIdP::from_key_store(store.clone(), &params).unwrap();
+        let old_key = idp.key_id().to_string();
+        assert_eq!(idp.id(), signers[0].id);
+
+        let new_key = store.import(StoredKey::new(signers[1].clone())).unwrap();
+        idp.reload_key().unwrap();
+        assert_eq!(idp.key_id(), new_key.key_id);
+        assert_ne!(idp.key_id(), old_key);
+        assert_eq!(idp.id(), signers[1].id);
+
+        // Keys for other params are refused, also when rotated in
+        let other = PublicParams::new(4, "test".as_bytes(), 3, 5);
+        let other_ttp = TrustedThirdParty::new(3, 5, &other).unwrap();
+        let other_signer = other_ttp.deserialize_server_i(&other_ttp.serialize_server_i(0).unwrap()).unwrap();
+        store.import(StoredKey::new(other_signer)).unwrap();
+        assert!(idp.reload_key().is_err());
+        assert_eq!(idp.key_id(), new_key.key_id);
+        assert!(DistributedIdP::from_key_store(store, &params).is_err());
+    }
+}
@@ -11,9 +11,6 @@ extern crate ps_sig;
 
 extern crate rand;
 
-#[macro_use]
-extern crate failure;
-
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -24,19 +21,28 @@ extern crate wasm_bindgen;
 
 // Imports
 
-use ps_sig::{ate_2_pairing, VerkeyGroup, VerkeyGroupVec, SignatureGroup, SignatureGroupVec};
+use coconut_sig::validation::Validate;
+use ps_sig::{ate_2_pairing, VerkeyGroup, VerkeyGroupVec, SignatureGroup};
+#[cfg(test)]
+use ps_sig::SignatureGroupVec;
 use ps_sig::keys::Verkey as PSVerkey;
+use serde::de::DeserializeOwned;
 use std::collections::HashSet;
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
 // Crate Imports
 
 use crate::client::ClientIdRequest;
 use crate::client::ServerBlindSignature;
+use crate::errors::{IssuanceError, IssuanceRejection, ThesisError};
 use crate::js_pok_sig::JSPoKOfSignatureProof;
-use crate::js_signature::JSMessages;
 use crate::js::Public as PublicParams;
+use crate::key_epoch::Validity;
+use crate::renewal::RenewalRequest;
+use crate::share_transport::KeyPair;
 use crate::ttp::TrustedThirdParty;
+use crate::verkey_distribution::SignedVerkeyShare;
 
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -48,6 +54,45 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 // Serialization tools
 // TODO(jsarihan): move this to separate module
 
+/// Errors reach JavaScript as `Error`s with the message `<code>: <description>`
+impl From<ThesisError> for JsError {
+    fn from(err: ThesisError) -> Self {
+        JsError::new(&format!("{}: {}", err.code(), err))
+    }
+}
+
+/// Deserialize and validate, see `Validate`
+fn from_json<T: DeserializeOwned + Validate>(json: &str) -> Result<T, ThesisError> {
+    let value: T = serde_json::from_str(json)?;
+    value.validate()?;
+    Ok(value)
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, ThesisError> {
+    Ok(serde_json::to_string(value)?)
+}
+
+/// Decode a JS value through its JSON representation
+fn json_from_js<T: DeserializeOwned>(value: &JsValue) -> Result<T, ThesisError> {
+    let json = js_sys::JSON::stringify(value)
+        .ok()
+        .and_then(|json| json.as_string())
+        .ok_or_else(|| ThesisError::SerializationError {
+            msg: String::from("Value has no JSON representation"),
+        })?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+fn from_js<T: DeserializeOwned + Validate>(value: &JsValue) -> Result<T, ThesisError> {
+    let value: T = json_from_js(value)?;
+    value.validate()?;
+    Ok(value)
+}
+
+fn strings_from_js(value: &JsValue) -> Result<Vec<String>, ThesisError> {
+    json_from_js(value)
+}
+
 #[wasm_bindgen]
 pub struct SerializedTTP {
     params: PublicParams,
@@ -56,87 +101,202 @@ pub struct SerializedTTP {
 
 #[wasm_bindgen]
 impl SerializedTTP {
-    pub fn new (msg_count: usize, label: String, threshold: usize, total: usize) -> Self {
+    pub fn new (msg_count: usize, label: String, threshold: usize, total: usize) -> Result<SerializedTTP, JsError> {
         let params = PublicParams::new(msg_count, label.as_bytes(), threshold, total);
-        SerializedTTP {
+        Ok(SerializedTTP {
             params: params.clone(),
-            ttp: TrustedThirdParty::new(threshold, total, &params),
-        }
+            ttp: TrustedThirdParty::new(threshold, total, &params)?,
+        })
     }
 
-    pub fn from_public_params (jsparams: String) -> Self {
-        let params: PublicParams = serde_json::from_str(&jsparams).unwrap();
-        SerializedTTP {
+    pub fn from_public_params (jsparams: String) -> Result<SerializedTTP, JsError> {
+        let params: PublicParams = from_json(&jsparams)?;
+        Ok(SerializedTTP {
             params: params.clone(),
-            ttp: TrustedThirdParty::new(params.threshold, params.server_count, &params),
-        }
+            ttp: TrustedThirdParty::new(params.threshold, params.server_count, &params)?,
+        })
+    }
+
+    pub fn serialize_public_params (&self) -> Result<String, JsError> {
+        Ok(to_json(&self.params)?)
+    }
+
+    pub fn params_fingerprint (&self) -> String {
+        self.params.fingerprint()
     }
 
-    pub fn serialize_public_params (&self) -> Option<String> {
-        serde_json::to_string(&self.params).ok()
+    /// Deal the keys of this generation for use from `not_before` until `not_after`, in seconds
+    /// since the unix epoch. Call before distributing the keys.
+    pub fn set_validity (&mut self, not_before: u64, not_after: Option<u64>) {
+        self.ttp = self.ttp.clone().with_validity(Validity::new(not_before, not_after));
     }
 
-    pub fn serialize_server (&self, idx: usize) -> Option<String> {
-        self.ttp.serialize_server_i(idx)
+    /// Key id of the dealt generation of keys
+    pub fn key_id (&self) -> String {
+        self.ttp.key_epoch().key_id.clone()
+    }
+
+    pub fn serialize_dealer_key (&self) -> Result<String, JsError> {
+        Ok(to_json(self.ttp.dealer_public_key())?)
+    }
+
+    /// Keys of server `idx` encrypted to its serialized identity public key
+    pub fn encrypt_server (&self, idx: usize, recipient: String) -> Result<String, JsError> {
+        let recipient: SignatureGroup = from_json(&recipient)?;
+        Ok(self.ttp.encrypt_server_i(idx, &recipient)?)
+    }
+
+    pub fn serialize_server (&self, idx: usize) -> Result<String, JsError> {
+        self.ttp.serialize_server_i(idx).ok_or_else(|| {
+            ThesisError::InvalidInput {
+                msg: format!("No server {}", idx),
+            }
+            .into()
+        })
     }
 }
 
+/// Recompute serialized public parameters from their spec and return their fingerprint, to be
+/// compared out of band with the one of the other parties
+#[wasm_bindgen]
+pub fn verify_public_params (jsparams: String) -> Result<String, JsError> {
+    let params: PublicParams = from_json(&jsparams)?;
+    Ok(params.fingerprint())
+}
 
 #[wasm_bindgen]
 pub struct SerializedClient {
     client: client::Client,
     signature_shares: Vec<ServerBlindSignature>,
-    messages: Option<JSMessages>,
 }
 
 #[wasm_bindgen]
 impl SerializedClient {
-    pub fn new(jsparams: String, user_secret: String) -> Self {
-        let params: PublicParams = serde_json::from_str(&jsparams).unwrap();
+    pub fn new(jsparams: String, user_secret: String) -> Result<SerializedClient, JsError> {
+        let params: PublicParams = from_json(&jsparams)?;
         let signature_shares = vec![];
-        SerializedClient {
+        Ok(SerializedClient {
             client: client::Client::new(params, user_secret),
             signature_shares,
-            messages: None,
-        }
+        })
+    }
+
+    /// Restore a client from a password encrypted backup created with `export`
+    pub fn import(jsparams: String, backup: String, password: String) -> Result<SerializedClient, JsError> {
+        let params: PublicParams = from_json(&jsparams)?;
+        let client = client::Client::import(params, &backup, &password).map_err(ThesisError::from)?;
+        Ok(SerializedClient {
+            client,
+            signature_shares: vec![],
+        })
+    }
+
+    /// Password encrypted backup of the client secret, keys and credential
+    pub fn export(&self, password: String) -> Result<String, JsError> {
+        Ok(self.client.export(&password).map_err(ThesisError::from)?)
+    }
+
+    pub fn serialized_id_request(&mut self, visible: &JsValue, hidden: &JsValue) -> Result<String, JsError> {
+        let visible_msgs = strings_from_js(visible)?;
+        let hidden_msgs = strings_from_js(hidden)?;
+
+        let idRequest = self.client.request_id(visible_msgs, hidden_msgs)?;
+        Ok(to_json(&idRequest)?)
     }
 
-    pub fn serialized_id_request(&mut self, visible: &JsValue, hidden: &JsValue) -> String {
-        let visible_msgs: Vec<String> = visible.into_serde().unwrap();
-        let hidden_msgs: Vec<String> = hidden.into_serde().unwrap();
-        self.messages = Some(JSMessages::new(hidden_msgs.clone(), visible_msgs.clone()));
+    /// Like `serialized_id_request` with the hidden messages in the slots `hidden_indices`
+    pub fn serialized_id_request_with_hidden_indices(&mut self, visible: &JsValue, hidden: &JsValue, hidden_indices: &JsValue) -> Result<String, JsError> {
+        let options = client::RequestOptions {
+            hidden_indices: Some(json_from_js(hidden_indices)?),
+            ..client::RequestOptions::default()
+        };
+        let request = self.client.request_id_with_options(strings_from_js(visible)?, strings_from_js(hidden)?, options)?;
+        Ok(to_json(&request)?)
+    }
 
-        let idRequest = self.client.request_id(visible_msgs, hidden_msgs);
-        serde_json::to_string(&idRequest).unwrap()
+    /// Like `serialized_id_request` for a credential of the registered type `credential_type`
+    pub fn serialized_id_request_of_type(&mut self, visible: &JsValue, hidden: &JsValue, credential_type: String) -> Result<String, JsError> {
+        let options = client::RequestOptions {
+            credential_type,
+            ..client::RequestOptions::default()
+        };
+        let request = self.client.request_id_with_options(strings_from_js(visible)?, strings_from_js(hidden)?, options)?;
+        Ok(to_json(&request)?)
     }
 
-    pub fn deserialize_blind_signature(&mut self, js_sig: String) {
-        let blind_sig: ServerBlindSignature = serde_json::from_str(&js_sig).expect("Failed to deserialize blind signature");
-        &self.signature_shares.push(blind_sig);
+    /// Like `serialized_id_request` presenting the credential of `other`, a client of another
+    /// deployment, with the slots `revealed` revealed and the hidden attributes `carried`, pairs of
+    /// the slot in that credential and in the new one, carried over
+    pub fn serialized_id_request_with_presentation(&mut self, visible: &JsValue, hidden: &JsValue, other: &SerializedClient, revealed: &JsValue, carried: &JsValue) -> Result<String, JsError> {
+        let revealed: HashSet<usize> = json_from_js(revealed)?;
+        let carried: Vec<(usize, usize)> = json_from_js(carried)?;
+        let options = client::RequestOptions {
+            presentation: Some(other.client.gate_credential(revealed, carried)?),
+            ..client::RequestOptions::default()
+        };
+        let request = self.client.request_id_with_options(strings_from_js(visible)?, strings_from_js(hidden)?, options)?;
+        Ok(to_json(&request)?)
     }
 
-    pub fn verify_signatures(&mut self) {
-        &self.client.verify_signatures(self.signature_shares.clone());
+    /// Renewal request for a credential with the hidden attributes of the stored one and the new
+    /// visible attributes `visible`
+    pub fn serialized_renewal_request(&mut self, visible: &JsValue) -> Result<String, JsError> {
+        let renewal = self.client.request_renewal(strings_from_js(visible)?, client::RequestOptions::default())?;
+        Ok(to_json(&renewal)?)
     }
 
-    pub fn deserialize_blind_signatures(&mut self, js_sigs: &JsValue) {
-        let blind_sigs: Vec<ServerBlindSignature> = js_sigs.into_serde().unwrap();
-        &self.client.verify_signatures(blind_sigs);
+    pub fn deserialize_blind_signature(&mut self, js_sig: String) -> Result<(), JsError> {
+        let blind_sig: ServerBlindSignature = from_json(&js_sig)?;
+        self.signature_shares.push(blind_sig);
+        Ok(())
     }
 
-    pub fn serialized_id_proof(&mut self, domain: String) -> Option<String> {
+    pub fn verify_signatures(&mut self) -> Result<(), JsError> {
+        Ok(self.client.verify_signatures(self.signature_shares.clone())?)
+    }
+
+    pub fn deserialize_blind_signatures(&mut self, js_sigs: &JsValue) -> Result<(), JsError> {
+        let blind_sigs: Vec<ServerBlindSignature> = from_js(js_sigs)?;
+        Ok(self.client.verify_signatures(blind_sigs)?)
+    }
+
+    pub fn serialized_id_proof(&mut self, domain: String) -> Result<String, JsError> {
         // let revealed_msg_indices: HashSet<usize> = js_msg_indices.into_serde().unwrap();
-        if let Some(msgs) = self.messages.clone() {
-            let proof = &self.client.prove_id(msgs, HashSet::new(), &domain).unwrap();
-            serde_json::to_string(&proof).ok()
-        } else {
-            None
-        }
+        let msgs = self.client.messages().cloned().ok_or_else(|| ThesisError::InvalidState {
+            msg: String::from("No credential"),
+        })?;
+        let proof = self.client.prove_id(msgs, HashSet::new(), &domain)?;
+        Ok(to_json(&proof)?)
+    }
+
+    /// Like `serialized_id_proof` for an RP allowing `k` presentations per epoch of `epoch_secs`
+    /// seconds, using `counter`
+    pub fn serialized_limited_id_proof(&mut self, domain: String, k: usize, epoch_secs: u64, counter: usize) -> Result<String, JsError> {
+        let msgs = self.client.messages().cloned().ok_or_else(|| ThesisError::InvalidState {
+            msg: String::from("No credential"),
+        })?;
+        let limit = rate_limit::RateLimit::new(k, epoch_secs);
+        let proof = self.client.prove_id_limited(msgs, HashSet::new(), &domain, &limit, counter)?;
+        Ok(to_json(&proof)?)
+    }
+
+    /// Like `serialized_id_proof` showing a one-show credential of `scope`, with the identity
+    /// attribute and serial seed as the first two hidden messages
+    pub fn serialized_one_show_proof(&mut self, domain: String, scope: String) -> Result<String, JsError> {
+        let msgs = self.client.messages().cloned().ok_or_else(|| ThesisError::InvalidState {
+            msg: String::from("No credential"),
+        })?;
+        let proof = self.client.prove_one_show(msgs, HashSet::new(), &domain, &one_show::OneShowSpec::new(&scope))?;
+        Ok(to_json(&proof)?)
     }
 
-    pub fn serialize_ps_verkey(&self) -> Option<String> {
-        let vk = self.client.offer_ps_verkey();
-        serde_json::to_string(&vk.unwrap()).ok()
+    /// The verification key of the credential. RPs only accept it if they pinned it from the
+    /// shares published by the IdPs.
+    pub fn serialize_ps_verkey(&self) -> Result<String, JsError> {
+        let vk = self.client.offer_ps_verkey().ok_or_else(|| ThesisError::InvalidState {
+            msg: String::from("No credential"),
+        })?;
+        Ok(to_json(&vk)?)
     }
 }
 
@@ -154,7 +314,7 @@ mod tests {
         let public_params = js::Public::new(msg_count, &label.as_bytes(), threshold, total_server_count);
         let js_params = serde_json::to_string(public_params);
 
-        let client = SerializedClient::new(js_params, "yo");
+        let client = SerializedClient::new(js_params, String::from("cheese"));
         let visible_strings = vec!["these", "are", "all", "visible"];
         let visible_messages = visible_strings.iter().map(|&s| s.to_string()).collect::<Vec<String>>();
         let hidden_strings = vec!["not", "these"];
@@ -182,17 +342,82 @@ pub struct SerializedDistributedIdP {
 
 #[wasm_bindgen]
 impl SerializedDistributedIdP {
-    pub fn new(jsparams: String, signer: String) -> Self {
-        let params: PublicParams = serde_json::from_str(&jsparams).unwrap();
-        SerializedDistributedIdP {
-            idp: d_idp::DistributedIdP::from_serialized_signer(&signer, &params)
-        }
+    pub fn new(jsparams: String, signer: String) -> Result<SerializedDistributedIdP, JsError> {
+        let params: PublicParams = from_json(&jsparams)?;
+        Ok(SerializedDistributedIdP {
+            idp: d_idp::DistributedIdP::from_serialized_signer(&signer, &params)?
+        })
+    }
+
+    /// Load the IdP from a share encrypted by the dealer. `identity` is the serialized identity
+    /// key pair of this IdP and `dealer` the serialized public key of the dealer.
+    pub fn from_encrypted_share(jsparams: String, share: String, identity: String, dealer: String) -> Result<SerializedDistributedIdP, JsError> {
+        let params: PublicParams = from_json(&jsparams)?;
+        let identity: KeyPair = from_json(&identity)?;
+        let dealer: SignatureGroup = from_json(&dealer)?;
+        Ok(SerializedDistributedIdP {
+            idp: d_idp::DistributedIdP::from_encrypted_share(&share, &identity, &dealer, &params)?
+        })
+    }
+
+    /// Fresh serialized identity key pair for an IdP. The public key goes to the dealer, the
+    /// whole pair is kept secret by the IdP.
+    pub fn generate_identity_keys() -> Result<String, JsError> {
+        Ok(to_json(&KeyPair::new())?)
+    }
+
+    /// Serialized public key of a serialized identity key pair
+    pub fn identity_public_key(identity: String) -> Result<String, JsError> {
+        let identity: KeyPair = from_json(&identity)?;
+        Ok(to_json(&identity.pk)?)
+    }
+
+    /// Verification key share signed with the identity keys, for the RPs
+    pub fn publish_vk_share (&self) -> Result<String, JsError> {
+        Ok(to_json(&self.idp.publish_vk_share()?)?)
+    }
+
+    /// Require a presentation of a credential issued under the serialized aggregated verification
+    /// key `jsvk` of the deployment with params `jsparams`, with the hidden attributes `carried`
+    /// carried over
+    pub fn require_presentation (&mut self, jsparams: String, jsvk: String, carried: &JsValue) -> Result<(), JsError> {
+        let params: PublicParams = from_json(&jsparams)?;
+        let vk: PSVerkey = from_json(&jsvk)?;
+        let carried: Vec<(usize, usize)> = json_from_js(carried)?;
+        self.idp = self.idp.clone().with_credential_gate(gate::CredentialGate::new(&params, vk, carried));
+        Ok(())
+    }
+
+    /// Renew credentials issued under the serialized aggregated verification key `jsvk`
+    pub fn accept_renewal_key (&mut self, jsvk: String) -> Result<(), JsError> {
+        let vk: PSVerkey = from_json(&jsvk)?;
+        self.idp.accept_renewal_key(vk);
+        Ok(())
+    }
+
+    /// On rejection the message after the code is the JSON of an `IssuanceRejection`
+    pub fn blind_sign (&self, js_req: String) -> Result<String, JsError> {
+        let cir: ClientIdRequest = from_json(&js_req)?;
+        let signed = self.idp.verify_and_blind_sign(&cir).map_err(|error| self.rejection(error))?;
+        Ok(to_json(&signed)?)
     }
 
-    pub fn blind_sign (&self, js_req: String) -> Option<String> {
-        let cir: ClientIdRequest = serde_json::from_str(&js_req).unwrap();
-        let signed = self.idp.blind_sign(&cir.sig_req);
-        serde_json::to_string(&signed).ok()
+    /// Like `blind_sign` for a serialized `RenewalRequest`
+    pub fn renew (&self, js_req: String) -> Result<String, JsError> {
+        let renewal: RenewalRequest = from_json(&js_req)?;
+        let signed = self.idp.verify_and_renew(&renewal).map_err(|error| self.rejection(error))?;
+        Ok(to_json(&signed)?)
+    }
+}
+
+impl SerializedDistributedIdP {
+    fn rejection (&self, error: IssuanceError) -> JsError {
+        let rejection = IssuanceRejection { idp: self.idp.id(), error };
+        JsError::new(&format!(
+            "{}: {}",
+            ThesisError::from(rejection.error.clone()).code(),
+            serde_json::to_string(&rejection).unwrap_or_default()
+        ))
     }
 }
 
@@ -203,33 +428,93 @@ pub struct SerializedRelyingParty {
 
 #[wasm_bindgen]
 impl SerializedRelyingParty {
-    pub fn new (jsparams: String, domain: String) -> Self {
-        let params: PublicParams = serde_json::from_str(&jsparams).unwrap();
-        SerializedRelyingParty {
+    pub fn new (jsparams: String, domain: String) -> Result<SerializedRelyingParty, JsError> {
+        let params: PublicParams = from_json(&jsparams)?;
+        Ok(SerializedRelyingParty {
             rp: rp::RelyingParty::new(domain, params),
-        }
+        })
+    }
+
+    /// Accept verification key shares of signer `signer_id` signed with the serialized identity
+    /// public key `identity_key`
+    pub fn trust_idp (&mut self, signer_id: usize, identity_key: String) -> Result<(), JsError> {
+        let identity_key: SignatureGroup = from_json(&identity_key)?;
+        self.rp.trust_idp(signer_id, identity_key);
+        Ok(())
+    }
+
+    /// Accept credentials of the registered type `credential_type`. Once a type is accepted,
+    /// credentials of other types are refused.
+    pub fn accept_type (&mut self, credential_type: String) {
+        self.rp.accept_type(&credential_type);
+    }
+
+    /// Accept at most `k` presentations per credential and epoch of `epoch_secs` seconds
+    pub fn set_rate_limit (&mut self, k: usize, epoch_secs: u64) {
+        self.rp.set_rate_limit(rate_limit::RateLimit::new(k, epoch_secs));
+    }
+
+    /// Accept only one-show presentations of credentials of `scope`. A token shown twice is
+    /// refused with the identity attribute of its holder in the error.
+    pub fn set_one_show (&mut self, scope: String) -> Result<(), JsError> {
+        Ok(self.rp.set_one_show(one_show::OneShowSpec::new(&scope), Arc::new(one_show::SpentTokens::new()))?)
+    }
+
+    /// Aggregate the signed verification key shares published by the IdPs and pin the result.
+    /// Returns the key id.
+    pub fn pin_verification_key (&mut self, js_shares: &JsValue) -> Result<String, JsError> {
+        let shares: Vec<SignedVerkeyShare> = from_js(js_shares)?;
+        Ok(self.rp.pin_verification_key(shares)?)
+    }
+
+    /// Use a pinned key. Keys that were not pinned, such as one offered by a client, are refused.
+    pub fn set_verification_key (&mut self, jsvk: String) -> Result<(), JsError> {
+        let vk: PSVerkey = from_json(&jsvk)?;
+        Ok(self.rp.set_verification_key(vk)?)
+    }
+
+    pub fn select_verification_key (&mut self, key_id: String) -> Result<(), JsError> {
+        Ok(self.rp.select_verification_key(&key_id)?)
     }
 
-    pub fn set_verification_key (&mut self, jsvk: String) {
-        let vk: PSVerkey = serde_json::from_str(&jsvk).unwrap();
-        self.rp.set_verification_key(vk);
+    /// Accept proofs for the pinned key `key_id` only from `not_before` until `not_after`
+    pub fn set_key_validity (&mut self, key_id: String, not_before: u64, not_after: Option<u64>) -> Result<(), JsError> {
+        Ok(self.rp.set_key_validity(&key_id, Validity::new(not_before, not_after))?)
     }
 
     pub fn verify_id (
         &self,
         jsproof: String,
-    ) -> Option<bool> {
-        let id_proof: JSPoKOfSignatureProof = serde_json::from_str(&jsproof).unwrap();
-        self.rp.verify_id(id_proof)
+    ) -> Result<bool, JsError> {
+        let id_proof: JSPoKOfSignatureProof = from_json(&jsproof)?;
+        Ok(self.rp.verify_id(id_proof)?)
     }
 }
 
 // Public modules
 
 pub mod ttp;
+pub mod attestation;
+pub mod attribute_count;
+pub mod backup;
 pub mod client;
+pub mod credential_type;
 pub mod d_idp;
+pub mod errors;
+pub mod gate;
+pub mod identity_tag;
+pub mod issuer_attributes;
 pub mod rp;
 pub mod js;
 pub mod js_signature;
 pub mod js_pok_sig;
+pub mod key_epoch;
+pub mod key_store;
+pub mod one_show;
+pub mod policy;
+pub mod rate_limit;
+pub mod renewal;
+pub mod response_cache;
+pub mod share_transport;
+pub mod utils;
+pub mod verkey_distribution;
@@ -0,0 +1,287 @@
+// One-show tokens, for tickets and coupons. A one-show credential has two hidden attributes: the
+// holder's identity attribute `u` and a random serial seed `s`. A one-show presentation reveals
+// - the serial number `S = B^s`, where the base `B` is hashed from the scope of the tokens, so
+//   every show of the credential in the scope has the same serial number, and
+// - the double-spend equation `T = u + R * s`, where `R` is hashed from the randomized signature
+//   of the presentation and the verifier's domain, so it differs between shows.
+// One show hides `u` as `s` is random, but from the transcripts `(R1, T1)` and `(R2, T2)` of two
+// shows with the same serial number anyone can compute `s = (T1 - T2) / (R1 - R2)` and from it
+// `u = T1 - R1 * s`.
+//
+// Like every hidden attribute, `u` is chosen by the client and signed blindly by the IdPs, so a
+// double spend identifies whatever value the client put there, possibly junk. Deployments that
+// need to identify a person must bind `u` to them, e.g. by carrying it over with a
+// `CredentialGate` from a credential whose attributes the IdPs checked, see `attestation`.
+//
+// The presentation proves that `S` and `T` are computed from the attributes of the credential:
+// the proofs for them use the blindings of `u` and `s` from the proof of possession, so they are
+// verified with its responses. Verifiers keep the transcripts in `SpentTokens`, which may be
+// shared by all verifiers of a scope.
+
+use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::group_elem::GroupElement;
+use coconut_sig::errors::CoconutError;
+use coconut_sig::validation::{check_element, Validate};
+use ps_sig::signature::Signature;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::SignatureGroup;
+use crate::errors::ThesisError;
+use crate::utils::random_id;
+
+/// Slots of the identity attribute and serial seed of one-show credentials of a scope
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OneShowSpec {
+    pub scope: String,
+    pub identity_slot: usize,
+    pub serial_slot: usize,
+}
+
+impl OneShowSpec {
+    /// Spec with the identity attribute and serial seed in the first two slots, where the first
+    /// two hidden messages go by default
+    pub fn new(scope: &str) -> Self {
+        OneShowSpec {
+            scope: scope.to_string(),
+            identity_slot: 0,
+            serial_slot: 1,
+        }
+    }
+
+    /// Spec with the identity attribute and serial seed in the given slots, which must differ
+    pub fn with_slots(mut self, identity_slot: usize, serial_slot: usize) -> Result<Self, ThesisError> {
+        self.identity_slot = identity_slot;
+        self.serial_slot = serial_slot;
+        self.check_slots()?;
+        Ok(self)
+    }
+
+    /// Refuse a spec with the identity attribute as serial seed, as then `T = u + R * u` reveals
+    /// `u` from a single show
+    pub fn check_slots(&self) -> Result<(), ThesisError> {
+        if self.identity_slot == self.serial_slot {
+            return Err(ThesisError::InvalidInput {
+                msg: format!("Identity attribute and serial seed both in slot {}", self.serial_slot),
+            });
+        }
+        Ok(())
+    }
+
+    /// Base of the serial numbers
+    pub fn serial_base(&self) -> SignatureGroup {
+        let bytes = [b"one show : ".as_ref(), self.scope.as_bytes()].concat();
+        SignatureGroup::from_msg_hash(&bytes)
+    }
+}
+
+/// Fresh serial seed, to request a one-show credential with as a hidden attribute
+pub fn serial_seed() -> String {
+    random_id()
+}
+
+/// Hashed value of an identity attribute, as recovered from a reused token
+pub fn identity_value(identity: &str) -> FieldElement {
+    FieldElement::from_msg_hash(identity.as_bytes())
+}
+
+/// Challenge `R` of the double-spend equation of a presentation with randomized signature `sig`
+fn equation_challenge(serial: &SignatureGroup, sig: &Signature, domain: &str) -> FieldElement {
+    let mut bytes = serial.to_bytes();
+    bytes.append(&mut sig.to_bytes());
+    bytes.extend_from_slice(domain.as_bytes());
+    FieldElement::from_msg_hash(&bytes)
+}
+
+/// Serial number and double-spend equation of a one-show presentation
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OneShowTag {
+    pub serial: SignatureGroup,
+    /// Commitment for the proof of the serial number
+    pub serial_commitment: SignatureGroup,
+    /// Value `T` of the double-spend equation
+    pub equation: FieldElement,
+    /// Commitment for the proof of the double-spend equation
+    pub equation_commitment: FieldElement,
+}
+
+/// Commitment phase of the proof of a one-show tag
+pub struct OneShowTagPoK {
+    tag: OneShowTag,
+}
+
+impl OneShowTagPoK {
+    /// Commit to the tag of the presentation with randomized signature `sig` for `domain`. The
+    /// blindings must be those of the identity attribute and serial seed in the proof of
+    /// possession.
+    pub fn init(
+        spec: &OneShowSpec,
+        sig: &Signature,
+        domain: &str,
+        identity: &FieldElement,
+        identity_blinding: &FieldElement,
+        seed: &FieldElement,
+        seed_blinding: &FieldElement,
+    ) -> Self {
+        let base = spec.serial_base();
+        let serial = &base * seed;
+        let r = equation_challenge(&serial, sig, domain);
+        OneShowTagPoK {
+            tag: OneShowTag {
+                serial_commitment: &base * seed_blinding,
+                equation: identity + &(&r * seed),
+                equation_commitment: identity_blinding + &(&r * seed_blinding),
+                serial,
+            },
+        }
+    }
+
+    /// Bytes of the public elements for the challenge
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.tag.get_bytes_for_challenge()
+    }
+
+    /// The responses come from the proof of possession, so nothing is left to compute
+    pub fn gen_proof(self) -> OneShowTag {
+        self.tag
+    }
+}
+
+impl OneShowTag {
+    /// Bytes hashed with those of the presentation to get the challenge
+    pub fn get_bytes_for_challenge(&self) -> Vec<u8> {
+        let mut bytes = self.serial.to_bytes();
+        bytes.append(&mut self.serial_commitment.to_bytes());
+        bytes.append(&mut self.equation.to_bytes());
+        bytes.append(&mut self.equation_commitment.to_bytes());
+        bytes
+    }
+
+    pub fn serial_hex(&self) -> String {
+        self.serial.to_hex()
+    }
+
+    /// Transcript of the presentation with randomized signature `sig` for `domain`
+    pub fn transcript(&self, sig: &Signature, domain: &str) -> Transcript {
+        Transcript {
+            serial: self.serial_hex(),
+            r: equation_challenge(&self.serial, sig, domain),
+            t: self.equation.clone(),
+        }
+    }
+
+    /// Verify the tag of the presentation with randomized signature `sig` for `domain` under
+    /// `challenge`, given the responses for the identity attribute and serial seed in the proof of
+    /// possession
+    pub fn verify(
+        &self,
+        spec: &OneShowSpec,
+        sig: &Signature,
+        domain: &str,
+        challenge: &FieldElement,
+        identity_response: &FieldElement,
+        seed_response: &FieldElement,
+    ) -> bool {
+        let r = equation_challenge(&self.serial, sig, domain);
+        let serial_valid = self.serial_commitment == &(&spec.serial_base() * seed_response) + &(&self.serial * challenge);
+        let equation_valid = self.equation_commitment
+            == identity_response + &(&r * seed_response) + &(challenge * &self.equation);
+        !self.serial.is_identity() && serial_valid && equation_valid
+    }
+}
+
+impl Validate for OneShowTag {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.serial, "serial number")?;
+        check_element(&self.serial_commitment, "serial number commitment")
+    }
+}
+
+/// What a verifier keeps of a one-show presentation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub serial: String,
+    pub r: FieldElement,
+    pub t: FieldElement,
+}
+
+impl Transcript {
+    /// Identity attribute of the holder who made both this and `other`, which has the same serial
+    /// number. `None` if they are the same presentation.
+    pub fn identity(&self, other: &Transcript) -> Option<FieldElement> {
+        if self.serial != other.serial || self.r == other.r {
+            return None;
+        }
+        let seed = (&self.t - &other.t) * (&self.r - &other.r).inverse();
+        Some(&self.t - &(&self.r * &seed))
+    }
+}
+
+/// Transcripts of the tokens shown, by serial number. Share it between the verifiers of a scope.
+#[derive(Debug, Default)]
+pub struct SpentTokens {
+    spent: Mutex<HashMap<String, Transcript>>,
+}
+
+impl SpentTokens {
+    pub fn new() -> Self {
+        SpentTokens::default()
+    }
+
+    /// Record `transcript`. If its serial number was shown before the token is refused and the
+    /// error carries the identity attribute of the holder, as hex, when it can be computed.
+    pub fn spend(&self, transcript: Transcript) -> Result<(), ThesisError> {
+        let mut spent = self.spent.lock().unwrap();
+        match spent.get(&transcript.serial) {
+            Some(first) => Err(ThesisError::TokenReused {
+                serial: transcript.serial.clone(),
+                identity: first.identity(&transcript).map(|u| u.to_hex()),
+            }),
+            None => {
+                spent.insert(transcript.serial.clone(), transcript);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn transcript(&self, serial: &str) -> Option<Transcript> {
+        self.spent.lock().unwrap().get(serial).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_from_two_transcripts() {
+        let u = identity_value("alice");
+        let s = FieldElement::random();
+        let transcript = |r: FieldElement| Transcript {
+            serial: String::from("serial"),
+            t: &u + &(&r * &s),
+            r,
+        };
+        let first = transcript(FieldElement::random());
+        let second = transcript(FieldElement::random());
+        assert_eq!(first.identity(&second), Some(u.clone()));
+        assert_eq!(first.identity(&first), None);
+
+        let tokens = SpentTokens::new();
+        tokens.spend(first.clone()).unwrap();
+        assert_eq!(
+            tokens.spend(second).unwrap_err(),
+            ThesisError::TokenReused {
+                serial: String::from("serial"),
+                identity: Some(u.to_hex()),
+            }
+        );
+        assert_eq!(tokens.transcript("serial"), Some(first));
+    }
+
+    #[test]
+    fn test_slots_must_differ() {
+        assert!(OneShowSpec::new("scope").with_slots(2, 3).is_ok());
+        assert_eq!(OneShowSpec::new("scope").with_slots(2, 2).unwrap_err().code(), "InvalidInput");
+    }
+}
@@ -0,0 +1,303 @@
+// Declarative issuance policy of an IdP. Every request goes through the policy before it is
+// signed. Rejections are returned as `PolicyViolation`s, which serialize with a stable `code` so a
+// client can tell why an IdP refused to sign.
+//
+// The credential type of a request is only bound to the request proof and the credential by a
+// `CredentialTypeRegistry`. Without one it is a label the client picks, so a policy can only
+// allow types of a deployment with a registry and quotas then count all types together.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::client::ClientIdRequest;
+use crate::errors::ThesisError;
+use crate::js::Public as PublicParams;
+
+/// Constraint on the plaintext value of a visible attribute
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValueConstraint {
+    OneOf(Vec<String>),
+    NotEmpty,
+    MaxLength(usize),
+    Prefix(String),
+    /// Value must parse as an integer within `min..=max`
+    IntegerRange { min: i64, max: i64 },
+}
+
+impl ValueConstraint {
+    pub fn allows(&self, value: &str) -> bool {
+        match self {
+            ValueConstraint::OneOf(values) => values.iter().any(|v| v == value),
+            ValueConstraint::NotEmpty => !value.is_empty(),
+            ValueConstraint::MaxLength(max) => value.len() <= *max,
+            ValueConstraint::Prefix(prefix) => value.starts_with(prefix.as_str()),
+            ValueConstraint::IntegerRange { min, max } => match value.parse::<i64>() {
+                Ok(v) => v >= *min && v <= *max,
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributeConstraint {
+    pub index: usize,
+    pub constraint: ValueConstraint,
+}
+
+/// At most `max_issuances` credentials of a type for each value of the visible attribute at
+/// `identity_index`. Without a credential type registry, at most that many of all types.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityQuota {
+    pub identity_index: usize,
+    pub max_issuances: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IssuancePolicy {
+    /// Credential types this IdP issues. Empty allows every type. Needs a credential type
+    /// registry.
+    pub allowed_credential_types: Vec<String>,
+    /// Indices of visible attributes that must be present
+    pub required_attributes: Vec<usize>,
+    pub constraints: Vec<AttributeConstraint>,
+    pub max_hidden: Option<usize>,
+    pub quota: Option<IdentityQuota>,
+}
+
+/// Machine readable reason a request was refused by the policy
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "code")]
+pub enum PolicyViolation {
+    CredentialTypeNotAllowed { credential_type: String },
+    MissingAttribute { index: usize },
+    ConstraintViolated { index: usize, constraint: ValueConstraint },
+    TooManyHiddenAttributes { max: usize, given: usize },
+    QuotaExceeded { identity_index: usize, max_issuances: usize },
+}
+
+impl PolicyViolation {
+    /// Stable code of the violation, same as the `code` field of its serialization
+    pub fn code(&self) -> &'static str {
+        match self {
+            PolicyViolation::CredentialTypeNotAllowed { .. } => "CredentialTypeNotAllowed",
+            PolicyViolation::MissingAttribute { .. } => "MissingAttribute",
+            PolicyViolation::ConstraintViolated { .. } => "ConstraintViolated",
+            PolicyViolation::TooManyHiddenAttributes { .. } => "TooManyHiddenAttributes",
+            PolicyViolation::QuotaExceeded { .. } => "QuotaExceeded",
+        }
+    }
+}
+
+/// An `IssuancePolicy` together with the issuance counts needed for the quota
+#[derive(Clone, Debug, Default)]
+pub struct PolicyEngine {
+    policy: IssuancePolicy,
+    /// Whether the credential types are bound by a registry, so quotas can count per type
+    typed: bool,
+    issued: Arc<Mutex<HashMap<(String, String), usize>>>,
+}
+
+impl PolicyEngine {
+    /// Engine for `policy` in the deployment with `params`. A policy allowing only some types is
+    /// refused if `params` have no credential type registry.
+    pub fn new(policy: IssuancePolicy, params: &PublicParams) -> Result<Self, ThesisError> {
+        let typed = params.credential_types.is_some();
+        if !typed && !policy.allowed_credential_types.is_empty() {
+            return Err(ThesisError::InvalidInput {
+                msg: String::from("Allowed credential types need a credential type registry"),
+            });
+        }
+        Ok(PolicyEngine {
+            policy,
+            typed,
+            issued: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn policy(&self) -> &IssuancePolicy {
+        &self.policy
+    }
+
+    /// Check a request against the policy without counting it towards the quota
+    pub fn check(&self, request: &ClientIdRequest) -> Result<(), PolicyViolation> {
+        let policy = &self.policy;
+        if !policy.allowed_credential_types.is_empty()
+            && !policy.allowed_credential_types.contains(&request.credential_type)
+        {
+            return Err(PolicyViolation::CredentialTypeNotAllowed {
+                credential_type: request.credential_type.clone(),
+            });
+        }
+
+        for index in &policy.required_attributes {
+            if *index >= request.visible.len() {
+                return Err(PolicyViolation::MissingAttribute { index: *index });
+            }
+        }
+
+        for c in &policy.constraints {
+            let allowed = match request.visible.get(c.index) {
+                Some(value) => c.constraint.allows(value),
+                None => false,
+            };
+            if !allowed {
+                return Err(PolicyViolation::ConstraintViolated {
+                    index: c.index,
+                    constraint: c.constraint.clone(),
+                });
+            }
+        }
+
+        if let Some(max) = policy.max_hidden {
+            let given = request.sig_req.ciphertexts.len();
+            if given > max {
+                return Err(PolicyViolation::TooManyHiddenAttributes { max, given });
+            }
+        }
+
+        if let Some(key) = self.quota_key(request)? {
+            let quota = policy.quota.as_ref().unwrap();
+            let issued = self.issued.lock().unwrap();
+            if issued.get(&key).cloned().unwrap_or(0) >= quota.max_issuances {
+                return Err(Self::quota_exceeded(quota));
+            }
+        }
+        Ok(())
+    }
+
+    /// Count a request towards the quota. Called right before signing; fails if the quota was
+    /// used up in the meantime.
+    pub fn record(&self, request: &ClientIdRequest) -> Result<(), PolicyViolation> {
+        if let Some(key) = self.quota_key(request)? {
+            let quota = self.policy.quota.as_ref().unwrap();
+            let mut issued = self.issued.lock().unwrap();
+            let count = issued.entry(key).or_insert(0);
+            if *count >= quota.max_issuances {
+                return Err(Self::quota_exceeded(quota));
+            }
+            *count += 1;
+        }
+        Ok(())
+    }
+
+    /// Type and identity a request counts towards. An unbound type is left out, as the client
+    /// could pick a fresh one to reset the count.
+    fn quota_key(&self, request: &ClientIdRequest) -> Result<Option<(String, String)>, PolicyViolation> {
+        let credential_type = if self.typed {
+            request.credential_type.clone()
+        } else {
+            String::new()
+        };
+        match &self.policy.quota {
+            Some(quota) => match request.visible.get(quota.identity_index) {
+                Some(identity) => Ok(Some((credential_type, identity.clone()))),
+                None => Err(PolicyViolation::MissingAttribute {
+                    index: quota.identity_index,
+                }),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn quota_exceeded(quota: &IdentityQuota) -> PolicyViolation {
+        PolicyViolation::QuotaExceeded {
+            identity_index: quota.identity_index,
+            max_issuances: quota.max_issuances,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, RequestOptions};
+    use crate::credential_type::{AttributeSpec, CredentialType, CredentialTypeRegistry};
+
+    fn request(visible: Vec<&str>, credential_type: &str) -> ClientIdRequest {
+        let params = PublicParams::new(visible.len() + 2, "test".as_bytes(), 3, 5);
+        let mut client = Client::new(params, String::from("cheese"));
+        let options = RequestOptions {
+            credential_type: credential_type.to_string(),
+            ..RequestOptions::default()
+        };
+        client.request_id_with_options(
+            visible.iter().map(|s| s.to_string()).collect(),
+            vec![String::from("hidden"); 2],
+            options,
+        )
+        .unwrap()
+    }
+
+    /// Params with a credential type registry. The requests of the tests are not typed, which
+    /// the IdP would refuse before running the policy.
+    fn typed_params() -> PublicParams {
+        let registry = CredentialTypeRegistry::new()
+            .with_type(CredentialType::new("id-card", vec![AttributeSpec::visible("name")]));
+        PublicParams::new(2, "test".as_bytes(), 3, 5).with_credential_types(registry)
+    }
+
+    #[test]
+    fn test_policy_checks() {
+        let policy = IssuancePolicy {
+            allowed_credential_types: vec![String::from("id-card")],
+            required_attributes: vec![0, 1],
+            constraints: vec![AttributeConstraint {
+                index: 1,
+                constraint: ValueConstraint::IntegerRange { min: 18, max: 130 },
+            }],
+            max_hidden: Some(2),
+            quota: None,
+        };
+        let untyped = PublicParams::new(4, "test".as_bytes(), 3, 5);
+        assert_eq!(PolicyEngine::new(policy.clone(), &untyped).unwrap_err().code(), "InvalidInput");
+        let engine = PolicyEngine::new(policy, &typed_params()).unwrap();
+
+        assert!(engine.check(&request(vec!["alice", "30"], "id-card")).is_ok());
+        assert_eq!(
+            engine.check(&request(vec!["alice", "30"], "library-card")).unwrap_err().code(),
+            "CredentialTypeNotAllowed"
+        );
+        assert_eq!(
+            engine.check(&request(vec!["alice"], "id-card")).unwrap_err(),
+            PolicyViolation::MissingAttribute { index: 1 }
+        );
+        assert_eq!(
+            engine.check(&request(vec!["alice", "12"], "id-card")).unwrap_err().code(),
+            "ConstraintViolated"
+        );
+    }
+
+    #[test]
+    fn test_quota() {
+        let policy = IssuancePolicy {
+            quota: Some(IdentityQuota {
+                identity_index: 0,
+                max_issuances: 1,
+            }),
+            ..IssuancePolicy::default()
+        };
+        let engine = PolicyEngine::new(policy.clone(), &typed_params()).unwrap();
+
+        let req = request(vec!["alice", "30"], "id-card");
+        assert!(engine.check(&req).is_ok());
+        assert!(engine.record(&req).is_ok());
+        assert_eq!(engine.check(&req).unwrap_err().code(), "QuotaExceeded");
+        assert!(engine.record(&req).is_err());
+
+        // Quotas are per identity and per credential type
+        assert!(engine.check(&request(vec!["bob", "30"], "id-card")).is_ok());
+        assert!(engine.check(&request(vec!["alice", "30"], "library-card")).is_ok());
+
+        let json = serde_json::to_string(&engine.check(&req).unwrap_err()).unwrap();
+        assert!(json.contains("\"code\":\"QuotaExceeded\""));
+
+        // Without a registry the type is a label of the client and does not reset the quota
+        let engine = PolicyEngine::new(policy, &PublicParams::new(4, "test".as_bytes(), 3, 5)).unwrap();
+        assert!(engine.record(&req).is_ok());
+        assert_eq!(
+            engine.check(&request(vec!["alice", "30"], "library-card")).unwrap_err().code(),
+            "QuotaExceeded"
+        );
+    }
+}
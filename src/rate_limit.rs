@@ -0,0 +1,327 @@
+// k-times anonymous authentication. An RP with a rate limit lets every credential holder
+// authenticate at most `k` times per epoch while its presentations stay unlinkable. With every
+// presentation the client sends the tag `T = B^(1/(s + j))`, where `s` is its link secret, the
+// message in the secret slot of its credential, `j < k` a counter and the base `B` is hashed from
+// the RP's domain and the epoch. Tags of different counters, domains or epochs cannot be linked, so
+// the RP only learns when a tag repeats, i.e. when a holder authenticated more than `k` times.
+//
+// Along with the tag the client proves under the challenge of the presentation that
+// - `B = T^s * T^j`, with the response for `s` equal to the one for the link secret in the proof
+//   of possession of the credential, so the tag is well formed,
+// - `C = g^j * h^r` commits to the same `j`, and
+// - `C / g^i = h^r` for one `i < k`, with an OR proof over all `i`, so the counter is in range.
+
+use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::group_elem::GroupElement;
+use coconut_sig::errors::CoconutError;
+use coconut_sig::signature::{ProofSignatureGroup, ProverCommittedSignatureGroup, ProverCommittingSignatureGroup};
+use coconut_sig::validation::{check_element, invalid, Validate};
+
+use crate::SignatureGroup;
+use crate::errors::ThesisError;
+
+/// At most `k` presentations per epoch of `epoch_secs` seconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub k: usize,
+    pub epoch_secs: u64,
+    /// Slot of the link secret in the credentials
+    pub secret_slot: usize,
+}
+
+impl RateLimit {
+    /// Limit with the link secret in the first slot, where the first hidden message goes by default
+    pub fn new(k: usize, epoch_secs: u64) -> Self {
+        RateLimit {
+            k,
+            epoch_secs,
+            secret_slot: 0,
+        }
+    }
+
+    pub fn with_secret_slot(mut self, secret_slot: usize) -> Self {
+        self.secret_slot = secret_slot;
+        self
+    }
+
+    pub fn epoch_at(&self, now: u64) -> u64 {
+        now / self.epoch_secs.max(1)
+    }
+}
+
+/// Base of the tags for `domain` in `epoch`
+pub fn tag_base(domain: &str, epoch: u64) -> SignatureGroup {
+    let bytes = [
+        b"rate limit : ".as_ref(),
+        domain.as_bytes(),
+        b" : ".as_ref(),
+        &epoch.to_be_bytes(),
+    ]
+    .concat();
+    SignatureGroup::from_msg_hash(&bytes)
+}
+
+/// Generators of the commitment to the counter
+fn counter_generators() -> (SignatureGroup, SignatureGroup) {
+    (
+        SignatureGroup::from_msg_hash(b"rate limit : counter g"),
+        SignatureGroup::from_msg_hash(b"rate limit : counter h"),
+    )
+}
+
+/// Tag of a presentation with the proof that it is well formed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RateLimitTag {
+    pub epoch: u64,
+    pub tag: SignatureGroup,
+    /// Commitment to the counter
+    pub commitment: SignatureGroup,
+    /// Proof of knowledge of the link secret and counter of the tag
+    pub tag_proof: ProofSignatureGroup,
+    /// Proof of knowledge of the counter and randomness of the commitment
+    pub commitment_proof: ProofSignatureGroup,
+    /// OR proof that the commitment opens to a counter below `k`, one branch per counter value
+    pub range_commitments: Vec<SignatureGroup>,
+    pub range_challenges: Vec<FieldElement>,
+    pub range_responses: Vec<FieldElement>,
+}
+
+/// Commitment phase of the proof of a tag
+pub struct RateLimitTagPoK {
+    epoch: u64,
+    tag: SignatureGroup,
+    commitment: SignatureGroup,
+    secret: FieldElement,
+    counter: usize,
+    randomness: FieldElement,
+    tag_committed: ProverCommittedSignatureGroup,
+    commitment_committed: ProverCommittedSignatureGroup,
+    range_blinding: FieldElement,
+    range_commitments: Vec<SignatureGroup>,
+    range_challenges: Vec<FieldElement>,
+    range_responses: Vec<FieldElement>,
+}
+
+impl RateLimitTagPoK {
+    /// Commit to the tag of `counter` for `domain` in `epoch`. `secret_blinding` must be the
+    /// blinding of the link secret in the proof of possession.
+    pub fn init(
+        limit: &RateLimit,
+        domain: &str,
+        epoch: u64,
+        secret: &FieldElement,
+        secret_blinding: &FieldElement,
+        counter: usize,
+    ) -> Result<Self, ThesisError> {
+        if counter >= limit.k {
+            return Err(ThesisError::InvalidInput {
+                msg: format!("Counter must be below {}", limit.k),
+            });
+        }
+        let j = FieldElement::from(counter as u64);
+        let tag = tag_base(domain, epoch) * (secret + &j).inverse();
+        let (g, h) = counter_generators();
+        let randomness = FieldElement::random();
+        let commitment = &g * &j + &h * &randomness;
+
+        // The counter gets the same blinding in both proofs
+        let counter_blinding = FieldElement::random();
+        let mut committing = ProverCommittingSignatureGroup::new();
+        committing.commit(&tag, Some(secret_blinding));
+        committing.commit(&tag, Some(&counter_blinding));
+        let tag_committed = committing.finish();
+        let mut committing = ProverCommittingSignatureGroup::new();
+        committing.commit(&g, Some(&counter_blinding));
+        committing.commit(&h, None);
+        let commitment_committed = committing.finish();
+
+        // Branches of the other counter values are simulated
+        let range_blinding = FieldElement::random();
+        let mut range_commitments = vec![];
+        let mut range_challenges = vec![];
+        let mut range_responses = vec![];
+        for i in 0..limit.k {
+            if i == counter {
+                range_commitments.push(&h * &range_blinding);
+                range_challenges.push(FieldElement::zero());
+                range_responses.push(FieldElement::zero());
+            } else {
+                let (c, z) = (FieldElement::random(), FieldElement::random());
+                range_commitments.push(&h * &z + (&commitment - &g * &FieldElement::from(i as u64)) * &c);
+                range_challenges.push(c);
+                range_responses.push(z);
+            }
+        }
+
+        Ok(RateLimitTagPoK {
+            epoch,
+            tag,
+            commitment,
+            secret: secret.clone(),
+            counter,
+            randomness,
+            tag_committed,
+            commitment_committed,
+            range_blinding,
+            range_commitments,
+            range_challenges,
+            range_responses,
+        })
+    }
+
+    /// Bytes of the public elements for the challenge
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.epoch.to_be_bytes().to_vec();
+        bytes.append(&mut self.tag.to_bytes());
+        bytes.append(&mut self.commitment.to_bytes());
+        bytes.append(&mut self.tag_committed.to_bytes());
+        bytes.append(&mut self.commitment_committed.to_bytes());
+        for c in &self.range_commitments {
+            bytes.append(&mut c.to_bytes());
+        }
+        bytes
+    }
+
+    pub fn gen_proof(self, challenge: &FieldElement) -> Result<RateLimitTag, ThesisError> {
+        let j = FieldElement::from(self.counter as u64);
+        let tag_proof = self.tag_committed.gen_proof(challenge, &[self.secret.clone(), j.clone()])?;
+        let commitment_proof = self
+            .commitment_committed
+            .gen_proof(challenge, &[j, self.randomness.clone()])?;
+
+        // The challenge of the real branch is what the simulated ones leave of the challenge
+        let mut range_challenges = self.range_challenges;
+        let mut range_responses = self.range_responses;
+        let simulated = range_challenges
+            .iter()
+            .fold(FieldElement::zero(), |sum, c| sum + c);
+        let c = challenge - &simulated;
+        range_responses[self.counter] = &self.range_blinding - &(&c * &self.randomness);
+        range_challenges[self.counter] = c;
+
+        Ok(RateLimitTag {
+            epoch: self.epoch,
+            tag: self.tag,
+            commitment: self.commitment,
+            tag_proof,
+            commitment_proof,
+            range_commitments: self.range_commitments,
+            range_challenges,
+            range_responses,
+        })
+    }
+}
+
+impl RateLimitTag {
+    /// Bytes hashed with those of the presentation to get the challenge
+    pub fn get_bytes_for_challenge(&self) -> Vec<u8> {
+        let (g, h) = counter_generators();
+        let mut bytes = self.epoch.to_be_bytes().to_vec();
+        bytes.append(&mut self.tag.to_bytes());
+        bytes.append(&mut self.commitment.to_bytes());
+        bytes.append(&mut self.tag.to_bytes());
+        bytes.append(&mut self.tag.to_bytes());
+        bytes.append(&mut self.tag_proof.commitment.to_bytes());
+        bytes.append(&mut g.to_bytes());
+        bytes.append(&mut h.to_bytes());
+        bytes.append(&mut self.commitment_proof.commitment.to_bytes());
+        for c in &self.range_commitments {
+            bytes.append(&mut c.to_bytes());
+        }
+        bytes
+    }
+
+    /// The tag as hex, as kept by the RP
+    pub fn tag_hex(&self) -> String {
+        self.tag.to_hex()
+    }
+
+    /// Verify the tag for `domain` under `challenge`. `secret_response` is the response for the
+    /// link secret in the proof of possession.
+    pub fn verify(
+        &self,
+        limit: &RateLimit,
+        domain: &str,
+        challenge: &FieldElement,
+        secret_response: &FieldElement,
+    ) -> Result<bool, ThesisError> {
+        let k = limit.k;
+        if self.range_commitments.len() != k || self.range_challenges.len() != k || self.range_responses.len() != k {
+            return Ok(false);
+        }
+        let tag_valid = self
+            .tag_proof
+            .verify(&[self.tag.clone(), self.tag.clone()], &tag_base(domain, self.epoch), challenge)?
+            && self.tag_proof.responses[0] == *secret_response;
+
+        // The commitment is to the counter of the tag
+        let (g, h) = counter_generators();
+        let commitment_valid = self
+            .commitment_proof
+            .verify(&[g.clone(), h.clone()], &self.commitment, challenge)?
+            && self.commitment_proof.responses[0] == self.tag_proof.responses[1];
+
+        let challenges = self
+            .range_challenges
+            .iter()
+            .fold(FieldElement::zero(), |sum, c| sum + c);
+        let range_valid = challenges == *challenge
+            && (0..k).all(|i| {
+                let value = &self.commitment - &g * &FieldElement::from(i as u64);
+                self.range_commitments[i] == &h * &self.range_responses[i] + value * &self.range_challenges[i]
+            });
+
+        Ok(tag_valid && commitment_valid && range_valid)
+    }
+}
+
+impl Validate for RateLimitTag {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.tag, "rate limit tag")?;
+        check_element(&self.commitment, "counter commitment")?;
+        self.tag_proof.validate()?;
+        self.commitment_proof.validate()?;
+        if self.tag_proof.responses.len() != 2 || self.commitment_proof.responses.len() != 2 {
+            return Err(invalid(String::from("Rate limit proofs have the wrong number of responses")));
+        }
+        for c in &self.range_commitments {
+            check_element(c, "range commitment")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(limit: &RateLimit, secret: &FieldElement, counter: usize) -> (RateLimitTag, FieldElement, FieldElement) {
+        let blinding = FieldElement::random();
+        let pok = RateLimitTagPoK::init(limit, "hello.com", 7, secret, &blinding, counter).unwrap();
+        let challenge = FieldElement::from_msg_hash(&pok.to_bytes());
+        let response = &blinding - &(&challenge * secret);
+        (pok.gen_proof(&challenge).unwrap(), challenge, response)
+    }
+
+    #[test]
+    fn test_rate_limit_tag() {
+        let limit = RateLimit::new(3, 3600);
+        let secret = FieldElement::random();
+        let (first, challenge, response) = tag(&limit, &secret, 2);
+        first.validate().unwrap();
+        assert_eq!(FieldElement::from_msg_hash(&first.get_bytes_for_challenge()), challenge);
+        assert!(first.verify(&limit, "hello.com", &challenge, &response).unwrap());
+        assert!(!first.verify(&limit, "other.com", &challenge, &response).unwrap());
+        assert!(!first.verify(&limit, "hello.com", &challenge, &FieldElement::random()).unwrap());
+
+        // A tag repeats only for the same secret and counter
+        assert_eq!(tag(&limit, &secret, 2).0.tag, first.tag);
+        assert_ne!(tag(&limit, &secret, 1).0.tag, first.tag);
+        assert_ne!(tag(&limit, &FieldElement::random(), 2).0.tag, first.tag);
+
+        assert!(RateLimitTagPoK::init(&limit, "hello.com", 7, &secret, &FieldElement::random(), 3).is_err());
+        let mut forged = first.clone();
+        forged.range_challenges[0] = FieldElement::random();
+        assert!(!forged.verify(&limit, "hello.com", &challenge, &response).unwrap());
+    }
+}
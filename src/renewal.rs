@@ -0,0 +1,132 @@
+// Renewal of credentials.
+//
+// A client holding a credential asks for a fresh one with the same hidden attributes and new
+// visible ones, e.g. after a change of address or when the key generation of its credential
+// expires. Along with an ordinary request for the new credential it sends a proof of possession of
+// the current credential revealing only its visible attributes. Both proofs are made under one
+// challenge and use the same blinding for each hidden attribute, so the responses for a hidden
+// attribute are equal in both exactly when the hidden value is the same. The IdPs thus learn that
+// the hidden attributes carry over without learning them.
+
+use amcl_wrapper::field_elem::FieldElement;
+use coconut_sig::errors::CoconutError;
+use coconut_sig::signature::{check_hidden_indices, known_indices, transform_to_PS_params};
+use coconut_sig::validation::{invalid, Validate};
+use ps_sig::keys::Verkey as PSVerkey;
+use std::collections::{HashMap, HashSet};
+
+use crate::client::ClientIdRequest;
+use crate::errors::IssuanceError;
+use crate::js::Public as PublicParams;
+use crate::js_pok_sig::JSPoKOfSignatureProof;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RenewalRequest {
+    /// Request for the new credential
+    pub request: ClientIdRequest,
+    /// Proof of possession of the current credential, for the session of the request
+    pub possession: JSPoKOfSignatureProof,
+    /// Slots of the hidden attributes of the current credential. Must be those of the new one.
+    pub current_hidden_indices: Vec<usize>,
+    /// Visible attributes of the current credential, in slot order
+    pub current_visible: Vec<String>,
+}
+
+impl RenewalRequest {
+    /// Key id of the current credential
+    pub fn key_id (&self) -> Option<&String> {
+        self.possession.key_id.as_ref()
+    }
+
+    /// Verify the request for the new credential and the proof of possession of the current one,
+    /// issued under `vk`, and that the hidden attributes of both are the same. The current
+    /// credential must have its hidden attributes in the slots of the new one, and a typed
+    /// credential must reveal its type attribute.
+    pub fn verify (&self, params: &PublicParams, vk: &PSVerkey) -> Result<(), IssuanceError> {
+        let msg_count = vk.Y_tilde.len();
+        let hidden = &self.request.sig_req.hidden_indices;
+        // The layout of the current credential is not taken from the client, which could hide
+        // another slot than the one hidden at issuance and so move its revealed attributes
+        let t = match &params.credential_types {
+            Some(registry) => Some(registry.type_of(&self.request)?),
+            None => None,
+        };
+        let layout = t.map_or_else(|| hidden.clone(), |t| t.hidden_indices());
+        if self.current_hidden_indices != layout
+            || *hidden != layout
+            || layout.len() + self.current_visible.len() != msg_count
+        {
+            return Err(IssuanceError::MalformedRequest {
+                msg: String::from("Renewal does not keep the hidden attributes"),
+            });
+        }
+        check_hidden_indices(&layout, msg_count)?;
+        if self.possession.target_domain != self.request.session_id {
+            return Err(IssuanceError::InvalidPossessionProof);
+        }
+
+        let revealed = known_indices(&layout, msg_count);
+        // A typed credential is renewed as the same type. The type attribute is revealed by the
+        // proof of possession, so the proof only verifies if the credential carries it.
+        if let Some(t) = t {
+            let current = revealed
+                .iter()
+                .position(|i| *i == t.type_slot())
+                .and_then(|p| self.current_visible.get(p));
+            if current != Some(&t.type_attribute()) {
+                return Err(IssuanceError::MalformedRequest {
+                    msg: format!("Current credential is not of type {}", t.id),
+                });
+            }
+        }
+        let revealed_msgs = revealed
+            .iter()
+            .zip(self.current_visible.iter())
+            .map(|(i, v)| (*i, FieldElement::from_msg_hash(v.as_bytes())))
+            .collect::<HashMap<usize, FieldElement>>();
+        let ps_params = transform_to_PS_params(&params.cparams);
+        let cparams = params.type_params(&self.request.credential_type)?;
+
+        let mut challenge_bytes = self.request.get_bytes_for_challenge(params, &cparams);
+        challenge_bytes.append(&mut self.possession.get_bytes_for_challenge(
+            revealed.into_iter().collect::<HashSet<usize>>(),
+            vk,
+            &ps_params,
+        ));
+        let challenge = FieldElement::from_msg_hash(&challenge_bytes);
+
+        self.request
+            .verify_with_challenge(params, &cparams, &challenge)
+            .map_err(|_| IssuanceError::InvalidRequestProof)?;
+        if !self.possession.verify(vk, &ps_params, revealed_msgs, &challenge).unwrap_or(false) {
+            return Err(IssuanceError::InvalidPossessionProof);
+        }
+
+        // The possession proof's responses start with the one for t, the request's with the
+        // hidden messages
+        let current = &self.possession.proof_vc.responses;
+        let new = &self.request.sig_req_proof.proof_commitment.responses;
+        if current.len() == hidden.len() + 1
+            && new.len() >= hidden.len()
+            && (0..hidden.len()).all(|k| current[k + 1] == new[k])
+        {
+            Ok(())
+        } else {
+            Err(IssuanceError::InvalidPossessionProof)
+        }
+    }
+}
+
+impl Validate for RenewalRequest {
+    fn validate(&self) -> Result<(), CoconutError> {
+        self.request.validate()?;
+        self.possession.validate()?;
+        let hidden = self.request.sig_req.hidden_indices.len();
+        if self.possession.proof_vc.responses.len() != hidden + 1
+            || self.request.sig_req_proof.proof_commitment.responses.len() < hidden
+        {
+            return Err(invalid(String::from("Renewal proofs do not cover the hidden attributes")));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+// Response cache making issuance retry safe. A client that did not get a response resends the
+// identical request with the same request id and receives the share that was already issued,
+// without the request counting again towards quotas or single use evidence.
+//
+// A request id is reserved when it is first looked up, so a second copy of a request arriving
+// while the first is still being checked is refused instead of being signed twice.
+
+use amcl_wrapper::field_elem::FieldElement;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::client::{ClientIdRequest, ServerBlindSignature};
+use crate::errors::IssuanceError;
+
+/// Default number of seconds responses are kept
+pub const DEFAULT_RETENTION_SECS: u64 = 3600;
+
+#[derive(Clone, Debug)]
+struct CachedResponse {
+    digest: Vec<u8>,
+    /// `None` while the request is being checked and signed
+    response: Option<ServerBlindSignature>,
+    stored_at: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ResponseCache {
+    retention_secs: u64,
+    entries: Arc<Mutex<HashMap<String, CachedResponse>>>,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETENTION_SECS)
+    }
+}
+
+impl ResponseCache {
+    pub fn new(retention_secs: u64) -> Self {
+        ResponseCache {
+            retention_secs,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Digest of the whole request. A retry must be byte for byte the same request.
+    pub fn digest(request: &ClientIdRequest) -> Result<Vec<u8>, IssuanceError> {
+        let bytes = serde_json::to_vec(request).map_err(|e| IssuanceError::MalformedRequest {
+            msg: e.to_string(),
+        })?;
+        Ok(FieldElement::from_msg_hash(&bytes).to_bytes())
+    }
+
+    /// Look up the response to an earlier request with the same request id. A different request
+    /// reusing the id is rejected, as is a copy of a request that is still being signed. If the
+    /// id is new it is reserved until `store` or `release` is called for the request.
+    pub fn lookup(&self, request: &ClientIdRequest, now: u64) -> Result<Option<ServerBlindSignature>, IssuanceError> {
+        let digest = Self::digest(request)?;
+        let mut entries = self.entries.lock().unwrap();
+        self.prune(&mut entries, now);
+        match entries.get(&request.request_id) {
+            Some(cached) if cached.digest != digest => Err(IssuanceError::RequestIdConflict {
+                request_id: request.request_id.clone(),
+            }),
+            Some(cached) => cached.response.clone().map(Some).ok_or_else(|| IssuanceError::RequestInProgress {
+                request_id: request.request_id.clone(),
+            }),
+            None => {
+                entries.insert(
+                    request.request_id.clone(),
+                    CachedResponse {
+                        digest,
+                        response: None,
+                        stored_at: now,
+                    },
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Keep the response to `request` for its retries
+    pub fn store(&self, request: &ClientIdRequest, response: &ServerBlindSignature, now: u64) -> Result<(), IssuanceError> {
+        let digest = Self::digest(request)?;
+        let mut entries = self.entries.lock().unwrap();
+        self.prune(&mut entries, now);
+        entries.insert(
+            request.request_id.clone(),
+            CachedResponse {
+                digest,
+                response: Some(response.clone()),
+                stored_at: now,
+            },
+        );
+        Ok(())
+    }
+
+    /// Drop the reservation of a request that was refused, so a corrected retry can be checked
+    pub fn release(&self, request: &ClientIdRequest) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.get(&request.request_id).is_some_and(|cached| cached.response.is_none()) {
+            entries.remove(&request.request_id);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn prune(&self, entries: &mut HashMap<String, CachedResponse>, now: u64) {
+        let retention_secs = self.retention_secs;
+        entries.retain(|_, cached| now.saturating_sub(cached.stored_at) < retention_secs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::d_idp::DistributedIdP;
+    use crate::js::Public as PublicParams;
+    use crate::policy::{IdentityQuota, IssuancePolicy};
+    use crate::ttp::TrustedThirdParty;
+
+    fn setup() -> (PublicParams, DistributedIdP) {
+        let params = PublicParams::new(4, "test".as_bytes(), 1, 1);
+        let ttp = TrustedThirdParty::new(1, 1, &params).unwrap();
+        let signer = ttp.serialize_server_i(0).unwrap();
+        let policy = IssuancePolicy {
+            quota: Some(IdentityQuota {
+                identity_index: 0,
+                max_issuances: 1,
+            }),
+            ..IssuancePolicy::default()
+        };
+        let idp = DistributedIdP::from_serialized_signer(&signer, &params).unwrap().with_policy(policy).unwrap();
+        (params, idp)
+    }
+
+    #[test]
+    fn test_replay_returns_same_response() {
+        let (params, idp) = setup();
+        let mut client = Client::new(params, String::from("cheese"));
+        let request = client.request_id(vec![String::from("alice"); 2], vec![String::from("hidden"); 2]).unwrap();
+
+        let first = idp.verify_and_blind_sign(&request).unwrap();
+        // The quota allows a single issuance, the retry must not count towards it
+        let second = idp.verify_and_blind_sign(&request).unwrap();
+        assert_eq!(
+            serde_json::to_string(&first).unwrap(),
+            serde_json::to_string(&second).unwrap()
+        );
+        client.verify_signatures(vec![second]).unwrap();
+    }
+
+    #[test]
+    fn test_conflicting_request_id() {
+        let (params, idp) = setup();
+        let mut client = Client::new(params, String::from("cheese"));
+        let request = client.request_id(vec![String::from("alice"); 2], vec![String::from("hidden"); 2]).unwrap();
+        idp.verify_and_blind_sign(&request).unwrap();
+
+        let mut other = client.request_id(vec![String::from("bob"); 2], vec![String::from("hidden"); 2]).unwrap();
+        other.request_id = request.request_id.clone();
+        assert_eq!(
+            idp.verify_and_blind_sign(&other).unwrap_err(),
+            IssuanceError::RequestIdConflict {
+                request_id: request.request_id.clone(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_retention() {
+        let (params, idp) = setup();
+        let mut client = Client::new(params, String::from("cheese"));
+        let request = client.request_id(vec![String::from("alice"); 2], vec![String::from("hidden"); 2]).unwrap();
+        let response = idp.verify_and_blind_sign(&request).unwrap();
+
+        let cache = ResponseCache::new(10);
+        cache.store(&request, &response, 100).unwrap();
+        assert!(cache.lookup(&request, 109).unwrap().is_some());
+        assert!(cache.lookup(&request, 110).unwrap().is_none());
+        // The expired entry was replaced by a reservation
+        assert_eq!(cache.len(), 1);
+        cache.release(&request);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_copy_is_refused() {
+        let (params, _) = setup();
+        let mut client = Client::new(params, String::from("cheese"));
+        let request = client.request_id(vec![String::from("alice"); 2], vec![String::from("hidden"); 2]).unwrap();
+
+        let cache = ResponseCache::default();
+        assert!(cache.lookup(&request, 100).unwrap().is_none());
+        // A copy arriving while the first is being signed
+        assert_eq!(
+            cache.lookup(&request, 100).unwrap_err(),
+            IssuanceError::RequestInProgress {
+                request_id: request.request_id.clone(),
+            }
+        );
+        // A refused request can be retried
+        cache.release(&request);
+        assert!(cache.lookup(&request, 100).unwrap().is_none());
+    }
+}
@@ -1,16 +1,47 @@
 use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::group_elem::GroupElementVector;
 use coconut_sig::signature::{Verkey, transform_to_PS_params, transform_to_PS_verkey};
+use coconut_sig::validation::Validate;
 use ps_sig::keys::Verkey as PSVerkey;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
+use crate::SignatureGroup;
+use crate::errors::ThesisError;
 use crate::js_pok_sig::JSPoKOfSignatureProof;
 use crate::js::Public as PublicParams;
+use crate::key_epoch::Validity;
+use crate::one_show::{OneShowSpec, SpentTokens, Transcript};
+use crate::rate_limit::{RateLimit, RateLimitTag};
+use crate::utils::now_secs;
+use crate::verkey_distribution::{aggregate_checked, verkey_id, SignedVerkeyShare};
+
+/// Aggregated verification key an RP accepts while its validity window lasts
+#[derive(Clone, Debug)]
+struct PinnedKey {
+    vk: PSVerkey,
+    validity: Validity,
+}
 
 
 #[derive(Clone, Debug)]
 pub struct RelyingParty {
     domain: String,
+    /// Key for proofs that do not name their key
     vk: Option<PSVerkey>,
+    /// Aggregated verification keys this RP accepts, by key id. Proofs name the key they are
+    /// made for, so keys of several generations can be accepted at once.
+    pinned: HashMap<String, PinnedKey>,
+    /// Identity keys of the IdPs whose verification key shares are accepted, by signer id
+    trusted_idps: HashMap<usize, SignatureGroup>,
+    /// Credential types this RP accepts. All registered types if not set.
+    accepted_types: Option<BTreeSet<String>>,
+    /// Presentations allowed per holder and epoch, if limited
+    rate_limit: Option<RateLimit>,
+    /// Rate limit tags seen, by epoch
+    seen_tags: Arc<Mutex<BTreeMap<u64, HashSet<String>>>>,
+    /// Spec of the one-show credentials this RP accepts, with the transcripts of those shown
+    one_show: Option<(OneShowSpec, Arc<SpentTokens>)>,
     public: PublicParams,
 }
 
@@ -19,37 +50,304 @@ impl RelyingParty {
         RelyingParty {
             domain,
             vk: None,
+            pinned: HashMap::new(),
+            trusted_idps: HashMap::new(),
+            accepted_types: None,
+            rate_limit: None,
+            seen_tags: Arc::new(Mutex::new(BTreeMap::new())),
+            one_show: None,
             public,
         }
     }
 
-    pub fn set_verification_key (&mut self, vk: PSVerkey) {
+    /// Accept verification key shares of signer `signer_id` signed with `identity_key`
+    pub fn with_trusted_idp (mut self, signer_id: usize, identity_key: SignatureGroup) -> Self {
+        self.trusted_idps.insert(signer_id, identity_key);
+        self
+    }
+
+    pub fn trust_idp (&mut self, signer_id: usize, identity_key: SignatureGroup) {
+        self.trusted_idps.insert(signer_id, identity_key);
+    }
+
+    /// Accept only credentials of the registered types `types`
+    pub fn with_accepted_types (mut self, types: Vec<String>) -> Self {
+        self.accepted_types = Some(types.into_iter().collect());
+        self
+    }
+
+    pub fn accept_type (&mut self, credential_type: &str) {
+        self.accepted_types
+            .get_or_insert_with(BTreeSet::new)
+            .insert(credential_type.to_string());
+    }
+
+    /// Accept at most `limit.k` presentations per credential and epoch. Proofs must carry a rate
+    /// limit tag.
+    pub fn with_rate_limit (mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    pub fn set_rate_limit (&mut self, limit: RateLimit) {
+        self.rate_limit = Some(limit);
+    }
+
+    /// Accept only one-show presentations of credentials of `spec`, each serial number once.
+    /// `tokens` may be shared with the other verifiers of the scope.
+    pub fn with_one_show (mut self, spec: OneShowSpec, tokens: Arc<SpentTokens>) -> Result<Self, ThesisError> {
+        self.set_one_show(spec, tokens)?;
+        Ok(self)
+    }
+
+    pub fn set_one_show (&mut self, spec: OneShowSpec, tokens: Arc<SpentTokens>) -> Result<(), ThesisError> {
+        spec.check_slots()?;
+        self.one_show = Some((spec, tokens));
+        Ok(())
+    }
+
+    /// Record the rate limit and one-show tags of a verified proof. A tag seen before means the
+    /// holder exceeded the limit or showed the token again; rate limit tags of past epochs are
+    /// dropped. Both are checked before either is recorded, so a proof refused for one tag does
+    /// not use up the other.
+    fn record_tags (
+        &self,
+        rate_limit: Option<(&RateLimit, &RateLimitTag)>,
+        one_show: Option<(&SpentTokens, Transcript)>,
+    ) -> Result<(), ThesisError> {
+        let mut seen = self.seen_tags.lock().unwrap();
+        if let Some((limit, tag)) = rate_limit {
+            let epoch = limit.epoch_at(now_secs());
+            *seen = seen.split_off(&epoch);
+            if seen.get(&tag.epoch).is_some_and(|tags| tags.contains(&tag.tag_hex())) {
+                return Err(ThesisError::RateLimitExceeded {
+                    tag: tag.tag_hex(),
+                });
+            }
+        }
+        // Spending checks and records the token at once. The rate limit tag stays unrecorded if
+        // the token was shown before, and cannot be recorded meanwhile as `seen` is still locked.
+        if let Some((tokens, transcript)) = one_show {
+            tokens.spend(transcript)?;
+        }
+        if let Some((_, tag)) = rate_limit {
+            seen.entry(tag.epoch).or_default().insert(tag.tag_hex());
+        }
+        Ok(())
+    }
+
+    /// Revealed type attribute an accepted proof must carry, as revealed indices and messages.
+    /// Proofs of untyped credentials reveal nothing if the deployment has no type registry.
+    fn revealed_type (&self, id_proof: &JSPoKOfSignatureProof) -> Result<(HashSet<usize>, HashMap<usize, FieldElement>), ThesisError> {
+        let rejected = || ThesisError::CredentialTypeRejected {
+            credential_type: id_proof.credential_type.clone(),
+        };
+        match (&self.public.credential_types, &id_proof.credential_type) {
+            (None, None) => Ok((HashSet::new(), HashMap::new())),
+            (Some(registry), Some(t)) => {
+                if self.accepted_types.as_ref().is_some_and(|accepted| !accepted.contains(t)) {
+                    return Err(rejected());
+                }
+                let (slot, attr) = registry.revealed_type_attribute(t).ok_or_else(rejected)?;
+                Ok((vec![slot].into_iter().collect(), vec![(slot, attr)].into_iter().collect()))
+            }
+            _ => Err(rejected()),
+        }
+    }
+
+    /// Use `vk`, which must have been pinned before with `pin_verification_key`
+    pub fn set_verification_key (&mut self, vk: PSVerkey) -> Result<(), ThesisError> {
+        vk.validate()?;
+        let key_id = verkey_id(&vk);
+        if !self.pinned.contains_key(&key_id) {
+            return Err(ThesisError::VerkeyRejected {
+                msg: format!("Key {} is not pinned", key_id),
+            });
+        }
         self.vk = Some(vk);
+        Ok(())
+    }
+
+    /// Use the pinned key `key_id`
+    pub fn select_verification_key (&mut self, key_id: &str) -> Result<(), ThesisError> {
+        let pinned = self.pinned.get(key_id).ok_or_else(|| ThesisError::VerkeyRejected {
+            msg: format!("Key {} is not pinned", key_id),
+        })?;
+        self.vk = Some(pinned.vk.clone());
+        Ok(())
+    }
+
+    /// Accept proofs for the pinned key `key_id` only during `validity`, for instance to stop
+    /// accepting the keys of an old generation
+    pub fn set_key_validity (&mut self, key_id: &str, validity: Validity) -> Result<(), ThesisError> {
+        let pinned = self.pinned.get_mut(key_id).ok_or_else(|| ThesisError::VerkeyRejected {
+            msg: format!("Key {} is not pinned", key_id),
+        })?;
+        pinned.validity = validity;
+        Ok(())
+    }
+
+    /// Ids of the pinned keys
+    pub fn pinned_key_ids (&self) -> Vec<String> {
+        self.pinned.keys().cloned().collect()
+    }
+
+    /// Check the signatures of the published shares against the trusted IdPs, aggregate them and
+    /// pin and use the result for the validity window of the shares' key epoch. Returns the key
+    /// id. Keys pinned before stay pinned.
+    pub fn pin_verification_key (&mut self, shares: Vec<SignedVerkeyShare>) -> Result<String, ThesisError> {
+        let epoch = match shares.first() {
+            Some(share) => share.epoch.clone(),
+            None => return Err(ThesisError::NotEnoughSignatures {
+                threshold: self.public.threshold,
+                valid: 0,
+            }),
+        };
+        for share in &shares {
+            share.validate()?;
+            share.verify(&self.trusted_idps)?;
+            if share.params_fingerprint != self.public.fingerprint() {
+                return Err(ThesisError::VerkeyRejected {
+                    msg: format!("Share of signer {} is for other public parameters", share.signer_id),
+                });
+            }
+            if share.epoch != epoch {
+                return Err(ThesisError::VerkeyRejected {
+                    msg: format!("Share of signer {} is of another key epoch", share.signer_id),
+                });
+            }
+        }
+        self.aggregate_and_pin(shares.into_iter().map(|s| (s.signer_id, s.vk_share)).collect(), epoch.validity)
     }
 
-    pub fn aggregate_and_store_verification_key (mut self, vk_pairs: Vec<(usize, Verkey)>) -> Self {
-        let threshold = self.public.threshold;
-        let aggr_ps_vk = transform_to_PS_verkey(&Verkey::owned_aggregate(
-            threshold,
-            vk_pairs
-        ));
+    /// Aggregate verification key shares taken directly from the IdPs, and pin and use the result
+    pub fn aggregate_and_store_verification_key (mut self, vk_pairs: Vec<(usize, Verkey)>) -> Result<Self, ThesisError> {
+        self.aggregate_and_pin(vk_pairs, Validity::default())?;
+        Ok(self)
+    }
+
+    fn aggregate_and_pin (&mut self, vk_pairs: Vec<(usize, Verkey)>, validity: Validity) -> Result<String, ThesisError> {
+        vk_pairs.iter().try_for_each(|(_, vk)| vk.validate())?;
+        if vk_pairs.iter().any(|(_, vk)| vk.Y_tilde.len() != self.public.cparams.h.len()) {
+            return Err(ThesisError::InvalidInput {
+                msg: String::from("Verkey share for wrong number of messages"),
+            });
+        }
+        let aggr_ps_vk = transform_to_PS_verkey(&aggregate_checked(self.public.threshold, &vk_pairs)?);
+        let key_id = verkey_id(&aggr_ps_vk);
+        self.pinned.insert(key_id.clone(), PinnedKey {
+            vk: aggr_ps_vk.clone(),
+            validity,
+        });
         self.vk = Some(aggr_ps_vk);
 
-        self
+        Ok(key_id)
+    }
+
+    /// Pinned key a proof is made for: the key it names, or the key in use if it names none.
+    /// The key must be within its validity window.
+    fn key_for (&self, id_proof: &JSPoKOfSignatureProof) -> Result<&PSVerkey, ThesisError> {
+        let key_id = match (&id_proof.key_id, &self.vk) {
+            (Some(key_id), _) => key_id.clone(),
+            (None, Some(vk)) => verkey_id(vk),
+            (None, None) => return Err(ThesisError::InvalidState {
+                msg: String::from("No verification key set"),
+            }),
+        };
+        let pinned = self.pinned.get(&key_id).ok_or_else(|| ThesisError::VerkeyRejected {
+            msg: format!("Key {} is not pinned", key_id),
+        })?;
+        if !pinned.validity.contains(now_secs()) {
+            return Err(ThesisError::VerkeyRejected {
+                msg: format!("Key {} is outside its validity window", key_id),
+            });
+        }
+        Ok(&pinned.vk)
     }
 
     pub fn verify_id (
         &self,
         id_proof: JSPoKOfSignatureProof,
-    ) -> Option<bool> {
-        if let Some(ps_vk) = &self.vk {
-            let chal_bytes = id_proof.get_bytes_for_challenge(HashSet::new(), ps_vk, &transform_to_PS_params(&self.public.cparams));
-            let chal_verifier = FieldElement::from_msg_hash(&chal_bytes);
-            
-            id_proof.verify(&ps_vk, &transform_to_PS_params(&self.public.cparams), HashMap::new(), &chal_verifier).ok()
-        } else {
-            None
+    ) -> Result<bool, ThesisError> {
+        id_proof.validate()?;
+        let ps_vk = self.key_for(&id_proof)?;
+        let (revealed_indices, revealed_msgs) = self.revealed_type(&id_proof)?;
+        let tag = match (&self.rate_limit, &id_proof.rate_limit_tag) {
+            (Some(limit), Some(tag)) => {
+                if tag.epoch != limit.epoch_at(now_secs()) {
+                    return Err(ThesisError::RateLimitTagRejected {
+                        msg: format!("Tag of epoch {} is not for the current epoch", tag.epoch),
+                    });
+                }
+                if limit.secret_slot >= ps_vk.Y_tilde.len() || revealed_indices.contains(&limit.secret_slot) {
+                    return Err(ThesisError::RateLimitTagRejected {
+                        msg: String::from("The link secret is not hidden"),
+                    });
+                }
+                Some((limit, tag))
+            }
+            (Some(_), None) => return Err(ThesisError::RateLimitTagRejected {
+                msg: String::from("Proof has no rate limit tag"),
+            }),
+            (None, _) => None,
+        };
+        let one_show = match (&self.one_show, &id_proof.one_show_tag) {
+            (Some((spec, tokens)), Some(tag)) => {
+                let slots = [spec.identity_slot, spec.serial_slot];
+                if slots.iter().any(|i| *i >= ps_vk.Y_tilde.len() || revealed_indices.contains(i)) {
+                    return Err(ThesisError::OneShowTagRejected {
+                        msg: String::from("The identity attribute and serial seed are not hidden"),
+                    });
+                }
+                Some((spec, tokens, tag))
+            }
+            (Some(_), None) => return Err(ThesisError::OneShowTagRejected {
+                msg: String::from("Proof has no one-show tag"),
+            }),
+            (None, _) => None,
+        };
+        // Responses for hidden messages come after the one for t
+        let position = |slot: usize| 1 + (0..slot).filter(|i| !revealed_indices.contains(i)).count();
+        let secret_position = tag.map(|(limit, _)| position(limit.secret_slot));
+        let one_show_positions = one_show.map(|(spec, _, _)| (position(spec.identity_slot), position(spec.serial_slot)));
+
+        let params = transform_to_PS_params(&self.public.cparams);
+        let mut chal_bytes = id_proof.get_bytes_for_challenge(revealed_indices, ps_vk, &params);
+        chal_bytes.extend_from_slice(self.public.fingerprint().as_bytes());
+        if let Some(tag) = &id_proof.rate_limit_tag {
+            chal_bytes.append(&mut tag.get_bytes_for_challenge());
+        }
+        if let Some(tag) = &id_proof.one_show_tag {
+            chal_bytes.append(&mut tag.get_bytes_for_challenge());
+        }
+        let chal_verifier = FieldElement::from_msg_hash(&chal_bytes);
+
+        if !id_proof.verify(ps_vk, &params, revealed_msgs, &chal_verifier)? {
+            return Ok(false);
+        }
+        // Tags are recorded only once the whole proof verified
+        let responses = id_proof.proof_vc.responses.as_slice();
+        if let (Some((limit, tag)), Some(position)) = (tag, secret_position) {
+            let response = match responses.get(position) {
+                Some(response) => response,
+                None => return Ok(false),
+            };
+            if !tag.verify(limit, &self.domain, &chal_verifier, response)? {
+                return Ok(false);
+            }
+        }
+        if let (Some((spec, _, tag)), Some((identity, serial))) = (one_show, one_show_positions) {
+            let (identity, serial) = match (responses.get(identity), responses.get(serial)) {
+                (Some(identity), Some(serial)) => (identity, serial),
+                _ => return Ok(false),
+            };
+            if !tag.verify(spec, &id_proof.sig, &self.domain, &chal_verifier, identity, serial) {
+                return Ok(false);
+            }
         }
+        let spend = one_show.map(|(_, tokens, tag)| (tokens.as_ref(), tag.transcript(&id_proof.sig, &self.domain)));
+        self.record_tags(tag, spend)?;
+        Ok(true)
     }
 }
 
@@ -0,0 +1,320 @@
+// Encrypted distribution of signer shares from the dealer to the IdPs.
+//
+// Every IdP has a long-term identity key pair on the signature group. The dealer encrypts each
+// signer share to the key of its IdP with a hybrid scheme: a Diffie-Hellman with a fresh ephemeral
+// key gives a ChaCha20-Poly1305 key, and the header (version, signer id, key epoch and all public
+// keys) is passed as associated data. The dealer signs the whole bundle with a Schnorr signature so an IdP
+// only loads shares from the dealer it has pinned.
+
+use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::group_elem::GroupElement;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use coconut_sig::errors::CoconutError;
+use coconut_sig::keygen::Signer;
+use coconut_sig::secret::SecretFieldElement;
+use coconut_sig::validation::{check_element, Validate};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use zeroize::Zeroizing;
+
+use crate::SignatureGroup;
+use crate::key_epoch::KeyEpoch;
+use crate::key_store::StoredKey;
+
+/// Version of the share bundle format produced by `EncryptedShare::seal`.
+pub const SHARE_FORMAT_VERSION: u16 = 2;
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ShareError {
+    UnsupportedVersion { version: u16 },
+    UntrustedDealer,
+    InvalidSignature,
+    WrongRecipient,
+    Decryption,
+    SignerMismatch { expected: usize, given: usize },
+    Serialization { msg: String },
+}
+
+impl fmt::Display for ShareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShareError::UnsupportedVersion { version } => write!(f, "Unsupported share format version {}", version),
+            ShareError::UntrustedDealer => write!(f, "Share was not issued by the pinned dealer"),
+            ShareError::InvalidSignature => write!(f, "Dealer signature on the share is invalid"),
+            ShareError::WrongRecipient => write!(f, "Share is encrypted to another identity key"),
+            ShareError::Decryption => write!(f, "Share could not be decrypted"),
+            ShareError::SignerMismatch { expected, given } => write!(f, "Share is for signer {} but contains signer {}", expected, given),
+            ShareError::Serialization { msg } => write!(f, "Error with serialization {:?}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ShareError {}
+
+impl From<serde_json::Error> for ShareError {
+    fn from(err: serde_json::Error) -> Self {
+        ShareError::Serialization { msg: err.to_string() }
+    }
+}
+
+/// Long-term key pair on the signature group. IdPs use it as identity key to receive their
+/// share and sign their verification key shares, the dealer uses it to sign share bundles.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyPair {
+    sk: SecretFieldElement,
+    pub pk: SignatureGroup,
+}
+
+impl KeyPair {
+    pub fn new() -> Self {
+        let sk = SecretFieldElement::random();
+        let pk = SignatureGroup::generator() * sk.expose_secret();
+        KeyPair { sk, pk }
+    }
+
+    /// Schnorr signature on `msg`
+    pub fn sign(&self, msg: &[u8]) -> SchnorrSignature {
+        let k = FieldElement::random();
+        let commitment = SignatureGroup::generator() * &k;
+        let challenge = SchnorrSignature::challenge(&commitment, &self.pk, msg);
+        SchnorrSignature {
+            commitment,
+            response: k + &challenge * self.sk.expose_secret(),
+        }
+    }
+}
+
+impl Default for KeyPair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Schnorr signature with a `KeyPair`, used by the dealer on share bundles and by the IdPs on
+/// their published verification key shares
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SchnorrSignature {
+    pub commitment: SignatureGroup,
+    pub response: FieldElement,
+}
+
+impl SchnorrSignature {
+    fn challenge(commitment: &SignatureGroup, pk: &SignatureGroup, msg: &[u8]) -> FieldElement {
+        let mut bytes = commitment.to_bytes();
+        bytes.append(&mut pk.to_bytes());
+        bytes.extend_from_slice(msg);
+        FieldElement::from_msg_hash(&bytes)
+    }
+
+    pub fn verify(&self, pk: &SignatureGroup, msg: &[u8]) -> bool {
+        let challenge = Self::challenge(&self.commitment, pk, msg);
+        SignatureGroup::generator() * &self.response == &self.commitment + &(pk * &challenge)
+    }
+}
+
+/// Signer share encrypted to the identity key of one IdP and signed by the dealer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncryptedShare {
+    pub version: u16,
+    pub signer_id: usize,
+    /// Generation the share belongs to
+    pub epoch: KeyEpoch,
+    pub recipient: SignatureGroup,
+    pub dealer: SignatureGroup,
+    pub ephemeral: SignatureGroup,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub signature: SchnorrSignature,
+}
+
+impl EncryptedShare {
+    /// Encrypt `signer` of key generation `epoch` to `recipient` and sign the bundle with the
+    /// dealer's keys
+    pub fn seal(signer: &Signer, epoch: &KeyEpoch, recipient: &SignatureGroup, dealer: &KeyPair) -> Result<Self, ShareError> {
+        let r = SecretFieldElement::random();
+        let ephemeral = SignatureGroup::generator() * r.expose_secret();
+        let key = derive_key(&(recipient * r.expose_secret()), &ephemeral, recipient);
+
+        let mut nonce = vec![0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let mut share = EncryptedShare {
+            version: SHARE_FORMAT_VERSION,
+            signer_id: signer.id,
+            epoch: epoch.clone(),
+            recipient: recipient.clone(),
+            dealer: dealer.pk.clone(),
+            ephemeral,
+            nonce,
+            ciphertext: vec![],
+            signature: SchnorrSignature {
+                commitment: SignatureGroup::identity(),
+                response: FieldElement::zero(),
+            },
+        };
+
+        let plaintext = Zeroizing::new(serde_json::to_vec(signer)?);
+        let cipher = ChaCha20Poly1305::new(key[..].into());
+        share.ciphertext = cipher
+            .encrypt(
+                share.nonce.as_slice().into(),
+                Payload { msg: &plaintext, aad: &share.header_bytes() },
+            )
+            .map_err(|_| ShareError::Serialization {
+                msg: String::from("Encryption failed"),
+            })?;
+        share.signature = dealer.sign(&share.signed_bytes());
+        Ok(share)
+    }
+
+    /// Check the dealer signature against the pinned `dealer` key and decrypt the share with the
+    /// IdP's identity keys. The key is stored under the share's key epoch.
+    pub fn open(&self, identity: &KeyPair, dealer: &SignatureGroup) -> Result<StoredKey, ShareError> {
+        if self.version != SHARE_FORMAT_VERSION {
+            return Err(ShareError::UnsupportedVersion { version: self.version });
+        }
+        if &self.dealer != dealer {
+            return Err(ShareError::UntrustedDealer);
+        }
+        if !self.signature.verify(dealer, &self.signed_bytes()) {
+            return Err(ShareError::InvalidSignature);
+        }
+        if self.recipient != identity.pk {
+            return Err(ShareError::WrongRecipient);
+        }
+        if self.nonce.len() != NONCE_LEN {
+            return Err(ShareError::Decryption);
+        }
+
+        let key = derive_key(&(&self.ephemeral * identity.sk.expose_secret()), &self.ephemeral, &self.recipient);
+        let cipher = ChaCha20Poly1305::new(key[..].into());
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(
+                    self.nonce.as_slice().into(),
+                    Payload { msg: &self.ciphertext, aad: &self.header_bytes() },
+                )
+                .map_err(|_| ShareError::Decryption)?,
+        );
+
+        let signer: Signer = serde_json::from_slice(&plaintext)?;
+        if signer.id != self.signer_id {
+            return Err(ShareError::SignerMismatch {
+                expected: self.signer_id,
+                given: signer.id,
+            });
+        }
+        Ok(StoredKey::in_epoch(signer, &self.epoch))
+    }
+
+    /// Associated data of the encryption
+    fn header_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.version.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&(self.signer_id as u64).to_be_bytes());
+        bytes.append(&mut self.epoch.to_bytes());
+        bytes.append(&mut self.recipient.to_bytes());
+        bytes.append(&mut self.dealer.to_bytes());
+        bytes.append(&mut self.ephemeral.to_bytes());
+        bytes
+    }
+
+    /// Everything but the signature
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header_bytes();
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+}
+
+/// Symmetric key from the Diffie-Hellman shared point, bound to both public keys
+fn derive_key(shared: &SignatureGroup, ephemeral: &SignatureGroup, recipient: &SignatureGroup) -> Zeroizing<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"share key : ");
+    hasher.update(&Zeroizing::new(shared.to_bytes())[..]);
+    hasher.update(ephemeral.to_bytes());
+    hasher.update(recipient.to_bytes());
+    Zeroizing::new(hasher.finalize().to_vec())
+}
+
+impl Validate for KeyPair {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.pk, "public key")
+    }
+}
+
+impl Validate for SchnorrSignature {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.commitment, "signature commitment")
+    }
+}
+
+impl Validate for EncryptedShare {
+    fn validate(&self) -> Result<(), CoconutError> {
+        check_element(&self.recipient, "recipient")?;
+        check_element(&self.dealer, "dealer key")?;
+        check_element(&self.ephemeral, "ephemeral key")?;
+        self.signature.validate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js::Public as PublicParams;
+    use crate::ttp::TrustedThirdParty;
+
+    fn signer() -> (Signer, KeyEpoch) {
+        let params = PublicParams::new(6, "test".as_bytes(), 3, 5);
+        let ttp = TrustedThirdParty::new(3, 5, &params).unwrap();
+        (ttp.deserialize_server_i(&ttp.serialize_server_i(1).unwrap()).unwrap(), ttp.key_epoch().clone())
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let (signer, epoch) = signer();
+        let dealer = KeyPair::new();
+        let identity = KeyPair::new();
+
+        let share = EncryptedShare::seal(&signer, &epoch, &identity.pk, &dealer).unwrap();
+        let json = serde_json::to_string(&share).unwrap();
+        assert!(!json.contains(&signer.sigkey.x.expose_secret().to_hex()));
+
+        let opened = share.open(&identity, &dealer.pk).unwrap();
+        assert_eq!(opened.signer.id, signer.id);
+        assert_eq!(opened.signer.sigkey.x, signer.sigkey.x);
+        assert_eq!(opened.metadata.key_id, epoch.key_id);
+
+        assert_eq!(share.open(&KeyPair::new(), &dealer.pk).unwrap_err(), ShareError::WrongRecipient);
+        assert_eq!(share.open(&identity, &KeyPair::new().pk).unwrap_err(), ShareError::UntrustedDealer);
+    }
+
+    #[test]
+    fn test_tampered_share() {
+        let dealer = KeyPair::new();
+        let identity = KeyPair::new();
+        let (signer, epoch) = signer();
+        let share = EncryptedShare::seal(&signer, &epoch, &identity.pk, &dealer).unwrap();
+
+        let mut tampered = share.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert_eq!(tampered.open(&identity, &dealer.pk).unwrap_err(), ShareError::InvalidSignature);
+
+        // The epoch is signed with the share
+        let mut extended = share.clone();
+        extended.epoch.validity.not_after = Some(u64::MAX - 1);
+        assert_eq!(extended.open(&identity, &dealer.pk).unwrap_err(), ShareError::InvalidSignature);
+
+        // Re-signing by anyone but the pinned dealer does not help
+        let mallory = KeyPair::new();
+        let mut forged = share.clone();
+        forged.signer_id = 0;
+        forged.dealer = mallory.pk.clone();
+        forged.signature = mallory.sign(&forged.signed_bytes());
+        assert_eq!(forged.open(&identity, &dealer.pk).unwrap_err(), ShareError::UntrustedDealer);
+    }
+}
@@ -1,37 +1,83 @@
 
 use coconut_sig::keygen::{trusted_party_SSS_keygen, Signer};
+use coconut_sig::signature::transform_to_PS_verkey;
 
+use crate::SignatureGroup;
+use crate::errors::ThesisError;
 use crate::js::Public as PublicParams;
+use crate::key_epoch::{KeyEpoch, Validity};
+use crate::key_store::StoredKey;
+use crate::share_transport::{EncryptedShare, KeyPair};
+use crate::verkey_distribution::aggregate_checked;
 
 #[derive(Clone, Debug)]
 pub struct TrustedThirdParty {
-    signers: Vec<Signer>
+    signers: Vec<Signer>,
+    /// Key id and validity of the dealt generation of keys
+    epoch: KeyEpoch,
+    /// Keys signing the encrypted shares. IdPs pin the public key.
+    dealer: KeyPair,
 }
 
 
 impl TrustedThirdParty {
 
     /// Instantiate Trusted Third Party with shared Params
-    pub fn new (threshold: usize, total: usize, public: &PublicParams) -> Self {
+    pub fn new (threshold: usize, total: usize, public: &PublicParams) -> Result<Self, ThesisError> {
+        if threshold == 0 || threshold > total {
+            return Err(ThesisError::InvalidInput {
+                msg: format!("Threshold {} not possible with {} signers", threshold, total),
+            });
+        }
         // Must include messages for gamma, user_secret, tp
         // Generate params and signing keys
         let (_, _, signers) = trusted_party_SSS_keygen(threshold, total, &public.cparams);
+        let vk_shares = signers.iter().map(|s| (s.id, s.verkey.clone())).collect::<Vec<_>>();
+        let epoch = KeyEpoch::new(&transform_to_PS_verkey(&aggregate_checked(threshold, &vk_shares)?), Validity::default());
 
         // Init trusted third party to hold + distribute keys
-        TrustedThirdParty {
+        Ok(TrustedThirdParty {
             signers,
-        }
+            epoch,
+            dealer: KeyPair::new(),
+        })
     }
 
-    /// Get serialized id and keys for ith server
+    /// Deal the keys for use in `validity` only, to rotate them without a flag day
+    pub fn with_validity (mut self, validity: Validity) -> Self {
+        self.epoch.validity = validity;
+        self
+    }
+
+    pub fn key_epoch (&self) -> &KeyEpoch {
+        &self.epoch
+    }
+
+    /// Public key the IdPs check the encrypted shares against
+    pub fn dealer_public_key (&self) -> &SignatureGroup {
+        &self.dealer.pk
+    }
+
+    /// Get the keys for ith server encrypted to its identity key `recipient` and signed by the
+    /// dealer, serialized
+    pub fn encrypt_server_i (&self, idx: usize, recipient: &SignatureGroup) -> Result<String, ThesisError> {
+        let signer = self.signers.get(idx).ok_or_else(|| ThesisError::InvalidInput {
+            msg: format!("No server {}", idx),
+        })?;
+        let share = EncryptedShare::seal(signer, &self.epoch, recipient, &self.dealer)?;
+        Ok(serde_json::to_string(&share)?)
+    }
+
+    /// Get serialized id and keys for ith server with the key epoch, as a `StoredKey` in
+    /// plaintext. Prefer `encrypt_server_i` whenever the keys leave the dealer.
     pub fn serialize_server_i (&self, idx: usize) -> Option<String> {
         let signer = self.signers.get(idx)?;
-        serde_json::to_string(&signer).ok()
+        serde_json::to_string(&StoredKey::in_epoch(signer.clone(), &self.epoch)).ok()
     }
 
     /// Get serialized id and keys for ith server
     pub fn deserialize_server_i (&self, string: &String) -> Option<Signer> {
-        serde_json::from_str(string).ok()
+        serde_json::from_str::<StoredKey>(string).ok().map(|key| key.signer)
     }
 }
 
@@ -47,7 +93,11 @@ mod tests {
         let label = "test";
         let public_params = PublicParams::new(msg_count, label.as_bytes(), threshold, total);
 
-        TrustedThirdParty::new(threshold, total, &public_params);
+        let ttp = TrustedThirdParty::new(threshold, total, &public_params).unwrap();
+        // Signing keys are not printed
+        let x = ttp.signers[0].sigkey.x.expose_secret().to_hex();
+        assert!(!format!("{:?}", ttp).contains(&x));
+        assert!(TrustedThirdParty::new(total + 1, total, &public_params).is_err());
     }
 
     #[test]
@@ -59,7 +109,7 @@ mod tests {
         let label = "test";
         let public_params = PublicParams::new(msg_count, label.as_bytes(), threshold, total);
 
-        let ttp = TrustedThirdParty::new(threshold, total, &public_params);
+        let ttp = TrustedThirdParty::new(threshold, total, &public_params).unwrap();
         let serialized_0 = ttp.serialize_server_i(0).expect("Could not serialize signer");
 
         
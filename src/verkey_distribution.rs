@@ -0,0 +1,155 @@
+// Distribution of the aggregated verification key to relying parties.
+//
+// An RP must not take its verification key from the prover. Every IdP publishes its verification
+// key share signed with its identity key, and the RP aggregates the shares of the IdPs it trusts.
+// Consistent shares give the same key for any `threshold` of them, so the RP checks that all
+// shares lie on one polynomial before pinning the key under its key id.
+
+use coconut_sig::errors::{CoconutError, CoconutErrorKind};
+use coconut_sig::signature::{transform_to_PS_verkey, Verkey};
+use coconut_sig::validation::{check_element, Validate};
+use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::group_elem::GroupElement;
+use ps_sig::keys::Verkey as PSVerkey;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::SignatureGroup;
+use crate::errors::ThesisError;
+use crate::key_epoch::KeyEpoch;
+use crate::share_transport::{KeyPair, SchnorrSignature};
+
+/// Verification key share of an IdP, signed with the IdP's identity key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedVerkeyShare {
+    pub signer_id: usize,
+    /// Key id and validity of the IdP's key
+    pub epoch: KeyEpoch,
+    /// Fingerprint of the public parameters of the IdP
+    pub params_fingerprint: String,
+    pub vk_share: Verkey,
+    pub idp_key: SignatureGroup,
+    pub signature: SchnorrSignature,
+}
+
+impl SignedVerkeyShare {
+    pub fn new(signer_id: usize, epoch: KeyEpoch, params_fingerprint: &str, vk_share: Verkey, identity: &KeyPair) -> Self {
+        let mut share = SignedVerkeyShare {
+            signer_id,
+            epoch,
+            params_fingerprint: params_fingerprint.to_string(),
+            vk_share,
+            idp_key: identity.pk.clone(),
+            signature: SchnorrSignature {
+                commitment: SignatureGroup::identity(),
+                response: FieldElement::zero(),
+            },
+        };
+        share.signature = identity.sign(&share.signed_bytes());
+        share
+    }
+
+    /// Check the signature against the identity keys of the trusted IdPs, keyed by signer id
+    pub fn verify(&self, trusted: &HashMap<usize, SignatureGroup>) -> Result<(), ThesisError> {
+        match trusted.get(&self.signer_id) {
+            Some(key) if key == &self.idp_key => (),
+            _ => return Err(ThesisError::VerkeyRejected {
+                msg: format!("Share of signer {} is not signed by a trusted IdP", self.signer_id),
+            }),
+        }
+        if !self.signature.verify(&self.idp_key, &self.signed_bytes()) {
+            return Err(ThesisError::VerkeyRejected {
+                msg: format!("Invalid signature on the share of signer {}", self.signer_id),
+            });
+        }
+        Ok(())
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = b"vk share : ".to_vec();
+        bytes.extend_from_slice(&(self.signer_id as u64).to_be_bytes());
+        bytes.append(&mut self.epoch.to_bytes());
+        bytes.extend_from_slice(self.params_fingerprint.as_bytes());
+        bytes.append(&mut verkey_bytes(&transform_to_PS_verkey(&self.vk_share)));
+        bytes
+    }
+}
+
+impl Validate for SignedVerkeyShare {
+    fn validate(&self) -> Result<(), CoconutError> {
+        self.vk_share.validate()?;
+        check_element(&self.idp_key, "IdP key")?;
+        self.signature.validate()
+    }
+}
+
+pub fn verkey_bytes(vk: &PSVerkey) -> Vec<u8> {
+    let mut bytes = vk.X_tilde.to_bytes();
+    for y in &vk.Y_tilde {
+        bytes.append(&mut y.to_bytes());
+    }
+    bytes
+}
+
+/// Id under which an RP pins an aggregated verification key: the first 16 bytes of the SHA-256 of
+/// the key, hex encoded.
+pub fn verkey_id(vk: &PSVerkey) -> String {
+    Sha256::digest(&verkey_bytes(vk))[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Aggregate the shares after checking that they all lie on one polynomial, so that every subset
+/// of `threshold` of them gives the same key. There must be at least `threshold` shares, each
+/// from a different signer.
+pub fn aggregate_checked(threshold: usize, shares: &[(usize, Verkey)]) -> Result<Verkey, ThesisError> {
+    if threshold == 0 || shares.len() < threshold {
+        return Err(ThesisError::NotEnoughSignatures {
+            threshold,
+            valid: shares.len(),
+        });
+    }
+    Verkey::checked_aggregate(threshold, shares.iter().map(|(id, vk)| (*id, vk)).collect()).map_err(|err| {
+        match err.kind() {
+            CoconutErrorKind::InconsistentVerkeyShares { ids } => ThesisError::VerkeyRejected {
+                msg: format!("Shares of signers {:?} are inconsistent with the others", ids),
+            },
+            _ => err.into(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js::Public as PublicParams;
+    use crate::ttp::TrustedThirdParty;
+    use coconut_sig::keygen::Signer;
+
+    fn vk_shares(ttp: &TrustedThirdParty, total: usize) -> Vec<(usize, Verkey)> {
+        (0..total)
+            .map(|i| {
+                let signer: Signer = ttp.deserialize_server_i(&ttp.serialize_server_i(i).unwrap()).unwrap();
+                (signer.id, signer.verkey)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_aggregate_checked() {
+        let params = PublicParams::new(4, "test".as_bytes(), 2, 3);
+        let ttp = TrustedThirdParty::new(2, 3, &params).unwrap();
+        let mut shares = vk_shares(&ttp, 3);
+        assert!(aggregate_checked(2, &shares).is_ok());
+        assert_eq!(
+            aggregate_checked(2, &shares[..1]).unwrap_err(),
+            ThesisError::NotEnoughSignatures { threshold: 2, valid: 1 }
+        );
+
+        // A share of another deployment does not lie on the polynomial of the others
+        let other = TrustedThirdParty::new(2, 3, &params).unwrap();
+        shares[2] = vk_shares(&other, 3).remove(2);
+        assert_eq!(aggregate_checked(2, &shares).unwrap_err().code(), "VerkeyRejected");
+    }
+}
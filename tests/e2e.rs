@@ -1,8 +1,20 @@
-use coconut_sig::signature::{Verkey};
+use amcl_wrapper::field_elem::FieldElement;
+use amcl_wrapper::group_elem::GroupElement;
+use amcl_wrapper::group_elem_g2::G2;
+use coconut_sig::signature::{Verkey, transform_to_PS_verkey};
+use coconut_sig::validation::Validate;
 use std::collections::HashSet;
-use thesis::{ttp, d_idp, client, rp, js};
+use std::sync::Arc;
+use thesis::{ttp, d_idp, client, gate, one_show, rate_limit, rp, js};
+use thesis::attestation::{AttestationPolicy, Attestor, OtpVerifier};
 use thesis::client::ServerBlindSignature;
-use thesis::js_signature::JSMessages;
+use thesis::credential_type::{AttributeSpec, CredentialType, CredentialTypeRegistry};
+use thesis::errors::{IssuanceError, ThesisError};
+use thesis::identity_tag::{MemoryTagRegistry, TagRegistry};
+use thesis::issuer_attributes::{IssuerAttributes, IssuerAttributeSpec};
+use thesis::key_epoch::Validity;
+use thesis::key_store::{KeyStore, MemoryKeyStore, StoredKey};
+use thesis::share_transport::{KeyPair, ShareError};
 
 #[test]
 fn test_get_credential_from_d_idp() {
@@ -19,13 +31,13 @@ fn test_get_credential_from_d_idp() {
     let domain = String::from("hello.com");
 
     // TTP Setup
-    let ttp = ttp::TrustedThirdParty::new(threshold, total_server_count, &public_params);
+    let ttp = ttp::TrustedThirdParty::new(threshold, total_server_count, &public_params).unwrap();
 
     // IdP Setup
     let mut idps: Vec<d_idp::DistributedIdP> = vec![];
     for id in 0..total_server_count {
         let serialized_signer = ttp.serialize_server_i(id).expect("Could not serialize signer");
-        idps.push(d_idp::DistributedIdP::from_serialized_signer(&serialized_signer, &public_params))
+        idps.push(d_idp::DistributedIdP::from_serialized_signer(&serialized_signer, &public_params).unwrap())
     }
     assert_eq!(idps.clone().len(), total_server_count);
 
@@ -37,30 +49,890 @@ fn test_get_credential_from_d_idp() {
         vk_shares.push(idp.get_id_vk());
     }
     // Get verification key from idps
-    let rp = rp.aggregate_and_store_verification_key(vk_shares);
+    let rp = rp.aggregate_and_store_verification_key(vk_shares).unwrap();
 
     // Client setup
-    let mut client = client::Client::new(public_params.clone(), String::from("my-secret"));
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
 
     // Create signature request
     let visible_messages = vec!["food".to_string(); 4];
     let hidden_messages = vec!["topsecret".to_string(); 2];
-    let messages = JSMessages::new(visible_messages.clone(), hidden_messages.clone());
 
-    let id_request = client.request_id(visible_messages, hidden_messages);
-    let sig_req = id_request.sig_req;
-    let sig_req_proof = id_request.sig_req_proof;
+    let id_request = client.request_id(visible_messages, hidden_messages).unwrap();
 
     
     let mut blinded_sigs: Vec<ServerBlindSignature> = vec![];
     for idp in idps {
-        blinded_sigs.push(idp.verify_and_blind_sign(&sig_req, &sig_req_proof, &client.elg_keys.pk));
+        blinded_sigs.push(idp.verify_and_blind_sign(&id_request).unwrap());
     }
 
-    client.verify_signatures(blinded_sigs);
+    client.verify_signatures(blinded_sigs).unwrap();
+    let messages = client.messages().unwrap().clone();
     let pok = client.prove_id(messages, HashSet::new(), &domain).unwrap();
     
     // Verify client id
     let verification = rp.verify_id(pok).unwrap();
     assert_eq!(verification, true)
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_credential_with_issuer_attributes() {
+    let threshold = 3;
+    let total_server_count = 5;
+    let spec = IssuerAttributeSpec::new(86400, 365 * 86400, "substantial");
+    // 2 hidden, 2 visible and the issuer attributes
+    let msg_count = 4 + IssuerAttributes::COUNT;
+    let public_params = js::Public::new(msg_count, "hello".as_bytes(), threshold, total_server_count)
+        .with_issuer_attributes(spec.clone());
+    let idps = deploy(&public_params);
+
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+    let id_request = client.request_id(vec!["food".to_string(); 2], vec!["topsecret".to_string(); 2]).unwrap();
+
+    // A request for an epoch far from the IdP's clock is refused
+    let mut tampered = id_request.clone();
+    tampered.issuance_epoch = Some(0);
+    assert_eq!(
+        idps[0].verify_and_blind_sign(&tampered).unwrap_err(),
+        IssuanceError::IssuanceEpochRejected { epoch: Some(0) }
+    );
+
+    // A request with other issuer attributes than the IdP derives is refused, even with a valid
+    // proof
+    let mut tampered = id_request.clone();
+    tampered.issuance_epoch = tampered.issuance_epoch.map(|epoch| epoch + 1);
+    assert_eq!(
+        idps[0].verify_and_blind_sign(&tampered).unwrap_err(),
+        IssuanceError::IssuerAttributesMismatch
+    );
+
+    // The issuer attribute spec is part of the params
+    let mut other_spec = spec;
+    other_spec.assurance_level = String::from("high");
+    let mut other_client = client::Client::new(public_params.clone().with_issuer_attributes(other_spec), String::from("cheese"));
+    let other_request = other_client.request_id(vec!["food".to_string(); 2], vec!["topsecret".to_string(); 2]).unwrap();
+    assert_eq!(
+        idps[0].verify_and_blind_sign(&other_request).unwrap_err(),
+        IssuanceError::ParamsMismatch { fingerprint: other_request.params_fingerprint.clone() }
+    );
+
+    let blinded_sigs = blind_sign_all(&idps, &id_request);
+    let expected = blinded_sigs[0].issuer_attributes.clone().unwrap();
+    for sig in &blinded_sigs {
+        assert_eq!(sig.issuer_attributes.as_ref(), Some(&expected));
+    }
+
+    client.verify_signatures(blinded_sigs).unwrap();
+    assert_eq!(client.issuer_attributes(), Some(&expected));
+    assert_eq!(client.messages().unwrap().visible_count(), 2 + IssuerAttributes::COUNT);
+}
+#[test]
+fn test_attested_credential_from_threshold_of_idps() {
+    let threshold = 2;
+    let total_server_count = 3;
+    let public_params = js::Public::new(4, "hello".as_bytes(), threshold, total_server_count);
+
+    // The IdPs share one verifier, a code can be used by a threshold of them
+    let otp = Arc::new(OtpVerifier::with_uses(threshold));
+    let attestor = Attestor::new(AttestationPolicy::new().require(0, "otp")).register("otp", otp.clone());
+    let idps = deploy(&public_params)
+        .into_iter()
+        .map(|idp| idp.with_attestor(attestor.clone()))
+        .collect::<Vec<d_idp::DistributedIdP>>();
+
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+    let visible = vec!["alice@example.com".to_string(), "food".to_string()];
+    let code = otp.issue("alice@example.com");
+    let with_code = |code: &str| client::RequestOptions {
+        evidence: vec![(String::from("otp"), code.to_string())].into_iter().collect(),
+        ..client::RequestOptions::default()
+    };
+
+    let wrong = if code == "000000" { "000001" } else { "000000" };
+    let bad_request = client
+        .request_id_with_options(visible.clone(), vec!["topsecret".to_string(); 2], with_code(wrong))
+        .unwrap();
+    assert_eq!(
+        idps[0].verify_and_blind_sign(&bad_request).unwrap_err(),
+        IssuanceError::AttestationFailed {
+            index: 0,
+            verifier: String::from("otp"),
+            reason: String::from("Wrong one time password"),
+        }
+    );
+
+    // The code is used up by the IdPs that sign, not by the first one to check it
+    let id_request = client
+        .request_id_with_options(visible, vec!["topsecret".to_string(); 2], with_code(&code))
+        .unwrap();
+    let blinded_sigs = blind_sign_all(&idps[..threshold], &id_request);
+    assert!(idps[threshold].verify_and_blind_sign(&id_request).is_err());
+    // A retry gets the response that was already issued
+    assert!(idps[0].verify_and_blind_sign(&id_request).is_ok());
+    client.verify_signatures(blinded_sigs).unwrap();
+}
+
+#[test]
+fn test_one_credential_per_identity() {
+    let threshold = 2;
+    let total_server_count = 3;
+    let public_params = js::Public::new(4, "hello".as_bytes(), threshold, total_server_count)
+        .with_identity_tags("hello-idp");
+
+    // The IdPs share a tag registry
+    let tags: Arc<dyn TagRegistry> = Arc::new(MemoryTagRegistry::new());
+    let idps = deploy(&public_params)
+        .into_iter()
+        .map(|idp| idp.with_tag_registry(tags.clone()))
+        .collect::<Vec<d_idp::DistributedIdP>>();
+
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+    let hidden = vec!["link-secret".to_string(), "topsecret".to_string()];
+    let id_request = client.request_id(vec!["food".to_string(); 2], hidden.clone()).unwrap();
+
+    // A tag not computed from the link secret is refused
+    let mut tampered = id_request.clone();
+    tampered.tag = Some(&tampered.tag.unwrap() + &tampered.elg_pk);
+    assert_eq!(
+        idps[0].verify_and_blind_sign(&tampered).unwrap_err(),
+        IssuanceError::InvalidRequestProof
+    );
+
+    // Every IdP signs the first session
+    client.verify_signatures(blind_sign_all(&idps, &id_request)).unwrap();
+
+    // A second credential with the same link secret is refused, also by IdPs that did not sign yet
+    let second_request = client.request_id(vec!["drink".to_string(); 2], hidden).unwrap();
+    assert_eq!(second_request.tag, id_request.tag);
+    assert_eq!(
+        idps[2].verify_and_blind_sign(&second_request).unwrap_err(),
+        IssuanceError::DuplicateIdentityTag {
+            tag: id_request.tag_hex().unwrap(),
+        }
+    );
+
+    let mut other_client = client::Client::new(public_params.clone(), String::from("cheese"));
+    let other_request = other_client.request_id(
+        vec!["food".to_string(); 2],
+        vec!["other-link-secret".to_string(), "topsecret".to_string()],
+    ).unwrap();
+    assert!(idps[0].verify_and_blind_sign(&other_request).is_ok());
+}
+
+#[test]
+fn test_credential_from_encrypted_shares() {
+    let threshold = 2;
+    let total_server_count = 3;
+    let public_params = js::Public::new(4, "hello".as_bytes(), threshold, total_server_count);
+    let ttp = ttp::TrustedThirdParty::new(threshold, total_server_count, &public_params).unwrap();
+
+    // Each IdP sends its identity public key to the dealer and pins the dealer's key
+    let mut idps: Vec<d_idp::DistributedIdP> = vec![];
+    for id in 0..total_server_count {
+        let identity = KeyPair::new();
+        let share = ttp.encrypt_server_i(id, &identity.pk).unwrap();
+
+        let other = KeyPair::new();
+        assert_eq!(
+            d_idp::DistributedIdP::from_encrypted_share(&share, &other, ttp.dealer_public_key(), &public_params).unwrap_err(),
+            ThesisError::ShareError { error: ShareError::WrongRecipient }
+        );
+        idps.push(
+            d_idp::DistributedIdP::from_encrypted_share(&share, &identity, ttp.dealer_public_key(), &public_params).unwrap()
+        );
+    }
+
+    let client = issue(&public_params, &idps.iter().collect::<Vec<_>>());
+    assert!(client.offer_ps_verkey().is_some());
+}
+
+#[test]
+fn test_rp_pins_published_verkey() {
+    let threshold = 2;
+    let total_server_count = 3;
+    let domain = String::from("hello.com");
+    let public_params = js::Public::new(4, "hello".as_bytes(), threshold, total_server_count);
+    let ttp = ttp::TrustedThirdParty::new(threshold, total_server_count, &public_params).unwrap();
+
+    let mut rp = rp::RelyingParty::new(domain.clone(), public_params.clone());
+    let mut idps: Vec<d_idp::DistributedIdP> = vec![];
+    for id in 0..total_server_count {
+        let identity = KeyPair::new();
+        let share = ttp.encrypt_server_i(id, &identity.pk).unwrap();
+        let idp = d_idp::DistributedIdP::from_encrypted_share(&share, &identity, ttp.dealer_public_key(), &public_params).unwrap();
+        rp.trust_idp(idp.id(), identity.pk.clone());
+        idps.push(idp);
+    }
+
+    // Shares signed by an IdP the RP does not trust are refused
+    let untrusted = d_idp::DistributedIdP::from_serialized_signer(&ttp.serialize_server_i(0).unwrap(), &public_params)
+        .unwrap()
+        .with_identity_keys(KeyPair::new());
+    assert_eq!(rp.pin_verification_key(vec![untrusted.publish_vk_share().unwrap()]).unwrap_err().code(), "VerkeyRejected");
+
+    let shares = idps.iter().map(|idp| idp.publish_vk_share().unwrap()).collect();
+    let key_id = rp.pin_verification_key(shares).unwrap();
+    assert_eq!(rp.pinned_key_ids(), vec![key_id]);
+
+    let client = issue(&public_params, &idps.iter().collect::<Vec<_>>());
+
+    // The key the client offers is the pinned one, a key of another deployment is refused
+    rp.set_verification_key(client.offer_ps_verkey().unwrap()).unwrap();
+    let other_ttp = ttp::TrustedThirdParty::new(threshold, total_server_count, &public_params).unwrap();
+    let other_vk = Verkey::owned_aggregate(
+        threshold,
+        (0..threshold)
+            .map(|id| d_idp::DistributedIdP::from_serialized_signer(&other_ttp.serialize_server_i(id).unwrap(), &public_params).unwrap().get_id_vk())
+            .collect(),
+    );
+    assert_eq!(rp.set_verification_key(transform_to_PS_verkey(&other_vk)).unwrap_err().code(), "VerkeyRejected");
+
+    let messages = client.messages().unwrap().clone();
+    assert!(rp.verify_id(client.prove_id(messages, HashSet::new(), &domain).unwrap()).unwrap());
+}
+
+#[test]
+fn test_client_ignores_inconsistent_verkey_share() {
+    let threshold = 2;
+    let total_server_count = 4;
+    let domain = String::from("hello.com");
+    let public_params = js::Public::new(4, "hello".as_bytes(), threshold, total_server_count);
+    let ttp = ttp::TrustedThirdParty::new(threshold, total_server_count, &public_params).unwrap();
+    let other_ttp = ttp::TrustedThirdParty::new(threshold, total_server_count, &public_params).unwrap();
+
+    // The IdP with the first signer id signs with a key of another deployment
+    let idps = (0..total_server_count)
+        .map(|id| {
+            let signer = if id == 0 { other_ttp.serialize_server_i(id) } else { ttp.serialize_server_i(id) };
+            d_idp::DistributedIdP::from_serialized_signer(&signer.unwrap(), &public_params).unwrap()
+        })
+        .collect::<Vec<d_idp::DistributedIdP>>();
+    let poisoned_id = idps[0].id();
+
+    let vk_shares = idps.iter().map(|idp| idp.get_id_vk()).collect::<Vec<(usize, Verkey)>>();
+    let keys = vk_shares.iter().map(|(id, vk)| (*id, vk)).collect::<Vec<(usize, &Verkey)>>();
+    assert_eq!(Verkey::inconsistent_shares(threshold, &keys), vec![poisoned_id]);
+    assert!(rp::RelyingParty::new(domain.clone(), public_params.clone())
+        .aggregate_and_store_verification_key(vk_shares.clone())
+        .is_err());
+    let rp = rp::RelyingParty::new(domain.clone(), public_params.clone())
+        .aggregate_and_store_verification_key(vk_shares[1..].to_vec())
+        .unwrap();
+
+    let client = issue(&public_params, &idps.iter().collect::<Vec<_>>());
+
+    let messages = client.messages().unwrap().clone();
+    assert!(rp.verify_id(client.prove_id(messages, HashSet::new(), &domain).unwrap()).unwrap());
+}
+
+#[test]
+fn test_invalid_inputs_are_rejected() {
+    let public_params = js::Public::new(4, "hello".as_bytes(), 2, 3);
+    public_params.validate().unwrap();
+
+    let mut bad_params = public_params.clone();
+    bad_params.threshold = 4;
+    assert!(bad_params.validate().is_err());
+    let mut bad_params = public_params.clone();
+    bad_params.msg_count = 5;
+    assert!(bad_params.validate().is_err());
+    let mut bad_params = public_params.clone();
+    bad_params.cparams.g = G2::identity();
+    assert!(bad_params.validate().is_err());
+    let bad_params = public_params.clone().with_issuer_attributes(IssuerAttributeSpec::new(0, 86400, "high"));
+    assert!(bad_params.validate().is_err());
+
+    let ttp = ttp::TrustedThirdParty::new(2, 3, &public_params).unwrap();
+    let idp = d_idp::DistributedIdP::from_serialized_signer(&ttp.serialize_server_i(0).unwrap(), &public_params).unwrap();
+    assert!(d_idp::DistributedIdP::from_serialized_signer(&ttp.serialize_server_i(0).unwrap(), &bad_params).is_err());
+
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+    let id_request = client.request_id(vec!["food".to_string(); 2], vec!["topsecret".to_string(); 2]).unwrap();
+    for tamper in 0..2 {
+        let mut bad_request = id_request.clone();
+        if tamper == 0 {
+            bad_request.elg_pk = G2::identity();
+        } else {
+            bad_request.sig_req.ciphertexts[0].1 = G2::identity();
+        }
+        match idp.verify_and_blind_sign(&bad_request).unwrap_err() {
+            IssuanceError::MalformedRequest { .. } => (),
+            err => panic!("Unexpected error {:?}", err),
+        }
+    }
+
+    // A blind signature share with an identity element is ignored by the client
+    let mut bad_sig = idp.verify_and_blind_sign(&id_request).unwrap();
+    bad_sig.blind_sig.h = G2::identity();
+    assert!(bad_sig.validate().is_err());
+    assert_eq!(
+        client.verify_signatures(vec![bad_sig]).unwrap_err(),
+        ThesisError::NotEnoughSignatures { threshold: 2, valid: 0 }
+    );
+}
+
+#[test]
+fn test_parties_agree_on_params_fingerprint() {
+    let public_params = js::Public::new(4, "hello".as_bytes(), 2, 3);
+    public_params.verify_derivation().unwrap();
+    assert_eq!(public_params.fingerprint(), js::Public::new(4, "hello".as_bytes(), 2, 3).fingerprint());
+
+    // Anyone can tell that a generator was not hashed to the curve
+    let mut trapdoor = public_params.clone();
+    trapdoor.h = &public_params.g + &public_params.g;
+    assert!(trapdoor.verify_derivation().is_err());
+    assert!(trapdoor.validate().is_err());
+
+    let idp = deploy(&public_params).remove(0);
+
+    // A request made under other params is refused
+    let other_params = js::Public::new(4, "other".as_bytes(), 2, 3);
+    assert_ne!(other_params.fingerprint(), public_params.fingerprint());
+    // Every option that changes what the IdPs accept changes the fingerprint
+    for variant in [
+        public_params.clone().with_identity_tags("scope"),
+        public_params.clone().with_variable_attribute_count(),
+        public_params.clone().with_issuer_attributes(IssuerAttributeSpec::new(86400, 86400, "high")),
+    ] {
+        assert_ne!(variant.fingerprint(), public_params.fingerprint());
+    }
+    let mut client = client::Client::new(other_params, String::from("cheese"));
+    let id_request = client.request_id(vec!["food".to_string(); 2], vec!["topsecret".to_string(); 2]).unwrap();
+    assert_eq!(
+        idp.verify_and_blind_sign(&id_request).unwrap_err(),
+        IssuanceError::ParamsMismatch { fingerprint: id_request.params_fingerprint.clone() }
+    );
+
+    // Relabelling the request does not help, the fingerprint is bound to the proof
+    let mut relabelled = id_request.clone();
+    relabelled.params_fingerprint = public_params.fingerprint();
+    assert!(idp.verify_and_blind_sign(&relabelled).is_err());
+}
+
+#[test]
+fn test_credential_with_hidden_messages_in_any_slot() {
+    let public_params = js::Public::new(5, "hello".as_bytes(), 2, 3);
+    let idps = deploy(&public_params);
+    let domain = String::from("hello.com");
+    let rp = rp::RelyingParty::new(domain.clone(), public_params.clone())
+        .aggregate_and_store_verification_key(idps.iter().map(|idp| idp.get_id_vk()).collect())
+        .unwrap();
+
+    // The schema has a visible attribute in slot 0
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+    let options = client::RequestOptions {
+        hidden_indices: Some(vec![1, 3]),
+        ..client::RequestOptions::default()
+    };
+    let visible = vec!["schema".to_string(), "food".to_string(), "drink".to_string()];
+    let id_request = client
+        .request_id_with_options(visible, vec!["topsecret".to_string(); 2], options)
+        .unwrap();
+    assert_eq!(id_request.sig_req.hidden_indices, vec![1, 3]);
+
+    client.verify_signatures(blind_sign_all(&idps, &id_request)).unwrap();
+
+    let messages = client.messages().unwrap().clone();
+    let pok = client.prove_id(messages, HashSet::new(), &domain).unwrap();
+    assert!(rp.verify_id(pok).unwrap());
+
+    let bad_options = client::RequestOptions {
+        hidden_indices: Some(vec![3, 1]),
+        ..client::RequestOptions::default()
+    };
+    assert!(client.request_id_with_options(vec!["food".to_string(); 3], vec!["topsecret".to_string(); 2], bad_options).is_err());
+}
+
+#[test]
+fn test_credentials_with_fewer_attributes_than_slots() {
+    let spec = IssuerAttributeSpec::new(86400, 365 * 86400, "substantial");
+    let msg_count = 6 + IssuerAttributes::COUNT;
+    let public_params = js::Public::new(msg_count, "hello".as_bytes(), 2, 3)
+        .with_issuer_attributes(spec)
+        .with_variable_attribute_count();
+    let idps = deploy(&public_params);
+    let domain = String::from("hello.com");
+    let rp = rp::RelyingParty::new(domain.clone(), public_params.clone())
+        .aggregate_and_store_verification_key(idps.iter().map(|idp| idp.get_id_vk()).collect())
+        .unwrap();
+
+    // The same keys serve credentials with 2 and with 5 attributes
+    for (visible, hidden) in [(1, 1), (3, 2)] {
+        let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+        let id_request = client.request_id(vec!["food".to_string(); visible], vec!["topsecret".to_string(); hidden]).unwrap();
+        client.verify_signatures(blind_sign_all(&idps, &id_request)).unwrap();
+
+        let messages = client.messages().unwrap().clone();
+        assert_eq!(messages.count(), msg_count);
+        let pok = client.prove_id(messages, HashSet::new(), &domain).unwrap();
+        assert!(rp.verify_id(pok).unwrap());
+    }
+
+    // Too many attributes leave no slot for the count
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+    assert!(client.request_id(vec!["food".to_string(); 4], vec!["topsecret".to_string(); 2]).is_err());
+}
+
+#[test]
+fn test_credential_types_are_bound_to_credentials() {
+    let registry = CredentialTypeRegistry::new()
+        .with_type(CredentialType::new(
+            "passport",
+            vec![AttributeSpec::visible("name"), AttributeSpec::hidden("number"), AttributeSpec::visible("country")],
+        ))
+        .with_type(CredentialType::new("membership", vec![AttributeSpec::hidden("member id")]));
+    let public_params = js::Public::new(5, "hello".as_bytes(), 2, 3)
+        .with_variable_attribute_count()
+        .with_credential_types(registry);
+    public_params.validate().unwrap();
+    let idps = deploy(&public_params);
+    let domain = String::from("hello.com");
+    let vk_pairs = idps.iter().map(|idp| idp.get_id_vk()).collect::<Vec<(usize, Verkey)>>();
+    let any_rp = rp::RelyingParty::new(domain.clone(), public_params.clone())
+        .aggregate_and_store_verification_key(vk_pairs.clone())
+        .unwrap();
+    let passport_rp = rp::RelyingParty::new(domain.clone(), public_params.clone())
+        .with_accepted_types(vec![String::from("passport")])
+        .aggregate_and_store_verification_key(vk_pairs)
+        .unwrap();
+
+    // Both types are issued with the same keys
+    let mut proofs = vec![];
+    for (credential_type, visible, hidden) in [("passport", 2, 1), ("membership", 0, 1)] {
+        let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+        let options = client::RequestOptions {
+            credential_type: credential_type.to_string(),
+            ..client::RequestOptions::default()
+        };
+        let id_request = client
+            .request_id_with_options(vec!["food".to_string(); visible], vec!["topsecret".to_string(); hidden], options)
+            .unwrap();
+
+        // A request relabelled as another type is refused
+        let mut relabelled = id_request.clone();
+        relabelled.credential_type = String::from(if credential_type == "passport" { "membership" } else { "passport" });
+        assert!(idps[0].verify_and_blind_sign(&relabelled).is_err());
+
+        client.verify_signatures(blind_sign_all(&idps, &id_request)).unwrap();
+
+        let messages = client.messages().unwrap().clone();
+        let pok = client.prove_id(messages, HashSet::new(), &domain).unwrap();
+        assert_eq!(pok.credential_type.as_deref(), Some(credential_type));
+        assert!(any_rp.verify_id(pok.clone()).unwrap());
+        proofs.push(pok);
+    }
+
+    assert!(passport_rp.verify_id(proofs[0].clone()).unwrap());
+    assert_eq!(passport_rp.verify_id(proofs[1].clone()).unwrap_err().code(), "CredentialTypeRejected");
+
+    // A membership credential does not pass as a passport
+    let mut forged = proofs[1].clone();
+    forged.credential_type = Some(String::from("passport"));
+    assert_ne!(passport_rp.verify_id(forged).ok(), Some(true));
+    let mut untyped = proofs[0].clone();
+    untyped.credential_type = None;
+    assert!(any_rp.verify_id(untyped).is_err());
+}
+
+/// IdPs of a fresh deployment with `public_params`, one for each server
+fn deploy(public_params: &js::Public) -> Vec<d_idp::DistributedIdP> {
+    let ttp = ttp::TrustedThirdParty::new(public_params.threshold, public_params.server_count, public_params).unwrap();
+    (0..public_params.server_count)
+        .map(|id| d_idp::DistributedIdP::from_serialized_signer(&ttp.serialize_server_i(id).unwrap(), public_params).unwrap())
+        .collect()
+}
+
+/// Blind signature share of each of `idps` on `id_request`
+fn blind_sign_all<'a, I>(idps: I, id_request: &client::ClientIdRequest) -> Vec<ServerBlindSignature>
+where
+    I: IntoIterator<Item = &'a d_idp::DistributedIdP>,
+{
+    idps.into_iter().map(|idp| idp.verify_and_blind_sign(id_request).unwrap()).collect()
+}
+
+fn issue(public_params: &js::Public, idps: &[&d_idp::DistributedIdP]) -> client::Client {
+    issue_attributes(public_params, idps, vec!["food".to_string(); 2], vec!["topsecret".to_string(); 2])
+}
+
+/// Client holding a credential on `visible` and `hidden` signed by each of `idps`
+fn issue_attributes(
+    public_params: &js::Public,
+    idps: &[&d_idp::DistributedIdP],
+    visible: Vec<String>,
+    hidden: Vec<String>,
+) -> client::Client {
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+    let id_request = client.request_id(visible, hidden).unwrap();
+    client.verify_signatures(blind_sign_all(idps.iter().copied(), &id_request)).unwrap();
+    client
+}
+
+#[test]
+fn test_key_rotation_with_overlapping_epochs() {
+    let threshold = 2;
+    let total_server_count = 3;
+    let domain = String::from("hello.com");
+    let public_params = js::Public::new(4, "hello".as_bytes(), threshold, total_server_count);
+    let old_ttp = ttp::TrustedThirdParty::new(threshold, total_server_count, &public_params).unwrap();
+    let new_ttp = ttp::TrustedThirdParty::new(threshold, total_server_count, &public_params).unwrap();
+    let stored_key = |ttp: &ttp::TrustedThirdParty, id: usize| {
+        StoredKey::in_epoch(ttp.deserialize_server_i(&ttp.serialize_server_i(id).unwrap()).unwrap(), ttp.key_epoch())
+    };
+
+    let mut rp = rp::RelyingParty::new(domain.clone(), public_params.clone());
+    let mut stores = vec![];
+    let mut idps = vec![];
+    for id in 0..total_server_count {
+        let identity = KeyPair::new();
+        let store = Arc::new(MemoryKeyStore::new());
+        store.import(stored_key(&old_ttp, id)).unwrap();
+        let idp = d_idp::DistributedIdP::from_key_store(store.clone(), &public_params)
+            .unwrap()
+            .with_identity_keys(identity.clone());
+        rp.trust_idp(idp.id(), identity.pk.clone());
+        stores.push(store);
+        idps.push(idp);
+    }
+    let old_shares = idps.iter().map(|idp| idp.publish_vk_share().unwrap()).collect::<Vec<_>>();
+    let old_key_id = rp.pin_verification_key(old_shares.clone()).unwrap();
+    assert_eq!(old_key_id, old_ttp.key_epoch().key_id);
+    let old_client = issue(&public_params, &idps.iter().collect::<Vec<_>>());
+    assert_eq!(old_client.key_id(), Some(old_key_id.clone()));
+
+    // The next generation is rolled out while credentials of the old one are still in use
+    for (id, (store, idp)) in stores.iter().zip(idps.iter_mut()).enumerate() {
+        store.import(stored_key(&new_ttp, id)).unwrap();
+        idp.reload_key().unwrap();
+    }
+    let new_key_id = rp.pin_verification_key(idps.iter().map(|idp| idp.publish_vk_share().unwrap()).collect()).unwrap();
+    assert_eq!(new_key_id, new_ttp.key_epoch().key_id);
+    assert_eq!(rp.pinned_key_ids().len(), 2);
+
+    // An IdP that has not switched yet signs with the old generation, which is not aggregated
+    let lagging = d_idp::DistributedIdP::from_serialized_signer(&old_ttp.serialize_server_i(2).unwrap(), &public_params).unwrap();
+    let new_client = issue(&public_params, &[&idps[0], &lagging, &idps[1]]);
+    assert_eq!(new_client.key_id(), Some(new_key_id.clone()));
+
+    // The RP picks the key by the id in the proof
+    let prove = |client: &client::Client| client.prove_id(client.messages().unwrap().clone(), HashSet::new(), &domain).unwrap();
+    assert!(rp.verify_id(prove(&old_client)).unwrap());
+    assert!(rp.verify_id(prove(&new_client)).unwrap());
+
+    // Once the old generation is retired only new credentials are accepted
+    rp.set_key_validity(&old_key_id, Validity::new(0, Some(1))).unwrap();
+    assert_eq!(rp.verify_id(prove(&old_client)).unwrap_err().code(), "VerkeyRejected");
+    assert!(rp.verify_id(prove(&new_client)).unwrap());
+
+    // Shares of different epochs are not aggregated
+    let mixed = vec![idps[0].publish_vk_share().unwrap(), old_shares[1].clone()];
+    assert_eq!(rp.pin_verification_key(mixed).unwrap_err().code(), "VerkeyRejected");
+}
+
+#[test]
+fn test_made_up_key_generation_is_not_aggregated() {
+    let public_params = js::Public::new(4, "hello".as_bytes(), 2, 3);
+    let idps = deploy(&public_params);
+    // A single responder holding all the shares of a generation of its own
+    let forger = deploy(&public_params);
+
+    let request_shares = |client: &mut client::Client| {
+        let id_request = client.request_id(vec!["food".to_string(); 2], vec!["topsecret".to_string(); 2]).unwrap();
+        idps[..2]
+            .iter()
+            .chain(forger.iter())
+            .map(|idp| idp.verify_and_blind_sign(&id_request).unwrap())
+            .collect::<Vec<ServerBlindSignature>>()
+    };
+
+    // Outnumbering the genuine shares does not get the forged key aggregated
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+    let shares = request_shares(&mut client);
+    assert_eq!(client.verify_signatures(shares).unwrap_err().code(), "VerkeyRejected");
+
+    let key_id = idps[0].key_id().to_string();
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese")).with_known_key_ids(vec![key_id.clone()]);
+    let shares = request_shares(&mut client);
+    client.verify_signatures(shares).unwrap();
+    assert_eq!(client.key_id(), Some(key_id));
+}
+
+#[test]
+fn test_renewal_keeps_hidden_attributes() {
+    let domain = String::from("hello.com");
+    let public_params = js::Public::new(4, "hello".as_bytes(), 2, 3).with_identity_tags("hello-idp");
+    let tags: Arc<dyn TagRegistry> = Arc::new(MemoryTagRegistry::new());
+    let mut idps = deploy(&public_params)
+        .into_iter()
+        .map(|idp| idp.with_tag_registry(tags.clone()))
+        .collect::<Vec<d_idp::DistributedIdP>>();
+
+    let mut client = client::Client::new(public_params.clone(), String::from("cheese"));
+    let hidden = vec!["link-secret".to_string(), "topsecret".to_string()];
+    let id_request = client.request_id(vec!["food".to_string(), "old-address".to_string()], hidden.clone()).unwrap();
+    client.verify_signatures(blind_sign_all(&idps, &id_request)).unwrap();
+    let vk = client.offer_ps_verkey().unwrap();
+
+    let renewal = client
+        .request_renewal(vec!["food".to_string(), "new-address".to_string()], client::RequestOptions::default())
+        .unwrap();
+    assert_eq!(renewal.current_visible, vec!["food".to_string(), "old-address".to_string()]);
+    assert_eq!(renewal.request.tag, id_request.tag);
+
+    // Only credentials of accepted keys are renewed
+    assert_eq!(
+        idps[0].verify_and_renew(&renewal).unwrap_err(),
+        IssuanceError::RenewalKeyRejected {
+            key_id: renewal.key_id().cloned(),
+        }
+    );
+    for idp in idps.iter_mut() {
+        idp.accept_renewal_key(vk.clone());
+    }
+
+    // The proof of possession is bound to the revealed attributes and the session
+    let mut tampered = renewal.clone();
+    tampered.current_visible[1] = "other-address".to_string();
+    assert_eq!(idps[0].verify_and_renew(&tampered).unwrap_err(), IssuanceError::InvalidPossessionProof);
+    let mut tampered = renewal.clone();
+    tampered.possession.proof_vc.responses[1] = tampered.possession.proof_vc.responses[2].clone();
+    assert_eq!(idps[0].verify_and_renew(&tampered).unwrap_err(), IssuanceError::InvalidPossessionProof);
+    let mut tampered = renewal.clone();
+    tampered.request.session_id = id_request.session_id.clone();
+    assert!(idps[0].verify_and_renew(&tampered).is_err());
+
+    // The layout of the current credential is that of the new one, not the client's
+    let mut tampered = renewal.clone();
+    tampered.current_hidden_indices = vec![0, 2];
+    assert_eq!(
+        idps[0].verify_and_renew(&tampered).unwrap_err(),
+        IssuanceError::MalformedRequest {
+            msg: String::from("Renewal does not keep the hidden attributes"),
+        }
+    );
+
+    // The tag of a renewal must have been registered at issuance
+    let unaware = idps[0].clone().with_tag_registry(Arc::new(MemoryTagRegistry::new()));
+    assert_eq!(
+        unaware.verify_and_renew(&renewal).unwrap_err(),
+        IssuanceError::UnregisteredIdentityTag {
+            tag: id_request.tag_hex().unwrap(),
+        }
+    );
+
+    // The tag is the one of the current credential but the renewal is not refused as a duplicate
+    let blinded_sigs = idps
+        .iter()
+        .map(|idp| idp.verify_and_renew(&renewal).unwrap())
+        .collect::<Vec<ServerBlindSignature>>();
+    client.verify_signatures(blinded_sigs).unwrap();
+    let messages = client.messages().unwrap().clone();
+    assert_eq!(messages.hidden, hidden);
+    assert_eq!(messages.visible, vec!["food".to_string(), "new-address".to_string()]);
+
+    let rp = rp::RelyingParty::new(domain.clone(), public_params.clone())
+        .aggregate_and_store_verification_key(idps.iter().map(|idp| idp.get_id_vk()).collect())
+        .unwrap();
+    assert!(rp.verify_id(client.prove_id(messages, HashSet::new(), &domain).unwrap()).unwrap());
+}
+
+#[test]
+fn test_issuance_gated_by_credential_of_other_issuer() {
+    let gov_params = js::Public::new(4, "government".as_bytes(), 2, 3);
+    let bank_params = js::Public::new(4, "bank".as_bytes(), 2, 3);
+    let bank_idps = deploy(&bank_params);
+
+    // The government ID has the ID number and birthdate hidden
+    let gov_client = issue_attributes(
+        &gov_params,
+        &deploy(&gov_params).iter().collect::<Vec<_>>(),
+        vec!["alice".to_string(), "NL".to_string()],
+        vec!["id-number".to_string(), "1990-01-01".to_string()],
+    );
+
+    // The bank requires the country to be revealed and the ID number to be carried over
+    let gate = gate::CredentialGate::new(&gov_params, gov_client.offer_ps_verkey().unwrap(), vec![(0, 0)]).with_revealed(3, "NL");
+    let bank_idps = bank_idps
+        .into_iter()
+        .map(|idp| idp.with_credential_gate(gate.clone()))
+        .collect::<Vec<d_idp::DistributedIdP>>();
+
+    let mut bank_client = client::Client::new(bank_params.clone(), String::from("cheese"));
+    let visible = vec!["alice".to_string(), "savings".to_string()];
+    let hidden = vec!["id-number".to_string(), "1990-01-01".to_string()];
+    let request_with = |client: &mut client::Client, revealed: Vec<usize>, carried: Vec<(usize, usize)>| {
+        let options = client::RequestOptions {
+            presentation: Some(gov_client.gate_credential(revealed.into_iter().collect(), carried).unwrap()),
+            ..client::RequestOptions::default()
+        };
+        client.request_id_with_options(visible.clone(), hidden.clone(), options).unwrap()
+    };
+
+    let plain = bank_client.request_id(visible.clone(), hidden.clone()).unwrap();
+    assert_eq!(bank_idps[0].verify_and_blind_sign(&plain).unwrap_err(), IssuanceError::PresentationRequired);
+    let hiding_country = request_with(&mut bank_client, vec![], vec![(0, 0)]);
+    assert_eq!(bank_idps[0].verify_and_blind_sign(&hiding_country).unwrap_err(), IssuanceError::InvalidPresentation);
+    // Carrying over another attribute does not show that the ID number is the same
+    let other_carried = request_with(&mut bank_client, vec![3], vec![(1, 1)]);
+    assert_eq!(bank_idps[0].verify_and_blind_sign(&other_carried).unwrap_err(), IssuanceError::InvalidPresentation);
+    // The client cannot carry over a value that is not in the presented credential
+    let options = client::RequestOptions {
+        presentation: Some(gov_client.gate_credential(vec![3].into_iter().collect(), vec![(0, 0)]).unwrap()),
+        ..client::RequestOptions::default()
+    };
+    assert!(bank_client
+        .request_id_with_options(visible.clone(), vec!["other-number".to_string(), "1990-01-01".to_string()], options)
+        .is_err());
+
+    let gated = request_with(&mut bank_client, vec![3], vec![(0, 0)]);
+    assert_eq!(gated.presentation.as_ref().unwrap().revealed.get(&3), Some(&"NL".to_string()));
+    let mut tampered = gated.clone();
+    tampered.presentation.as_mut().unwrap().revealed.insert(3, "BE".to_string());
+    assert_eq!(bank_idps[0].verify_and_blind_sign(&tampered).unwrap_err(), IssuanceError::InvalidPresentation);
+
+    bank_client
+        .verify_signatures(blind_sign_all(&bank_idps, &gated))
+        .unwrap();
+    let rp = rp::RelyingParty::new(String::from("bank.com"), bank_params.clone())
+        .aggregate_and_store_verification_key(bank_idps.iter().map(|idp| idp.get_id_vk()).collect())
+        .unwrap();
+    let messages = bank_client.messages().unwrap().clone();
+    assert!(rp.verify_id(bank_client.prove_id(messages, HashSet::new(), &String::from("bank.com")).unwrap()).unwrap());
+}
+
+#[test]
+fn test_k_times_anonymous_authentication() {
+    let domain = String::from("api.hello.com");
+    let public_params = js::Public::new(4, "hello".as_bytes(), 2, 3);
+    let idps = deploy(&public_params);
+    let limit = rate_limit::RateLimit::new(2, 24 * 3600);
+    let rp = rp::RelyingParty::new(domain.clone(), public_params.clone())
+        .aggregate_and_store_verification_key(idps.iter().map(|idp| idp.get_id_vk()).collect())
+        .unwrap()
+        .with_rate_limit(limit);
+
+    let client = issue(&public_params, &idps.iter().collect::<Vec<_>>());
+    let prove = |client: &client::Client, counter: usize| {
+        client.prove_id_limited(client.messages().unwrap().clone(), HashSet::new(), &domain, &limit, counter)
+    };
+
+    // Proofs without a tag are refused
+    let untagged = client.prove_id(client.messages().unwrap().clone(), HashSet::new(), &domain).unwrap();
+    assert_eq!(rp.verify_id(untagged).unwrap_err().code(), "RateLimitTagRejected");
+
+    let first = prove(&client, 0).unwrap();
+    let second = prove(&client, 1).unwrap();
+    assert_ne!(first.rate_limit_tag.as_ref().unwrap().tag, second.rate_limit_tag.as_ref().unwrap().tag);
+    assert!(rp.verify_id(first).unwrap());
+    assert!(rp.verify_id(second).unwrap());
+
+    // A third presentation in the epoch has to reuse a counter, which repeats the tag
+    assert!(prove(&client, 2).is_err());
+    assert_eq!(rp.verify_id(prove(&client, 0).unwrap()).unwrap_err().code(), "RateLimitExceeded");
+
+    // Another holder has tags of its own
+    let other = issue_attributes(
+        &public_params,
+        &idps.iter().collect::<Vec<_>>(),
+        vec!["food".to_string(); 2],
+        vec!["other-link-secret".to_string(), "topsecret".to_string()],
+    );
+    let mut stolen = prove(&other, 0).unwrap();
+    assert!(rp.verify_id(stolen.clone()).unwrap());
+
+    // A tag must be computed from the link secret in the credential
+    stolen.rate_limit_tag = prove(&client, 1).unwrap().rate_limit_tag;
+    assert_ne!(rp.verify_id(stolen).ok(), Some(true));
+}
+
+#[test]
+fn test_reused_one_show_token_reveals_identity() {
+    let public_params = js::Public::new(4, "hello".as_bytes(), 2, 3);
+    let idps = deploy(&public_params);
+
+    // The ticket hides the holder's identity and a fresh serial seed
+    let client = issue_attributes(
+        &public_params,
+        &idps.iter().collect::<Vec<_>>(),
+        vec!["concert".to_string(), "row 5".to_string()],
+        vec!["alice@example.com".to_string(), one_show::serial_seed()],
+    );
+
+    // The gates of the venue share the spent tokens
+    let spec = one_show::OneShowSpec::new("concert-2026");
+    let tokens = Arc::new(one_show::SpentTokens::new());
+    let gate_rp = |domain: &str| {
+        rp::RelyingParty::new(domain.to_string(), public_params.clone())
+            .aggregate_and_store_verification_key(idps.iter().map(|idp| idp.get_id_vk()).collect())
+            .unwrap()
+            .with_one_show(spec.clone(), tokens.clone())
+            .unwrap()
+    };
+    let (north, south) = (gate_rp("north.venue.com"), gate_rp("south.venue.com"));
+    let show = |domain: &str| {
+        client.prove_one_show(client.messages().unwrap().clone(), HashSet::new(), &domain.to_string(), &spec).unwrap()
+    };
+
+    let plain = client.prove_id(client.messages().unwrap().clone(), HashSet::new(), &String::from("north.venue.com")).unwrap();
+    assert_eq!(north.verify_id(plain).unwrap_err().code(), "OneShowTagRejected");
+
+    let first = show("north.venue.com");
+    let second = show("south.venue.com");
+    assert_eq!(first.one_show_tag.as_ref().unwrap().serial, second.one_show_tag.as_ref().unwrap().serial);
+    assert_ne!(first.one_show_tag.as_ref().unwrap().equation, second.one_show_tag.as_ref().unwrap().equation);
+
+    // A forged double-spend equation does not verify
+    let mut forged = first.clone();
+    forged.one_show_tag.as_mut().unwrap().equation = FieldElement::random();
+    assert_ne!(north.verify_id(forged).ok(), Some(true));
+
+    assert!(north.verify_id(first.clone()).unwrap());
+    match south.verify_id(second).unwrap_err() {
+        ThesisError::TokenReused { identity, .. } => {
+            assert_eq!(identity, Some(one_show::identity_value("alice@example.com").to_hex()))
+        }
+        err => panic!("Unexpected error {:?}", err),
+    }
+
+    // Replaying the same presentation is refused but does not reveal anything
+    match north.verify_id(first).unwrap_err() {
+        ThesisError::TokenReused { identity, .. } => assert_eq!(identity, None),
+        err => panic!("Unexpected error {:?}", err),
+    }
+}
+
+#[test]
+fn test_refused_presentation_does_not_use_up_tags() {
+    let domain = String::from("tickets.hello.com");
+    let public_params = js::Public::new(4, "hello".as_bytes(), 2, 3);
+    let idps = deploy(&public_params);
+    let limit = rate_limit::RateLimit::new(2, 24 * 3600);
+    let spec = one_show::OneShowSpec::new("concert-2026");
+    let rp = rp::RelyingParty::new(domain.clone(), public_params.clone())
+        .aggregate_and_store_verification_key(idps.iter().map(|idp| idp.get_id_vk()).collect())
+        .unwrap()
+        .with_rate_limit(limit)
+        .with_one_show(spec.clone(), Arc::new(one_show::SpentTokens::new()))
+        .unwrap();
+
+    // Two tickets of the same holder have the same rate limit tags
+    let ticket = || {
+        issue_attributes(
+            &public_params,
+            &idps.iter().collect::<Vec<_>>(),
+            vec!["concert".to_string(), "row 5".to_string()],
+            vec!["alice@example.com".to_string(), one_show::serial_seed()],
+        )
+    };
+    let (first, second) = (ticket(), ticket());
+    let show = |client: &client::Client, counter: usize| {
+        client
+            .prove_one_show_limited(client.messages().unwrap().clone(), HashSet::new(), &domain, &spec, &limit, counter)
+            .unwrap()
+    };
+
+    assert!(rp.verify_id(show(&first, 0)).unwrap());
+    assert_eq!(rp.verify_id(show(&first, 1)).unwrap_err().code(), "TokenReused");
+    // The refused presentation did not record its rate limit tag
+    assert!(rp.verify_id(show(&second, 1)).unwrap());
+    assert_eq!(rp.verify_id(show(&second, 0)).unwrap_err().code(), "RateLimitExceeded");
+}